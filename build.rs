@@ -1,15 +1,17 @@
 use spirv_builder::{SpirvBuilder, SpirvMetadata, ModuleResult};
 
-fn main() {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     let result = SpirvBuilder::new("shader", "spirv-unknown-vulkan1.1")
         .spirv_metadata(SpirvMetadata::Full)
-        .build()
-        .unwrap();
-    
+        .build()?;
+
     // We can use the module path in our main code
     let path = match &result.module {
         ModuleResult::SingleModule(path) => path,
-        ModuleResult::MultiModule(_) => panic!("Expected single module"),
+        ModuleResult::MultiModule(modules) => {
+            return Err(format!("expected a single SPIR-V module, got {} entry-point modules", modules.len()).into());
+        }
     };
     println!("cargo:rustc-env=SHADER_PATH={}", path.display());
+    Ok(())
 }
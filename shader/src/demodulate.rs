@@ -0,0 +1,80 @@
+//! RF-to-IQ demodulation of the channel data: each trace is mixed down by
+//! `config.demodulation_frequency`, low-pass filtered and decimated, so the
+//! beamformer runs on complex baseband at a fraction of the RF sample rate.
+
+use spirv_std::glam::{UVec3, Vec2};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::spirv;
+
+use core::f32::consts::PI;
+
+use crate::{fetch, input_format, thread_index, BeamformingConfig};
+
+/// Samples per trace after decimation.
+pub fn decimated_samples(config: &BeamformingConfig) -> u32 {
+    config.num_samples.div_ceil(config.decimation)
+}
+
+/// Config of the data the beamformer sees: with demodulation enabled,
+/// interleaved IQ at the decimated rate, otherwise `config` itself.
+pub fn demodulated_config(config: &BeamformingConfig) -> BeamformingConfig {
+    if config.decimation == 0 {
+        return *config;
+    }
+    BeamformingConfig {
+        sampling_frequency: config.sampling_frequency / config.decimation as f32,
+        num_samples: decimated_samples(config),
+        input_format: input_format::IQ_INTERLEAVED,
+        num_filter_taps: 0,
+        decimation: 0,
+        num_demodulation_taps: 0,
+        ..*config
+    }
+}
+
+/// Threads of the demodulation stage: one per IQ sample.
+pub fn demodulate_threads(config: &BeamformingConfig) -> usize {
+    (config.num_transmits * config.num_channels * decimated_samples(config)) as usize
+}
+
+/// IQ sample `index` of the demodulated data, numbered
+/// `trace * decimated_samples + sample`, from the RF traces in `rf`.
+///
+/// The RF samples are multiplied by `2 exp(-j 2 pi f_demod t)` at their
+/// absolute time `t` (so IQ matches the convention of the beamformer) and
+/// filtered with `taps`, centred like [`filter`](crate::filter) taps.
+pub fn demodulate_sample(rf: &[f32], taps: &[f32], config: &BeamformingConfig, index: usize) -> Vec2 {
+    let samples = decimated_samples(config) as usize;
+    let trace = index / samples;
+    let last = ((index % samples) * config.decimation as usize + (config.num_demodulation_taps as usize - 1) / 2) as i32;
+
+    let mut sum = Vec2::ZERO;
+    let mut k = 0;
+    while k < config.num_demodulation_taps as usize {
+        let sample = last - k as i32;
+        // Whole carrier cycles are dropped before scaling to radians, to keep the phase accurate
+        let cycles = config.demodulation_frequency * (config.start_time + sample as f32 / config.sampling_frequency);
+        let phase = -2.0 * PI * (cycles - cycles.floor());
+        sum += taps[k] * fetch(rf, config, trace, 0, sample) * Vec2::new(phase.cos(), phase.sin());
+        k += 1;
+    }
+    2.0 * sum
+}
+
+#[spirv(compute(threads(64)))]
+pub fn demodulate_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] rf: &[f32],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] taps: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] iq: &mut [f32],
+) {
+    let index = thread_index(global_id, num_workgroups);
+    if index < demodulate_threads(config) {
+        let value = demodulate_sample(rf, taps, config, index);
+        iq[2 * index] = value.x;
+        iq[2 * index + 1] = value.y;
+    }
+}
@@ -0,0 +1,136 @@
+//! Colour-flow and power Doppler: axial velocity, variance and power of every
+//! pixel from an ensemble of beamformed IQ frames, with Kasai's lag-one
+//! autocorrelation estimator, after a polynomial regression wall filter.
+
+use spirv_std::glam::{UVec3, Vec2, Vec3};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::spirv;
+
+use core::f32::consts::PI;
+
+use crate::fft::complex_mul;
+use crate::{pixel_count, thread_index, BeamformingConfig};
+
+/// Threads of the Doppler stage: one per pixel.
+pub fn doppler_threads(config: &BeamformingConfig) -> usize {
+    pixel_count(config)
+}
+
+/// Highest supported `config.clutter_filter_order`.
+pub const MAX_CLUTTER_FILTER_ORDER: u32 = 2;
+
+/// Mean and slope per frame of the ensemble of pixel `pixel`, laid out
+/// `[frame][pixel]`, as far as the wall filter removes them: zero beyond
+/// `config.clutter_filter_order`.
+///
+/// The constant and the linear trend centred on the ensemble are orthogonal,
+/// so each is the projection of the samples on its own.
+pub fn clutter_trend(ensemble: &[Vec2], config: &BeamformingConfig, pixel: usize) -> (Vec2, Vec2) {
+    let pixels = pixel_count(config);
+    let frames = config.ensemble_length as usize;
+    let center = (frames - 1) as f32 / 2.0;
+    let mut sum = Vec2::ZERO;
+    let mut moment = Vec2::ZERO;
+    let mut norm = 0.0;
+    let mut frame = 0;
+    while frame < frames {
+        let sample = ensemble[frame * pixels + pixel];
+        let offset = frame as f32 - center;
+        sum += sample;
+        moment += offset * sample;
+        norm += offset * offset;
+        frame += 1;
+    }
+    let mean = if config.clutter_filter_order >= 1 { sum / frames as f32 } else { Vec2::ZERO };
+    let slope = if config.clutter_filter_order >= 2 && norm > 0.0 { moment / norm } else { Vec2::ZERO };
+    (mean, slope)
+}
+
+/// Sample `frame` of pixel `pixel` with the `(mean, slope)` trend of
+/// [`clutter_trend`] subtracted.
+pub fn clutter_filtered(
+    ensemble: &[Vec2],
+    config: &BeamformingConfig,
+    pixel: usize,
+    frame: usize,
+    trend: (Vec2, Vec2),
+) -> Vec2 {
+    let center = (config.ensemble_length - 1) as f32 / 2.0;
+    ensemble[frame * pixel_count(config) + pixel] - trend.0 - (frame as f32 - center) * trend.1
+}
+
+/// Mean power `R(0)` and lag-one autocorrelation `R(1)` of pixel `pixel`
+/// over the wall-filtered ensemble, laid out `[frame][pixel]`.
+pub fn autocorrelation(ensemble: &[Vec2], config: &BeamformingConfig, pixel: usize) -> (f32, Vec2) {
+    let frames = config.ensemble_length as usize;
+    let trend = clutter_trend(ensemble, config, pixel);
+    let mut r0 = 0.0;
+    let mut r1 = Vec2::ZERO;
+    let mut previous = clutter_filtered(ensemble, config, pixel, 0, trend);
+    r0 += previous.length_squared();
+    let mut frame = 1;
+    while frame < frames {
+        let current = clutter_filtered(ensemble, config, pixel, frame, trend);
+        r0 += current.length_squared();
+        r1 += complex_mul(current, Vec2::new(previous.x, -previous.y));
+        previous = current;
+        frame += 1;
+    }
+    (r0 / frames as f32, r1 / (frames - 1) as f32)
+}
+
+/// Axial velocity (m/s, positive towards the probe), normalized variance
+/// `1 - |R(1)| / R(0)` (0 for uniform flow, up to 1 for noise) and mean
+/// power of pixel `pixel`.
+///
+/// The velocity is `c PRF / (4 pi f0)` times the phase of `R(1)`, so it
+/// aliases beyond the Nyquist velocity `c PRF / (4 f0)`.
+pub fn kasai(ensemble: &[Vec2], config: &BeamformingConfig, pixel: usize) -> Vec3 {
+    let (r0, r1) = autocorrelation(ensemble, config, pixel);
+    let scale = config.speed_of_sound * config.pulse_repetition_frequency / (4.0 * PI * config.center_frequency);
+    let velocity = scale * r1.y.atan2(r1.x);
+    let variance = if r0 > 0.0 { 1.0 - r1.length() / r0 } else { 0.0 };
+    Vec3::new(velocity, variance, r0)
+}
+
+/// Root mean square amplitude `sqrt(R(0))` of the wall-filtered ensemble of
+/// pixel `pixel`, the envelope that power Doppler log-compresses.
+pub fn power_amplitude(ensemble: &[Vec2], config: &BeamformingConfig, pixel: usize) -> f32 {
+    autocorrelation(ensemble, config, pixel).0.sqrt()
+}
+
+#[spirv(compute(threads(64)))]
+pub fn doppler_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] ensemble: &[Vec2],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] maps: &mut [f32],
+) {
+    let pixel = thread_index(global_id, num_workgroups);
+    let pixels = pixel_count(config);
+    // Velocity, variance and power maps, one after another
+    if pixel < pixels {
+        let estimate = kasai(ensemble, config, pixel);
+        maps[pixel] = estimate.x;
+        maps[pixels + pixel] = estimate.y;
+        maps[2 * pixels + pixel] = estimate.z;
+    }
+}
+
+/// Writes the power Doppler amplitudes as envelopes, which `peak_shader` and
+/// `log_compress_shader` then turn into a log-scaled power map.
+#[spirv(compute(threads(64)))]
+pub fn power_doppler_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] ensemble: &[Vec2],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] envelopes: &mut [f32],
+) {
+    let pixel = thread_index(global_id, num_workgroups);
+    if pixel < pixel_count(config) {
+        envelopes[pixel] = power_amplitude(ensemble, config, pixel);
+    }
+}
@@ -0,0 +1,178 @@
+//! Batched 1D FFTs: mixed radix-4/2 Stockham passes for power-of-two
+//! lengths, and Bluestein's chirp-z algorithm on top of them for any other
+//! length. Transforms are stored one after another as `(re, im)` pairs, so a
+//! `[trace][sample]` buffer is transformed along its sample axis.
+//!
+//! Each function below computes the output of one thread; the kernels call
+//! them once per invocation and the CPU reference once per thread index.
+
+use spirv_std::glam::{UVec3, Vec2};
+use spirv_std::spirv;
+
+use crate::thread_index;
+pub use shared::FftParams;
+
+/// Complex product of two `(re, im)` pairs.
+pub fn complex_mul(a: Vec2, b: Vec2) -> Vec2 {
+    Vec2::new(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x)
+}
+
+fn conj_if(value: Vec2, inverse: bool) -> Vec2 {
+    if inverse {
+        Vec2::new(value.x, -value.y)
+    } else {
+        value
+    }
+}
+
+/// Threads of a radix pass: one per butterfly.
+pub fn radix_pass_threads(params: &FftParams) -> usize {
+    (params.batch * (params.len / params.radix)) as usize
+}
+
+/// Threads of the Bluestein pre-multiply and multiply stages: one per padded
+/// sample.
+pub fn padded_threads(params: &FftParams) -> usize {
+    (params.batch * params.len) as usize
+}
+
+/// Threads of the Bluestein post-multiply stage: one per output sample.
+pub fn signal_threads(params: &FftParams) -> usize {
+    (params.batch * params.signal_len) as usize
+}
+
+/// Input `r` of butterfly `j`, times its twiddle factor.
+fn butterfly_input(params: &FftParams, src: &[Vec2], twiddles: &[Vec2], base: usize, j: usize, r: usize) -> Vec2 {
+    let len = params.len as usize;
+    let stride = len / params.radix as usize;
+    let value = src[base + j + r * stride];
+    if r == 0 {
+        return value;
+    }
+    let k = j % params.span as usize;
+    let step = len / (params.span * params.radix) as usize;
+    complex_mul(value, conj_if(twiddles[k * r * step], params.inverse != 0))
+}
+
+/// One radix-2 or radix-4 butterfly of a Stockham pass from `src` to `dst`.
+/// `twiddles[k]` holds `exp(-2 pi i k / len)`; inverse passes use its
+/// conjugate.
+pub fn radix_pass(params: &FftParams, src: &[Vec2], dst: &mut [Vec2], twiddles: &[Vec2], thread: usize) {
+    let len = params.len as usize;
+    let span = params.span as usize;
+    let stride = len / params.radix as usize;
+    let base = thread / stride * len;
+    let j = thread % stride;
+    // Outputs of the butterfly land `span` apart, in the sub-transform of `j`
+    let out = base + j / span * span * params.radix as usize + j % span;
+    let scale = params.scale;
+
+    let v0 = butterfly_input(params, src, twiddles, base, j, 0);
+    let v1 = butterfly_input(params, src, twiddles, base, j, 1);
+    if params.radix == 2 {
+        dst[out] = (v0 + v1) * scale;
+        dst[out + span] = (v0 - v1) * scale;
+        return;
+    }
+
+    let v2 = butterfly_input(params, src, twiddles, base, j, 2);
+    let v3 = butterfly_input(params, src, twiddles, base, j, 3);
+    let (sum02, diff02) = (v0 + v2, v0 - v2);
+    let (sum13, diff13) = (v1 + v3, v1 - v3);
+    // diff13 times -i for a forward transform, +i for an inverse one
+    let rotated = if params.inverse != 0 { Vec2::new(-diff13.y, diff13.x) } else { Vec2::new(diff13.y, -diff13.x) };
+    dst[out] = (sum02 + sum13) * scale;
+    dst[out + span] = (diff02 + rotated) * scale;
+    dst[out + 2 * span] = (sum02 - sum13) * scale;
+    dst[out + 3 * span] = (diff02 - rotated) * scale;
+}
+
+/// Bluestein stage 1: multiplies the signal by the chirp `chirp[j] =
+/// exp(-pi i j^2 / signal_len)` and zero-pads it to `len`.
+pub fn bluestein_premultiply(params: &FftParams, src: &[Vec2], dst: &mut [Vec2], chirp: &[Vec2], thread: usize) {
+    let len = params.len as usize;
+    let signal_len = params.signal_len as usize;
+    let (batch, j) = (thread / len, thread % len);
+    dst[thread] = if j < signal_len {
+        complex_mul(src[batch * signal_len + j], conj_if(chirp[j], params.inverse != 0))
+    } else {
+        Vec2::ZERO
+    };
+}
+
+/// Bluestein stage 2: multiplies the transformed signal by the transformed
+/// conjugate chirp, `spectrum[..len]` for forward transforms and
+/// `spectrum[len..]` for inverse ones. In place.
+pub fn bluestein_multiply(params: &FftParams, data: &mut [Vec2], spectrum: &[Vec2], thread: usize) {
+    let len = params.len as usize;
+    let offset = if params.inverse != 0 { len } else { 0 };
+    data[thread] = complex_mul(data[thread], spectrum[offset + thread % len]);
+}
+
+/// Bluestein stage 3: multiplies the convolution by the chirp again and
+/// drops the padding.
+pub fn bluestein_postmultiply(params: &FftParams, src: &[Vec2], dst: &mut [Vec2], chirp: &[Vec2], thread: usize) {
+    let signal_len = params.signal_len as usize;
+    let (batch, k) = (thread / signal_len, thread % signal_len);
+    let value = src[batch * params.len as usize + k];
+    dst[thread] = complex_mul(value, conj_if(chirp[k], params.inverse != 0)) * params.scale;
+}
+
+#[spirv(compute(threads(64)))]
+pub fn fft_radix_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] params: &FftParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] src: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] dst: &mut [Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] twiddles: &[Vec2],
+) {
+    let thread = thread_index(global_id, num_workgroups);
+    if thread < radix_pass_threads(params) {
+        radix_pass(params, src, dst, twiddles, thread);
+    }
+}
+
+#[spirv(compute(threads(64)))]
+pub fn fft_premultiply_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] params: &FftParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] src: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] dst: &mut [Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] chirp: &[Vec2],
+) {
+    let thread = thread_index(global_id, num_workgroups);
+    if thread < padded_threads(params) {
+        bluestein_premultiply(params, src, dst, chirp, thread);
+    }
+}
+
+#[spirv(compute(threads(64)))]
+pub fn fft_multiply_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] params: &FftParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] data: &mut [Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] spectrum: &[Vec2],
+) {
+    let thread = thread_index(global_id, num_workgroups);
+    if thread < padded_threads(params) {
+        bluestein_multiply(params, data, spectrum, thread);
+    }
+}
+
+#[spirv(compute(threads(64)))]
+pub fn fft_postmultiply_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 0)] params: &FftParams,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] src: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] dst: &mut [Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] chirp: &[Vec2],
+) {
+    let thread = thread_index(global_id, num_workgroups);
+    if thread < signal_threads(params) {
+        bluestein_postmultiply(params, src, dst, chirp, thread);
+    }
+}
@@ -0,0 +1,63 @@
+//! FIR filtering of the channel data along the sample axis, before
+//! beamforming: a bandpass that drops DC offsets and out-of-band noise, or a
+//! matched filter that compresses a coded excitation such as a chirp.
+
+use spirv_std::glam::UVec3;
+use spirv_std::spirv;
+
+use crate::{fetch, input_format, thread_index, BeamformingConfig};
+
+/// Threads of the filter stage: one per value of the channel data.
+pub fn filter_threads(config: &BeamformingConfig) -> usize {
+    let components = if config.input_format == input_format::RF { 1 } else { 2 };
+    (config.num_transmits * config.num_channels * config.num_samples) as usize * components
+}
+
+/// Filtered value of `input[index]`, where `input` is laid out as
+/// `config.input_format` describes. IQ components are filtered separately
+/// with the same real taps, and samples beyond either end of a trace count
+/// as 0.
+///
+/// The taps are centred on tap `(n - 1) / 2`, rounded down, so a symmetric
+/// filter of odd length does not delay the echoes and a matched filter (the
+/// time-reversed pulse) peaks at the centre of each echo.
+pub fn filter_sample(input: &[f32], taps: &[f32], config: &BeamformingConfig, index: usize) -> f32 {
+    let num_taps = config.num_filter_taps as usize;
+    if num_taps == 0 {
+        return input[index];
+    }
+    let num_samples = config.num_samples as usize;
+    let (sample, component) = match config.input_format {
+        input_format::IQ_INTERLEAVED => (index / 2, index % 2),
+        input_format::IQ_PLANAR => {
+            let plane = (config.num_transmits * config.num_channels) as usize * num_samples;
+            (index % plane, index / plane)
+        }
+        _ => (index, 0),
+    };
+    let trace = sample / num_samples;
+    let last = (sample % num_samples + (num_taps - 1) / 2) as i32;
+
+    let mut sum = 0.0;
+    let mut k = 0;
+    while k < num_taps {
+        sum += taps[k] * fetch(input, config, trace, component, last - k as i32);
+        k += 1;
+    }
+    sum
+}
+
+#[spirv(compute(threads(64)))]
+pub fn filter_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[f32],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] taps: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] filtered: &mut [f32],
+) {
+    let index = thread_index(global_id, num_workgroups);
+    if index < filter_threads(config) {
+        filtered[index] = filter_sample(input, taps, config, index);
+    }
+}
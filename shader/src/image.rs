@@ -0,0 +1,155 @@
+//! Image formation after beamforming: envelope detection and log compression
+//! into a normalized B-mode image.
+
+use spirv_std::glam::UVec3;
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::spirv;
+
+use core::f32::consts::{LOG10_2, PI};
+
+use crate::{input_format, pixel_count, thread_index, BeamformingConfig, WORKGROUP_SIZE};
+
+/// Taps on each side of the centre of the FIR Hilbert transformer.
+pub const HILBERT_HALF_WIDTH: i32 = 16;
+
+/// Tap `k` of a Hamming-windowed FIR Hilbert transformer: `2 / (pi k)` for
+/// odd `k` and 0 for even `k`, so that it turns `cos` into `sin`.
+pub fn hilbert_tap(k: i32) -> f32 {
+    if k % 2 == 0 {
+        return 0.0;
+    }
+    let window = 0.54 + 0.46 * (PI * k as f32 / HILBERT_HALF_WIDTH as f32).cos();
+    2.0 / (PI * k as f32) * window
+}
+
+/// Envelope of the beamformed pixel in column `col` and row `row`.
+///
+/// IQ pixels are complex already, so this is their magnitude. RF pixels are
+/// completed into the analytic signal with a Hilbert transform down their
+/// column, which needs an axial pixel spacing below a quarter wavelength.
+pub fn envelope(beamformed: &[f32], config: &BeamformingConfig, col: usize, row: usize) -> f32 {
+    let width = config.grid_width as usize;
+    let pixel = row * width + col;
+    if config.input_format != input_format::RF {
+        let (re, im) = (beamformed[2 * pixel], beamformed[2 * pixel + 1]);
+        return (re * re + im * im).sqrt();
+    }
+
+    let mut quadrature = 0.0;
+    let mut k = -HILBERT_HALF_WIDTH;
+    while k <= HILBERT_HALF_WIDTH {
+        let source_row = row as i32 - k;
+        if source_row >= 0 && source_row < config.grid_depth as i32 {
+            quadrature += hilbert_tap(k) * beamformed[source_row as usize * width + col];
+        }
+        k += 1;
+    }
+    let value = beamformed[pixel];
+    (value * value + quadrature * quadrature).sqrt()
+}
+
+/// Maps an envelope value onto `[0, 1]` on a log scale: `peak` plus the gain
+/// lands at 1, and `config.dynamic_range` dB below that at 0.
+pub fn log_compress(config: &BeamformingConfig, envelope: f32, peak: f32) -> f32 {
+    if envelope <= 0.0 || peak <= 0.0 {
+        return 0.0;
+    }
+    let db = 20.0 * LOG10_2 * (envelope / peak).log2() + config.gain;
+    ((db + config.dynamic_range) / config.dynamic_range).clamp(0.0, 1.0)
+}
+
+/// Rounds a normalized value to an 8-bit grey level.
+pub fn quantize(value: f32) -> u32 {
+    (value * 255.0 + 0.5) as u32
+}
+
+#[spirv(compute(threads(64)))]
+pub fn envelope_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] beamformed: &[f32],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] envelopes: &mut [f32],
+) {
+    let pixel = thread_index(global_id, num_workgroups);
+    if pixel >= pixel_count(config) {
+        return;
+    }
+    let width = config.grid_width as usize;
+    envelopes[pixel] = envelope(beamformed, config, pixel % width, pixel / width);
+}
+
+/// Reduces the envelope to its maximum in `peak[0]`, in a single workgroup.
+#[spirv(compute(threads(64)))]
+pub fn peak_shader(
+    #[spirv(local_invocation_id)] local_id: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] envelopes: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] peak: &mut [f32],
+    #[spirv(workgroup)] partial_max: &mut [f32; WORKGROUP_SIZE],
+) {
+    let thread_id = local_id.x as usize;
+    let mut max = 0.0f32;
+    let mut pixel = thread_id;
+    while pixel < pixel_count(config) {
+        max = max.max(envelopes[pixel]);
+        pixel += WORKGROUP_SIZE;
+    }
+    partial_max[thread_id] = max;
+    spirv_std::arch::workgroup_memory_barrier_with_group_sync();
+
+    let mut stride = WORKGROUP_SIZE / 2;
+    while stride > 0 {
+        if thread_id < stride {
+            partial_max[thread_id] = partial_max[thread_id].max(partial_max[thread_id + stride]);
+        }
+        spirv_std::arch::workgroup_memory_barrier_with_group_sync();
+        stride /= 2;
+    }
+
+    if thread_id == 0 {
+        peak[0] = partial_max[0];
+    }
+}
+
+#[spirv(compute(threads(64)))]
+pub fn log_compress_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] envelopes: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] peak: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] image: &mut [f32],
+) {
+    let pixel = thread_index(global_id, num_workgroups);
+    if pixel >= pixel_count(config) {
+        return;
+    }
+    image[pixel] = log_compress(config, envelopes[pixel], peak[0]);
+}
+
+/// 8-bit variant of [`log_compress_shader`]: each thread packs four
+/// consecutive pixels into one little-endian word.
+#[spirv(compute(threads(64)))]
+pub fn log_compress_u8_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] envelopes: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] peak: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] image: &mut [u32],
+) {
+    let word = thread_index(global_id, num_workgroups);
+    let pixels = pixel_count(config);
+    if 4 * word >= pixels {
+        return;
+    }
+    let mut packed = 0u32;
+    let mut byte = 0;
+    while byte < 4 && 4 * word + byte < pixels {
+        packed |= quantize(log_compress(config, envelopes[4 * word + byte], peak[0])) << (8 * byte);
+        byte += 1;
+    }
+    image[word] = packed;
+}
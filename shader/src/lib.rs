@@ -1,42 +1,335 @@
 #![no_std]
 
+use spirv_std::glam::{UVec3, Vec2};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
 use spirv_std::spirv;
-use spirv_std::glam::UVec3;
 
-#[repr(C)]
-pub struct BeamformingConfig {
-    pub speed_of_sound: f32,
+use core::f32::consts::PI;
+
+pub mod demodulate;
+pub mod doppler;
+pub mod fft;
+pub mod filter;
+pub mod image;
+pub mod mvdr;
+pub mod scan_convert;
+pub mod svd_filter;
+pub mod time_gain;
+
+pub use spirv_std::glam;
+pub use shared::{grid_geometry, input_format, interpolation, tgc, transmit_model, window, BeamformingConfig, Raster, TransmitEvent};
+
+/// Threads per workgroup. Channels are strided across the workgroup, so any
+/// channel count is supported.
+pub const WORKGROUP_SIZE: usize = 64;
+
+/// Flat thread index of a 1D dispatch that spills into `y` once it exceeds
+/// the per-dimension workgroup limit. Threads past the end of the work must
+/// return early.
+pub fn thread_index(global_id: UVec3, num_workgroups: UVec3) -> usize {
+    (global_id.y * num_workgroups.x * WORKGROUP_SIZE as u32 + global_id.x) as usize
+}
+
+/// Lateral position of an element, for a linear array centred on x = 0.
+pub fn element_position(config: &BeamformingConfig, channel: usize) -> f32 {
+    (channel as f32 - (config.num_channels as f32 - 1.0) * 0.5) * config.element_pitch
+}
+
+/// Time for the wavefront of `transmit` to reach `(x, z)`, relative to its
+/// passage through the transmit origin, using the event's transmit-delay model.
+pub fn transmit_delay(config: &BeamformingConfig, transmit: &TransmitEvent, x: f32, z: f32) -> f32 {
+    let source = Vec2::new(transmit.source_x, transmit.source_z);
+    let origin = Vec2::new(transmit.origin_x, transmit.origin_z);
+    let pixel = Vec2::new(x, z);
+    let path = match transmit.model {
+        // Spherical wave from the virtual source or element, timed from its
+        // passage through the origin
+        transmit_model::DIVERGING | transmit_model::ELEMENT => pixel.distance(source) - origin.distance(source),
+        // Converges on the focus, reaching it after the origin-to-focus path,
+        // then spreads from it. Pixels shallower than the focus are hit earlier.
+        transmit_model::FOCUSED => {
+            let to_focus = origin.distance(source);
+            if z < transmit.source_z {
+                to_focus - pixel.distance(source)
+            } else {
+                to_focus + pixel.distance(source)
+            }
+        }
+        _ => {
+            let (sin, cos) = (transmit.angle.sin(), transmit.angle.cos());
+            (x - origin.x) * sin + (z - origin.y) * cos
+        }
+    };
+    path / config.speed_of_sound
 }
 
+/// Two-way time of flight: from `transmit` to the pixel at `(x, z)`, then
+/// back to the element at `element_x`.
+pub fn time_of_flight(config: &BeamformingConfig, transmit: &TransmitEvent, x: f32, z: f32, element_x: f32) -> f32 {
+    let dx = x - element_x;
+    transmit_delay(config, transmit, x, z) + (dx * dx + z * z).sqrt() / config.speed_of_sound
+}
+
+/// Component `component` (0 for RF or I, 1 for Q) of sample `index` of
+/// `trace`, or 0 outside the recorded trace. Traces are numbered
+/// `transmit * num_channels + channel`; the layout of `input` is given by
+/// `config.input_format`.
+pub fn fetch(input: &[f32], config: &BeamformingConfig, trace: usize, component: usize, index: i32) -> f32 {
+    if index < 0 || index >= config.num_samples as i32 {
+        return 0.0;
+    }
+    let sample = trace * config.num_samples as usize + index as usize;
+    match config.input_format {
+        input_format::IQ_INTERLEAVED => input[2 * sample + component],
+        input_format::IQ_PLANAR => {
+            input[component * (config.num_transmits * config.num_channels * config.num_samples) as usize + sample]
+        }
+        _ => input[sample],
+    }
+}
+
+/// Component `component` of `trace` at the fractional sample `position`,
+/// using the interpolation mode selected in `config`.
+pub fn sample_at(input: &[f32], config: &BeamformingConfig, trace: usize, component: usize, position: f32) -> f32 {
+    let base = position.floor();
+    let frac = position - base;
+    let i = base as i32;
+    match config.interpolation {
+        interpolation::NEAREST => fetch(input, config, trace, component, position.round() as i32),
+        interpolation::CUBIC => {
+            let p0 = fetch(input, config, trace, component, i - 1);
+            let p1 = fetch(input, config, trace, component, i);
+            let p2 = fetch(input, config, trace, component, i + 1);
+            let p3 = fetch(input, config, trace, component, i + 2);
+            let a = -0.5 * p0 + 1.5 * p1 - 1.5 * p2 + 0.5 * p3;
+            let b = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+            let c = -0.5 * p0 + 0.5 * p2;
+            ((a * frac + b) * frac + c) * frac + p1
+        }
+        interpolation::SINC => {
+            let half_width = interpolation::SINC_HALF_WIDTH;
+            let mut sum = 0.0;
+            let mut k = i - half_width + 1;
+            while k <= i + half_width {
+                let d = position - k as f32;
+                let sinc = if d.abs() < 1e-6 { 1.0 } else { (PI * d).sin() / (PI * d) };
+                let hann = 0.5 + 0.5 * (PI * d / half_width as f32).cos();
+                sum += fetch(input, config, trace, component, k) * sinc * hann;
+                k += 1;
+            }
+            sum
+        }
+        _ => {
+            let p0 = fetch(input, config, trace, component, i);
+            let p1 = fetch(input, config, trace, component, i + 1);
+            p0 + (p1 - p0) * frac
+        }
+    }
+}
+
+/// Samples the echo of `transmit` (the `index`-th of the frame) from `(x, z)`
+/// on `channel`, at the time it reached that element, as `(re, im)`. RF
+/// samples have a zero imaginary part; IQ samples are rotated by
+/// `exp(j 2 pi f_demod tau)` to undo the demodulation phase of the delay `tau`.
+pub fn delayed_sample(
+    input: &[f32],
+    config: &BeamformingConfig,
+    transmit: &TransmitEvent,
+    index: usize,
+    channel: usize,
+    x: f32,
+    z: f32,
+) -> Vec2 {
+    let tof = time_of_flight(config, transmit, x, z, element_position(config, channel));
+    let position = (tof - config.start_time) * config.sampling_frequency;
+    let trace = index * config.num_channels as usize + channel;
+    if config.input_format == input_format::RF {
+        return Vec2::new(sample_at(input, config, trace, 0, position), 0.0);
+    }
+    let i = sample_at(input, config, trace, 0, position);
+    let q = sample_at(input, config, trace, 1, position);
+    let phase = 2.0 * PI * config.demodulation_frequency * tof;
+    let (sin, cos) = (phase.sin(), phase.cos());
+    Vec2::new(i * cos - q * sin, i * sin + q * cos)
+}
+
+/// Value of an apodization window at normalized aperture position `t`, where
+/// 0 and 1 are the outermost elements. Positions outside the aperture get 0.
+pub fn window_weight(kind: u32, tukey_alpha: f32, t: f32) -> f32 {
+    if !(0.0..=1.0).contains(&t) {
+        return 0.0;
+    }
+    match kind {
+        window::HANN => 0.5 - 0.5 * (2.0 * PI * t).cos(),
+        window::HAMMING => 0.54 - 0.46 * (2.0 * PI * t).cos(),
+        window::TUKEY => {
+            // Cosine taper over the outer `alpha / 2` of each side, flat in between
+            let taper = 0.5 * tukey_alpha;
+            let edge_distance = t.min(1.0 - t);
+            if edge_distance >= taper {
+                1.0
+            } else {
+                0.5 - 0.5 * (PI * edge_distance / taper).cos()
+            }
+        }
+        _ => 1.0,
+    }
+}
+
+/// Receive apodization weight of `channel` for the pixel at `(x, z)`.
+/// `apodization` holds one weight per element and is only read for
+/// [`window::CUSTOM`].
+///
+/// With a non-zero F-number the window spans an aperture of width
+/// `z / f_number` centred on the pixel, and elements outside it get 0;
+/// otherwise it spans the whole array.
+pub fn apodization_weight(config: &BeamformingConfig, apodization: &[f32], channel: usize, x: f32, z: f32) -> f32 {
+    let t = if config.f_number > 0.0 {
+        // Never narrower than one element, so the pixel always has a neighbour
+        let aperture = (z / config.f_number).max(config.element_pitch);
+        (element_position(config, channel) - x) / aperture + 0.5
+    } else if config.num_channels > 1 {
+        channel as f32 / (config.num_channels - 1) as f32
+    } else {
+        0.5
+    };
+    if config.apodization_window == window::CUSTOM {
+        return if (0.0..=1.0).contains(&t) { apodization[channel] } else { 0.0 };
+    }
+    window_weight(config.apodization_window, config.tukey_alpha, t)
+}
+
+/// Apodized sum of the delayed samples that `channel` contributes to the
+/// pixel at `(x, z)` over all transmits of the frame.
+pub fn channel_contribution(
+    input: &[f32],
+    apodization: &[f32],
+    transmits: &[TransmitEvent],
+    config: &BeamformingConfig,
+    channel: usize,
+    x: f32,
+    z: f32,
+) -> Vec2 {
+    let weight = apodization_weight(config, apodization, channel, x, z);
+    if weight == 0.0 {
+        return Vec2::ZERO;
+    }
+    let mut sum = Vec2::ZERO;
+    let mut index = 0;
+    while index < config.num_transmits as usize {
+        sum += delayed_sample(input, config, &transmits[index], index, channel, x, z);
+        index += 1;
+    }
+    weight * sum
+}
+
+/// Position `(x, z)` of the pixel in column `col` and row `row` of the output
+/// grid. On a polar grid the column gives the angle and the row the distance
+/// from the apex.
+pub fn pixel_position(config: &BeamformingConfig, col: usize, row: usize) -> Vec2 {
+    let u = config.grid_origin_x + col as f32 * config.grid_spacing_x;
+    let v = config.grid_origin_z + row as f32 * config.grid_spacing_z;
+    if config.grid_geometry == grid_geometry::POLAR {
+        Vec2::new(v * u.sin(), config.apex_z + v * u.cos())
+    } else {
+        Vec2::new(u, v)
+    }
+}
+
+/// Number of pixels in the output grid.
+pub fn pixel_count(config: &BeamformingConfig) -> usize {
+    config.grid_width as usize * config.grid_depth as usize
+}
+
+/// Position `(x, z)` of pixel `index` of the output, numbered
+/// `row * grid_width + col`: read from `pixels` for
+/// [`grid_geometry::POINTS`], otherwise computed from the grid.
+pub fn pixel_at(config: &BeamformingConfig, pixels: &[Vec2], index: usize) -> Vec2 {
+    if config.grid_geometry == grid_geometry::POINTS {
+        pixels[index]
+    } else {
+        let width = config.grid_width as usize;
+        pixel_position(config, index % width, index / width)
+    }
+}
+
+/// Delay-and-sum of every channel and transmit for the pixel at `(x, z)`, in
+/// channel order, as `(re, im)`. Summing the transmits coherently compounds
+/// their images.
+///
+/// `main_shader` computes the same sum split across a workgroup; this serial
+/// form is what the CPU backend runs.
+pub fn beamform_pixel(
+    input: &[f32],
+    apodization: &[f32],
+    transmits: &[TransmitEvent],
+    config: &BeamformingConfig,
+    x: f32,
+    z: f32,
+) -> Vec2 {
+    let mut sum = Vec2::ZERO;
+    for channel in 0..config.num_channels as usize {
+        sum += channel_contribution(input, apodization, transmits, config, channel, x, z);
+    }
+    sum
+}
+
+// Every binding is a parameter of the entry point
+#[allow(clippy::too_many_arguments)]
 #[spirv(compute(threads(64)))]
 pub fn main_shader(
-    #[spirv(global_invocation_id)] global_id: UVec3,
     #[spirv(local_invocation_id)] local_id: UVec3,
     #[spirv(workgroup_id)] group_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
     #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[f32],
     #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [f32],
     #[spirv(uniform, descriptor_set = 0, binding = 2)] config: &BeamformingConfig,
-    #[spirv(workgroup)] shared_samples: &mut [f32; 64],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] apodization: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] transmits: &[TransmitEvent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] pixels: &[Vec2],
+    #[spirv(workgroup)] partial_sums: &mut [Vec2; WORKGROUP_SIZE],
 ) {
     let thread_id = local_id.x as usize;
-    let sample_idx = group_id.x as usize;
-    const NUM_CHANNELS: usize = 64;
+    // One workgroup per pixel, spilling into y past the per-dimension limit;
+    // the whole workgroup leaves together, before any barrier
+    let pixel = (group_id.y * num_workgroups.x + group_id.x) as usize;
+    if pixel >= pixel_count(config) {
+        return;
+    }
+    let position = pixel_at(config, pixels, pixel);
+    let (x, z) = (position.x, position.y);
 
-    // 1. Each thread loads its channel's data for this specific time/location
-    let global_idx = sample_idx * NUM_CHANNELS + thread_id;
-    if global_idx < input.len() {
-        shared_samples[thread_id] = input[global_idx];
+    // 1. Each thread accumulates the apodized, delayed samples of every WORKGROUP_SIZE-th channel,
+    //    over all transmits of the frame
+    let mut sum = Vec2::ZERO;
+    let mut channel = thread_id;
+    while channel < config.num_channels as usize {
+        sum += channel_contribution(input, apodization, transmits, config, channel, x, z);
+        channel += WORKGROUP_SIZE;
     }
+    partial_sums[thread_id] = sum;
 
     // 2. Synchronize: Ensure all threads have finished writing to shared memory
     spirv_std::arch::workgroup_memory_barrier_with_group_sync();
 
-    // 3. Summation scaled by Speed of Sound
+    // 3. Tree reduction of the partial sums into the pixel
+    let mut stride = WORKGROUP_SIZE / 2;
+    while stride > 0 {
+        if thread_id < stride {
+            partial_sums[thread_id] += partial_sums[thread_id + stride];
+        }
+        spirv_std::arch::workgroup_memory_barrier_with_group_sync();
+        stride /= 2;
+    }
+
+    // RF frames hold one real value per pixel, IQ frames interleaved (re, im)
     if thread_id == 0 {
-        let mut sum = 0.0;
-        for i in 0..NUM_CHANNELS {
-            sum += shared_samples[i];
+        if config.input_format == input_format::RF {
+            output[pixel] = partial_sums[0].x;
+        } else {
+            output[2 * pixel] = partial_sums[0].x;
+            output[2 * pixel + 1] = partial_sums[0].y;
         }
-        output[sample_idx] = sum * config.speed_of_sound;
     }
 }
@@ -0,0 +1,190 @@
+//! Minimum-variance distortionless response (Capon) beamforming.
+//!
+//! The delayed channel samples of a pixel are split into overlapping
+//! subarrays of `config.mvdr_subarray` elements, whose averaged outer
+//! products estimate the spatial covariance `R`. Diagonal loading keeps it
+//! well conditioned, and the weights `w = R^-1 a / (a^H R^-1 a)`, with `a`
+//! all ones as the data is already focused on the pixel, pass it with unit
+//! gain while minimizing the power received from elsewhere. They are applied
+//! to every subarray and the results averaged.
+//!
+//! Each pixel solves its own small system, so unlike `main_shader` a thread
+//! beamforms whole pixels, working in its own slot of a scratch buffer.
+
+use spirv_std::glam::{UVec3, Vec2};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::spirv;
+
+use crate::fft::complex_mul;
+use crate::{
+    delayed_sample, input_format, pixel_at, pixel_count, thread_index, BeamformingConfig, TransmitEvent,
+    WORKGROUP_SIZE,
+};
+
+/// `Vec2` values of one scratch slot: the delayed samples of every channel,
+/// the subarray covariance (factored in place) and the solution of the
+/// weight system.
+pub fn mvdr_scratch_len(config: &BeamformingConfig) -> usize {
+    let (channels, subarray) = (config.num_channels as usize, config.mvdr_subarray as usize);
+    channels + subarray * subarray + subarray
+}
+
+fn conj(a: Vec2) -> Vec2 {
+    Vec2::new(a.x, -a.y)
+}
+
+/// Minimum-variance beamformed value of the pixel at `(x, z)`, computed in
+/// the scratch slot starting at `slot`. It is scaled by the channel count,
+/// so that uniform weights give the unapodized delay-and-sum value.
+pub fn mvdr_pixel(
+    input: &[f32],
+    transmits: &[TransmitEvent],
+    config: &BeamformingConfig,
+    x: f32,
+    z: f32,
+    scratch: &mut [Vec2],
+    slot: usize,
+) -> Vec2 {
+    let channels = config.num_channels as usize;
+    let size = config.mvdr_subarray as usize;
+    let subarrays = channels + 1 - size;
+    let covariance = slot + channels;
+    let solution = covariance + size * size;
+
+    // 1. Delayed samples of every channel, summed over the transmits
+    let mut channel = 0;
+    while channel < channels {
+        let mut sum = Vec2::ZERO;
+        let mut index = 0;
+        while index < config.num_transmits as usize {
+            sum += delayed_sample(input, config, &transmits[index], index, channel, x, z);
+            index += 1;
+        }
+        scratch[slot + channel] = sum;
+        channel += 1;
+    }
+
+    // 2. Lower triangle of the covariance, entry (row, col) the mean over
+    //    subarrays of sample `row` times the conjugate of sample `col`
+    let mut trace = 0.0;
+    let mut row = 0;
+    while row < size {
+        let mut col = 0;
+        while col <= row {
+            let mut sum = Vec2::ZERO;
+            let mut first = slot;
+            while first < slot + subarrays {
+                sum += complex_mul(scratch[first + row], conj(scratch[first + col]));
+                first += 1;
+            }
+            scratch[covariance + row * size + col] = sum / subarrays as f32;
+            col += 1;
+        }
+        trace += scratch[covariance + row * size + row].x;
+        row += 1;
+    }
+    if trace <= 0.0 {
+        return Vec2::ZERO;
+    }
+
+    // 3. Cholesky factorization `R + loading I = C C^H`, column by column
+    let loading = config.mvdr_loading * trace / size as f32;
+    let mut col = 0;
+    while col < size {
+        let mut pivot = scratch[covariance + col * size + col].x + loading;
+        let mut k = 0;
+        while k < col {
+            pivot -= scratch[covariance + col * size + k].length_squared();
+            k += 1;
+        }
+        let pivot = pivot.max(f32::MIN_POSITIVE).sqrt();
+        scratch[covariance + col * size + col] = Vec2::new(pivot, 0.0);
+        let mut row = col + 1;
+        while row < size {
+            let mut sum = scratch[covariance + row * size + col];
+            let mut k = 0;
+            while k < col {
+                sum -= complex_mul(scratch[covariance + row * size + k], conj(scratch[covariance + col * size + k]));
+                k += 1;
+            }
+            scratch[covariance + row * size + col] = sum / pivot;
+            row += 1;
+        }
+        col += 1;
+    }
+
+    // 4. Solve `C y = a` forwards, then `C^H u = y` backwards in place
+    let mut row = 0;
+    while row < size {
+        let mut sum = Vec2::new(1.0, 0.0);
+        let mut k = 0;
+        while k < row {
+            sum -= complex_mul(scratch[covariance + row * size + k], scratch[solution + k]);
+            k += 1;
+        }
+        scratch[solution + row] = sum / scratch[covariance + row * size + row].x;
+        row += 1;
+    }
+    let mut row = size;
+    while row > 0 {
+        row -= 1;
+        let mut sum = scratch[solution + row];
+        let mut k = row + 1;
+        while k < size {
+            sum -= complex_mul(conj(scratch[covariance + k * size + row]), scratch[solution + k]);
+            k += 1;
+        }
+        scratch[solution + row] = sum / scratch[covariance + row * size + row].x;
+    }
+
+    // 5. Apply `w = u / (a^H u)` to every subarray; `a^H R^-1 a` is real
+    let mut norm = 0.0;
+    let mut output = Vec2::ZERO;
+    let mut element = 0;
+    while element < size {
+        let weight = scratch[solution + element];
+        norm += weight.x;
+        let mut first = slot;
+        while first < slot + subarrays {
+            output += complex_mul(conj(weight), scratch[first + element]);
+            first += 1;
+        }
+        element += 1;
+    }
+    output * (channels as f32 / (norm * subarrays as f32))
+}
+
+/// One thread per scratch slot, each beamforming every `slots`-th pixel; the
+/// host sizes the scratch buffer for exactly as many slots as it launches
+/// threads, in a 1D dispatch.
+// Every binding is a parameter of the entry point
+#[allow(clippy::too_many_arguments)]
+#[spirv(compute(threads(64)))]
+pub fn mvdr_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] input: &[f32],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 1)] output: &mut [f32],
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] transmits: &[TransmitEvent],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 5)] pixels: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 6)] scratch: &mut [Vec2],
+) {
+    let slots = num_workgroups.x as usize * WORKGROUP_SIZE;
+    let thread = thread_index(global_id, num_workgroups);
+    let slot = thread * mvdr_scratch_len(config);
+    let mut pixel = thread;
+    while pixel < pixel_count(config) {
+        let position = pixel_at(config, pixels, pixel);
+        let value = mvdr_pixel(input, transmits, config, position.x, position.y, scratch, slot);
+        // RF frames hold one real value per pixel, IQ frames interleaved (re, im)
+        if config.input_format == input_format::RF {
+            output[pixel] = value.x;
+        } else {
+            output[2 * pixel] = value.x;
+            output[2 * pixel + 1] = value.y;
+        }
+        pixel += slots;
+    }
+}
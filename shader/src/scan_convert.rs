@@ -0,0 +1,72 @@
+//! Scan conversion of a beamformed image onto a Cartesian raster for display:
+//! a sector beamformed on a polar grid is resampled bilinearly, and raster
+//! pixels outside it are masked to 0.
+
+use spirv_std::glam::{UVec3, Vec2};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::spirv;
+
+use crate::{grid_geometry, thread_index, BeamformingConfig, Raster};
+
+/// Threads of the scan conversion: one per raster pixel.
+pub fn scan_convert_threads(raster: &Raster) -> usize {
+    raster.width as usize * raster.depth as usize
+}
+
+/// Fractional column and row of the grid of `config` at `(x, z)`; on a polar
+/// grid, from the angle and distance of the point from the apex.
+pub fn grid_coordinates(config: &BeamformingConfig, x: f32, z: f32) -> Vec2 {
+    let (u, v) = if config.grid_geometry == grid_geometry::POLAR {
+        let dz = z - config.apex_z;
+        (x.atan2(dz), (x * x + dz * dz).sqrt())
+    } else {
+        (x, z)
+    };
+    Vec2::new((u - config.grid_origin_x) / config.grid_spacing_x, (v - config.grid_origin_z) / config.grid_spacing_z)
+}
+
+/// Pixel `index` of the raster, numbered `row * raster.width + col`,
+/// interpolated bilinearly from `image`, laid out `[depth][width]` on the
+/// grid of `config`. Pixels outside the grid are 0.
+pub fn scan_convert_pixel(image: &[f32], config: &BeamformingConfig, raster: &Raster, index: usize) -> f32 {
+    let col = index % raster.width as usize;
+    let row = index / raster.width as usize;
+    let x = raster.origin_x + col as f32 * raster.spacing_x;
+    let z = raster.origin_z + row as f32 * raster.spacing_z;
+    let position = grid_coordinates(config, x, z);
+
+    let width = config.grid_width as usize;
+    let depth = config.grid_depth as usize;
+    let inside = position.x >= 0.0
+        && position.y >= 0.0
+        && position.x <= (width - 1) as f32
+        && position.y <= (depth - 1) as f32;
+    if !inside {
+        return 0.0;
+    }
+    let base = position.floor();
+    let frac = position - base;
+    let (c0, r0) = (base.x as usize, base.y as usize);
+    // The last column and row only interpolate towards themselves
+    let c1 = if c0 + 1 < width { c0 + 1 } else { c0 };
+    let r1 = if r0 + 1 < depth { r0 + 1 } else { r0 };
+    let top = image[r0 * width + c0] * (1.0 - frac.x) + image[r0 * width + c1] * frac.x;
+    let bottom = image[r1 * width + c0] * (1.0 - frac.x) + image[r1 * width + c1] * frac.x;
+    top * (1.0 - frac.y) + bottom * frac.y
+}
+
+#[spirv(compute(threads(64)))]
+pub fn scan_convert_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] image: &[f32],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(uniform, descriptor_set = 0, binding = 2)] raster: &Raster,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] output: &mut [f32],
+) {
+    let index = thread_index(global_id, num_workgroups);
+    if index < scan_convert_threads(raster) {
+        output[index] = scan_convert_pixel(image, config, raster, index);
+    }
+}
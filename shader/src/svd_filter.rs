@@ -0,0 +1,101 @@
+//! Spatiotemporal (SVD) clutter filtering of Doppler ensembles.
+//!
+//! The ensemble, laid out `[frame][pixel]`, is the Casorati matrix `S` of
+//! pixels by frames. The eigenvectors of its time covariance `S^H S` are its
+//! right singular vectors; the host decomposes that small matrix and hands back
+//! the projection `P = I - sum v_k v_k^H` over the removed components, which
+//! filters every pixel's ensemble as `s P`.
+
+use spirv_std::glam::{UVec3, Vec2};
+use spirv_std::spirv;
+
+use crate::fft::complex_mul;
+use crate::{pixel_count, thread_index, BeamformingConfig, WORKGROUP_SIZE};
+
+/// Threads of the filter stage: one per sample of the ensemble.
+pub fn svd_filter_threads(config: &BeamformingConfig) -> usize {
+    config.ensemble_length as usize * pixel_count(config)
+}
+
+/// Sum over pixels `first`, `first + stride`, ... of the product of frames
+/// `row` and `col`, conjugating the first: part of entry `(row, col)` of the
+/// time covariance `S^H S`.
+pub fn covariance_sum(
+    ensemble: &[Vec2],
+    config: &BeamformingConfig,
+    row: usize,
+    col: usize,
+    first: usize,
+    stride: usize,
+) -> Vec2 {
+    let pixels = pixel_count(config);
+    let mut sum = Vec2::ZERO;
+    let mut pixel = first;
+    while pixel < pixels {
+        let a = ensemble[row * pixels + pixel];
+        sum += complex_mul(Vec2::new(a.x, -a.y), ensemble[col * pixels + pixel]);
+        pixel += stride;
+    }
+    sum
+}
+
+/// Sample `index` of the filtered ensemble, laid out like the input: the
+/// samples of its pixel times column `frame` of the projection, stored row
+/// by row.
+pub fn project(ensemble: &[Vec2], projection: &[Vec2], config: &BeamformingConfig, index: usize) -> Vec2 {
+    let pixels = pixel_count(config);
+    let frames = config.ensemble_length as usize;
+    let (frame, pixel) = (index / pixels, index % pixels);
+    let mut sum = Vec2::ZERO;
+    let mut source = 0;
+    while source < frames {
+        sum += complex_mul(ensemble[source * pixels + pixel], projection[source * frames + frame]);
+        source += 1;
+    }
+    sum
+}
+
+/// One workgroup per covariance entry, `x` the column and `y` the row,
+/// pixels strided across its threads.
+#[spirv(compute(threads(64)))]
+pub fn covariance_shader(
+    #[spirv(local_invocation_id)] local_id: UVec3,
+    #[spirv(workgroup_id)] group_id: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] ensemble: &[Vec2],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] covariance: &mut [Vec2],
+    #[spirv(workgroup)] partial_sums: &mut [Vec2; WORKGROUP_SIZE],
+) {
+    let thread_id = local_id.x as usize;
+    let (row, col) = (group_id.y as usize, group_id.x as usize);
+    partial_sums[thread_id] = covariance_sum(ensemble, config, row, col, thread_id, WORKGROUP_SIZE);
+    spirv_std::arch::workgroup_memory_barrier_with_group_sync();
+
+    let mut stride = WORKGROUP_SIZE / 2;
+    while stride > 0 {
+        if thread_id < stride {
+            partial_sums[thread_id] += partial_sums[thread_id + stride];
+        }
+        spirv_std::arch::workgroup_memory_barrier_with_group_sync();
+        stride /= 2;
+    }
+
+    if thread_id == 0 {
+        covariance[row * config.ensemble_length as usize + col] = partial_sums[0];
+    }
+}
+
+#[spirv(compute(threads(64)))]
+pub fn svd_filter_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 0)] ensemble: &[Vec2],
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] projection: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 4)] filtered: &mut [Vec2],
+) {
+    let index = thread_index(global_id, num_workgroups);
+    if index < svd_filter_threads(config) {
+        filtered[index] = project(ensemble, projection, config, index);
+    }
+}
@@ -0,0 +1,68 @@
+//! Time-gain compensation of the channel data: a gain growing with the depth
+//! each sample comes from, applied in place just before beamforming so deep
+//! echoes, weakened by attenuation, come out as bright as shallow ones.
+
+use spirv_std::glam::{UVec3, Vec2};
+#[allow(unused_imports)]
+use spirv_std::num_traits::Float;
+use spirv_std::spirv;
+
+use crate::filter::filter_threads;
+use crate::{input_format, tgc, thread_index, BeamformingConfig};
+
+/// Gain (dB) at `depth` (m) for the TGC mode selected in `config`. `curve`
+/// holds the `(depth, gain)` points of [`tgc::CURVE`], sorted by depth.
+pub fn gain_db(config: &BeamformingConfig, curve: &[Vec2], depth: f32) -> f32 {
+    match config.tgc {
+        // Round trip through `depth` cm at `frequency` MHz
+        tgc::ATTENUATION => 2.0 * config.tgc_attenuation * config.tgc_frequency * 1.0e-6 * depth.max(0.0) * 100.0,
+        tgc::CURVE => {
+            let num_points = config.num_tgc_points as usize;
+            if depth <= curve[0].x {
+                return curve[0].y;
+            }
+            let mut i = 1;
+            while i < num_points {
+                let (a, b) = (curve[i - 1], curve[i]);
+                // Points sharing a depth make a step, never reached here
+                if depth < b.x {
+                    return a.y + (b.y - a.y) * (depth - a.x) / (b.x - a.x);
+                }
+                i += 1;
+            }
+            curve[num_points - 1].y
+        }
+        _ => 0.0,
+    }
+}
+
+/// Linear gain of sample `sample` of every trace, from the depth `c t / 2`
+/// its absolute time `t` corresponds to.
+pub fn sample_gain(config: &BeamformingConfig, curve: &[Vec2], sample: usize) -> f32 {
+    let time = config.start_time + sample as f32 / config.sampling_frequency;
+    let depth = 0.5 * config.speed_of_sound * time;
+    10.0f32.powf(gain_db(config, curve, depth) / 20.0)
+}
+
+/// `data[index]` with its gain applied, where `data` is laid out as
+/// `config.input_format` describes. Both IQ components get the same gain.
+pub fn compensate_sample(data: &[f32], curve: &[Vec2], config: &BeamformingConfig, index: usize) -> f32 {
+    // Planes of planar IQ hold whole traces, so only interleaving moves samples
+    let position = if config.input_format == input_format::IQ_INTERLEAVED { index / 2 } else { index };
+    data[index] * sample_gain(config, curve, position % config.num_samples as usize)
+}
+
+#[spirv(compute(threads(64)))]
+pub fn time_gain_shader(
+    #[spirv(global_invocation_id)] global_id: UVec3,
+    #[spirv(num_workgroups)] num_workgroups: UVec3,
+    #[spirv(uniform, descriptor_set = 0, binding = 1)] config: &BeamformingConfig,
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 2)] curve: &[Vec2],
+    #[spirv(storage_buffer, descriptor_set = 0, binding = 3)] data: &mut [f32],
+) {
+    let index = thread_index(global_id, num_workgroups);
+    // One thread per value, like the filter stage
+    if index < filter_threads(config) {
+        data[index] = compensate_sample(data, curve, config, index);
+    }
+}
@@ -0,0 +1,420 @@
+//! Types shared between the host and the shader crate.
+//!
+//! Everything here is uploaded to the GPU as raw bytes, so each struct is
+//! `#[repr(C)]`, built from 4-byte scalars only, and padded by hand. The
+//! layout assertions below fail the build of *both* crates if a field is
+//! added, removed or reordered without updating the expected offsets.
+
+#![no_std]
+
+/// Uniform block describing the acquisition geometry and the output grid.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub struct BeamformingConfig {
+    /// Speed of sound in the medium (m/s).
+    pub speed_of_sound: f32,
+    /// RF sampling frequency (Hz).
+    pub sampling_frequency: f32,
+    /// Centre-to-centre distance between neighbouring elements (m).
+    pub element_pitch: f32,
+    /// Time of the first RF sample relative to the transmit event (s).
+    pub start_time: f32,
+    /// Lateral position of the first pixel column (m), or its angle from the
+    /// z axis on a polar grid (rad).
+    pub grid_origin_x: f32,
+    /// Depth of the first pixel row (m), or its distance from the apex on a
+    /// polar grid.
+    pub grid_origin_z: f32,
+    /// Lateral distance between pixel columns (m), or the angle between them
+    /// on a polar grid (rad).
+    pub grid_spacing_x: f32,
+    /// Axial distance between pixel rows (m), or the radial one on a polar
+    /// grid.
+    pub grid_spacing_z: f32,
+    /// Number of pixel columns.
+    pub grid_width: u32,
+    /// Number of pixel rows.
+    pub grid_depth: u32,
+    /// Number of receive channels (array elements).
+    pub num_channels: u32,
+    /// Number of RF samples recorded per channel.
+    pub num_samples: u32,
+    /// Receive apodization window, one of the [`window`] constants.
+    pub apodization_window: u32,
+    /// Tapered fraction of the aperture for [`window::TUKEY`], from 0
+    /// (rectangular) to 1 (Hann).
+    pub tukey_alpha: f32,
+    /// Receive F-number (depth / aperture width). The active aperture grows
+    /// with depth and is centred on each pixel; 0 uses the full array.
+    pub f_number: f32,
+    /// How RF traces are sampled between samples, one of the
+    /// [`interpolation`] constants.
+    pub interpolation: u32,
+    /// Layout of the channel data, one of the [`input_format`] constants.
+    pub input_format: u32,
+    /// Frequency the IQ data was demodulated with (Hz). Delayed IQ samples
+    /// are rotated by `exp(j 2 pi f_demod tau)` to restore the carrier phase.
+    pub demodulation_frequency: f32,
+    /// Number of transmit events per frame, matching the transmit buffer.
+    /// Their echoes are beamformed and summed coherently into one image.
+    pub num_transmits: u32,
+    /// Range of envelope levels (dB) below the frame's peak that the B-mode
+    /// image spans; weaker echoes are black.
+    pub dynamic_range: f32,
+    /// Offset (dB) added to the log-compressed envelope before it is mapped
+    /// onto the dynamic range. Positive values brighten the image.
+    pub gain: f32,
+    /// Number of FIR taps applied along the samples of every trace before
+    /// beamforming, matching the filter buffer. 0 disables the filter.
+    pub num_filter_taps: u32,
+    /// Decimation factor of the RF-to-IQ demodulation stage; 0 disables it.
+    /// RF input is then mixed down by `demodulation_frequency`, low-pass
+    /// filtered and every `decimation`-th sample kept, and the beamformer
+    /// sees interleaved IQ at `sampling_frequency / decimation`.
+    pub decimation: u32,
+    /// Number of low-pass taps of the demodulation stage, matching the
+    /// demodulation filter buffer.
+    pub num_demodulation_taps: u32,
+    /// Time-gain compensation of the channel data before beamforming, one of
+    /// the [`tgc`] constants.
+    pub tgc: u32,
+    /// Attenuation compensated by [`tgc::ATTENUATION`] (dB/cm/MHz).
+    pub tgc_attenuation: f32,
+    /// Frequency the attenuation is evaluated at for [`tgc::ATTENUATION`],
+    /// usually the centre frequency of the pulse (Hz).
+    pub tgc_frequency: f32,
+    /// Number of `(depth, gain)` points of the [`tgc::CURVE`] gain curve,
+    /// matching the TGC buffer.
+    pub num_tgc_points: u32,
+    /// Shape of the pixel grid, one of the [`grid_geometry`] constants.
+    pub grid_geometry: u32,
+    /// Depth of the apex of a polar grid (m): 0 for a phased array, minus
+    /// the radius of curvature for a convex one.
+    pub apex_z: f32,
+    /// Number of frames per Doppler ensemble, at least 2 for the lag-one
+    /// autocorrelation.
+    pub ensemble_length: u32,
+    /// Rate at which the frames of a Doppler ensemble are acquired (Hz).
+    pub pulse_repetition_frequency: f32,
+    /// Centre frequency of the transmitted pulse, which Doppler phase shifts
+    /// are converted to velocities with (Hz).
+    pub center_frequency: f32,
+    /// Order of the polynomial regression wall filter applied along the
+    /// ensemble of every pixel before Doppler estimation: trends of lower
+    /// degree are projected out. 0 disables it, 1 removes the mean (static
+    /// clutter) and 2 a linear drift as well.
+    pub clutter_filter_order: u32,
+    /// Subarray length of minimum-variance (Capon) beamforming, which replaces
+    /// the apodized delay-and-sum with adaptive weights over every channel.
+    /// 0 keeps delay-and-sum.
+    pub mvdr_subarray: u32,
+    /// Diagonal loading of the minimum-variance covariance, as a fraction of
+    /// its mean eigenvalue. Larger values trade resolution for robustness and
+    /// tend towards delay-and-sum.
+    pub mvdr_loading: f32,
+}
+
+impl Default for BeamformingConfig {
+    /// A 128-element, 0.3 mm pitch probe sampled at 40 MHz in soft tissue,
+    /// with an empty output grid.
+    fn default() -> Self {
+        Self {
+            speed_of_sound: 1540.0,
+            sampling_frequency: 40.0e6,
+            element_pitch: 0.3e-3,
+            start_time: 0.0,
+            grid_origin_x: 0.0,
+            grid_origin_z: 0.0,
+            grid_spacing_x: 0.0,
+            grid_spacing_z: 0.0,
+            grid_width: 0,
+            grid_depth: 0,
+            num_channels: 128,
+            num_samples: 0,
+            apodization_window: window::RECTANGULAR,
+            tukey_alpha: 0.5,
+            f_number: 0.0,
+            interpolation: interpolation::NEAREST,
+            input_format: input_format::RF,
+            demodulation_frequency: 0.0,
+            num_transmits: 1,
+            dynamic_range: 60.0,
+            gain: 0.0,
+            num_filter_taps: 0,
+            decimation: 0,
+            num_demodulation_taps: 0,
+            tgc: tgc::OFF,
+            tgc_attenuation: 0.0,
+            tgc_frequency: 0.0,
+            num_tgc_points: 0,
+            grid_geometry: grid_geometry::CARTESIAN,
+            apex_z: 0.0,
+            ensemble_length: 0,
+            pulse_repetition_frequency: 0.0,
+            center_frequency: 0.0,
+            clutter_filter_order: 0,
+            mvdr_subarray: 0,
+            mvdr_loading: 0.01,
+        }
+    }
+}
+
+/// One transmit event of a frame, read from a storage buffer.
+///
+/// The wavefront passes through `(origin_x, origin_z)` at time 0; `model`
+/// selects its shape.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub struct TransmitEvent {
+    /// Steering angle of a plane wave from the z axis, positive towards +x
+    /// (rad).
+    pub angle: f32,
+    /// Lateral position of the transmit origin (m).
+    pub origin_x: f32,
+    /// Depth of the transmit origin (m).
+    pub origin_z: f32,
+    /// Transmit-delay model, one of the [`transmit_model`] constants.
+    pub model: u32,
+    /// Lateral position of the virtual source of a diverging or focused wave,
+    /// or of the firing element (m).
+    pub source_x: f32,
+    /// Depth of the virtual source: negative (behind the array) for a
+    /// diverging wave, positive (the focus) for a focused one (m).
+    pub source_z: f32,
+    pub _pad0: u32,
+    pub _pad1: u32,
+}
+
+impl TransmitEvent {
+    /// A plane wave steered by `angle`, timed from the centre of the array.
+    pub fn plane_wave(angle: f32) -> Self {
+        Self { angle, ..Default::default() }
+    }
+
+    /// A diverging wave from a virtual source behind the array (`z < 0`),
+    /// timed from the centre of the array.
+    pub fn diverging(source_x: f32, source_z: f32) -> Self {
+        Self { model: transmit_model::DIVERGING, source_x, source_z, ..Default::default() }
+    }
+
+    /// A wave focused on `(focus_x, focus_z)` in front of the array, timed
+    /// from the centre of the array.
+    pub fn focused(focus_x: f32, focus_z: f32) -> Self {
+        Self { model: transmit_model::FOCUSED, source_x: focus_x, source_z: focus_z, ..Default::default() }
+    }
+
+    /// A single element at lateral position `element_x` firing alone, timed
+    /// from the moment it fires. One per element makes a synthetic transmit
+    /// aperture acquisition.
+    pub fn element(element_x: f32) -> Self {
+        Self { model: transmit_model::ELEMENT, origin_x: element_x, source_x: element_x, ..Default::default() }
+    }
+}
+
+/// Apodization window kinds for [`BeamformingConfig::apodization_window`].
+pub mod window {
+    pub const RECTANGULAR: u32 = 0;
+    pub const HANN: u32 = 1;
+    pub const HAMMING: u32 = 2;
+    pub const TUKEY: u32 = 3;
+    /// Per-element weights read from the apodization buffer.
+    pub const CUSTOM: u32 = 4;
+}
+
+/// Sub-sample interpolation modes for [`BeamformingConfig::interpolation`].
+pub mod interpolation {
+    pub const NEAREST: u32 = 0;
+    pub const LINEAR: u32 = 1;
+    /// Catmull-Rom cubic through the four surrounding samples.
+    pub const CUBIC: u32 = 2;
+    /// Hann-windowed sinc over [`SINC_HALF_WIDTH`] samples on each side.
+    pub const SINC: u32 = 3;
+
+    pub const SINC_HALF_WIDTH: i32 = 4;
+}
+
+/// Channel data layouts for [`BeamformingConfig::input_format`].
+pub mod input_format {
+    /// Real RF samples, `[channel][sample]`.
+    pub const RF: u32 = 0;
+    /// Complex baseband samples, `[channel][sample][I, Q]`.
+    pub const IQ_INTERLEAVED: u32 = 1;
+    /// Complex baseband samples, `[I, Q][channel][sample]`.
+    pub const IQ_PLANAR: u32 = 2;
+}
+
+/// Time-gain compensation modes for [`BeamformingConfig::tgc`]. The gain of a
+/// sample depends on the depth `c t / 2` its round trip time `t` reaches.
+pub mod tgc {
+    pub const OFF: u32 = 0;
+    /// Cancels the round-trip attenuation of `tgc_attenuation` dB/cm/MHz at
+    /// `tgc_frequency`.
+    pub const ATTENUATION: u32 = 1;
+    /// Gain (dB) interpolated linearly between the points of the TGC buffer,
+    /// held constant before the first and after the last.
+    pub const CURVE: u32 = 2;
+}
+
+/// Pixel grid shapes for [`BeamformingConfig::grid_geometry`].
+pub mod grid_geometry {
+    /// Columns along x and rows along z.
+    pub const CARTESIAN: u32 = 0;
+    /// Columns at angles from the z axis and rows at distances from an apex
+    /// at `(0, apex_z)`: the sector of a phased or convex probe.
+    pub const POLAR: u32 = 1;
+    /// Arbitrary positions read from the pixel buffer, `grid_width` per row
+    /// over `grid_depth` rows; the grid origin and spacing are unused.
+    pub const POINTS: u32 = 2;
+}
+
+/// Uniform block describing the Cartesian raster an image is scan-converted
+/// onto.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub struct Raster {
+    /// Lateral position of the first column (m).
+    pub origin_x: f32,
+    /// Depth of the first row (m).
+    pub origin_z: f32,
+    /// Lateral distance between columns (m).
+    pub spacing_x: f32,
+    /// Axial distance between rows (m).
+    pub spacing_z: f32,
+    /// Number of columns.
+    pub width: u32,
+    /// Number of rows.
+    pub depth: u32,
+    pub _pad0: u32,
+    pub _pad1: u32,
+}
+
+/// Uniform block of one step of a batched FFT: a radix pass, or one of the
+/// chirp stages of Bluestein's algorithm.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "bytemuck", derive(bytemuck::Pod, bytemuck::Zeroable))]
+pub struct FftParams {
+    /// Length of the transforms the step works on: a power of two, padded for
+    /// Bluestein.
+    pub len: u32,
+    /// Length of the signal being transformed; differs from `len` only in the
+    /// Bluestein stages.
+    pub signal_len: u32,
+    /// Number of transforms, stored one after another.
+    pub batch: u32,
+    /// Product of the radices of the earlier passes.
+    pub span: u32,
+    /// Radix of the pass, 2 or 4.
+    pub radix: u32,
+    /// 1 for an inverse transform, 0 for a forward one.
+    pub inverse: u32,
+    /// Factor applied to every value the step writes.
+    pub scale: f32,
+    pub _pad0: u32,
+}
+
+/// Transmit-delay models for [`TransmitEvent::model`].
+pub mod transmit_model {
+    /// Planar wavefront steered by the transmit angle.
+    pub const PLANE_WAVE: u32 = 0;
+    /// Spherical wavefront spreading from a virtual source behind the array.
+    pub const DIVERGING: u32 = 1;
+    /// Wavefront converging on a focus in front of the array, then spreading
+    /// from it.
+    pub const FOCUSED: u32 = 2;
+    /// Spherical wavefront from a single element on the array.
+    pub const ELEMENT: u32 = 3;
+}
+
+/// Asserts at compile time that a GPU-visible struct has the given size and
+/// field offsets, and that it satisfies the std140 rules for uniform blocks
+/// (which are also valid std430): 4-byte alignment for the scalar fields and
+/// a total size rounded up to 16 bytes.
+macro_rules! assert_gpu_layout {
+    ($ty:ty, size = $size:expr, { $($field:ident: $offset:expr),* $(,)? }) => {
+        const _: () = {
+            assert!(core::mem::size_of::<$ty>() == $size);
+            assert!(core::mem::size_of::<$ty>() % 16 == 0);
+            assert!(core::mem::align_of::<$ty>() == 4);
+            $(
+                assert!(core::mem::offset_of!($ty, $field) == $offset);
+                assert!(core::mem::offset_of!($ty, $field) % 4 == 0);
+            )*
+        };
+    };
+}
+
+assert_gpu_layout!(BeamformingConfig, size = 144, {
+    speed_of_sound: 0,
+    sampling_frequency: 4,
+    element_pitch: 8,
+    start_time: 12,
+    grid_origin_x: 16,
+    grid_origin_z: 20,
+    grid_spacing_x: 24,
+    grid_spacing_z: 28,
+    grid_width: 32,
+    grid_depth: 36,
+    num_channels: 40,
+    num_samples: 44,
+    apodization_window: 48,
+    tukey_alpha: 52,
+    f_number: 56,
+    interpolation: 60,
+    input_format: 64,
+    demodulation_frequency: 68,
+    num_transmits: 72,
+    dynamic_range: 76,
+    gain: 80,
+    num_filter_taps: 84,
+    decimation: 88,
+    num_demodulation_taps: 92,
+    tgc: 96,
+    tgc_attenuation: 100,
+    tgc_frequency: 104,
+    num_tgc_points: 108,
+    grid_geometry: 112,
+    apex_z: 116,
+    ensemble_length: 120,
+    pulse_repetition_frequency: 124,
+    center_frequency: 128,
+    clutter_filter_order: 132,
+    mvdr_subarray: 136,
+    mvdr_loading: 140,
+});
+
+assert_gpu_layout!(Raster, size = 32, {
+    origin_x: 0,
+    origin_z: 4,
+    spacing_x: 8,
+    spacing_z: 12,
+    width: 16,
+    depth: 20,
+    _pad0: 24,
+    _pad1: 28,
+});
+
+assert_gpu_layout!(FftParams, size = 32, {
+    len: 0,
+    signal_len: 4,
+    batch: 8,
+    span: 12,
+    radix: 16,
+    inverse: 20,
+    scale: 24,
+    _pad0: 28,
+});
+
+assert_gpu_layout!(TransmitEvent, size = 32, {
+    angle: 0,
+    origin_x: 4,
+    origin_z: 8,
+    model: 12,
+    source_x: 16,
+    source_z: 20,
+    _pad0: 24,
+    _pad1: 28,
+});
@@ -0,0 +1,34 @@
+use shared::{window, BeamformingConfig};
+
+/// Receive apodization applied across the aperture during summation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Apodization {
+    #[default]
+    Rectangular,
+    Hann,
+    Hamming,
+    /// Flat top with cosine tapers; `alpha` is the tapered fraction of the
+    /// aperture, from 0 (rectangular) to 1 (Hann).
+    Tukey { alpha: f32 },
+    /// One weight per element, in channel order.
+    Custom(Vec<f32>),
+}
+
+impl Apodization {
+    /// Writes the window selection into `config` and returns the weights to
+    /// upload to the apodization buffer (empty unless custom).
+    pub(crate) fn apply(self, config: &mut BeamformingConfig) -> Vec<f32> {
+        let (kind, weights) = match self {
+            Self::Rectangular => (window::RECTANGULAR, Vec::new()),
+            Self::Hann => (window::HANN, Vec::new()),
+            Self::Hamming => (window::HAMMING, Vec::new()),
+            Self::Tukey { alpha } => {
+                config.tukey_alpha = alpha;
+                (window::TUKEY, Vec::new())
+            }
+            Self::Custom(weights) => (window::CUSTOM, weights),
+        };
+        config.apodization_window = kind;
+        weights
+    }
+}
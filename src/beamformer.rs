@@ -0,0 +1,162 @@
+use crate::apodization::Apodization;
+use crate::cpu::CpuBeamformer;
+use crate::error::{BeamformError, Result};
+use crate::frame::{Frame, IqFrame};
+use crate::gpu::GpuBeamformer;
+use crate::spectral_doppler::SampleVolume;
+use crate::time_gain::Tgc;
+use shared::{BeamformingConfig, TransmitEvent};
+
+/// Where a [`Beamformer`] runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Use the GPU, and fail if no adapter is available.
+    Gpu,
+    /// Use the CPU reference implementation.
+    Cpu,
+    /// Use the GPU if an adapter is available, otherwise fall back to the CPU.
+    #[default]
+    Auto,
+}
+
+/// Delay-and-sum beamformer on a backend chosen at runtime.
+pub enum Beamformer {
+    Gpu(Box<GpuBeamformer>),
+    Cpu(Box<CpuBeamformer>),
+}
+
+impl Beamformer {
+    pub async fn new(config: BeamformingConfig, backend: Backend) -> Result<Self> {
+        match backend {
+            Backend::Gpu => Ok(Self::Gpu(Box::new(GpuBeamformer::new(config).await?))),
+            Backend::Cpu => Ok(Self::Cpu(Box::new(CpuBeamformer::new(config)?))),
+            Backend::Auto => match GpuBeamformer::new(config).await {
+                Ok(gpu) => Ok(Self::Gpu(Box::new(gpu))),
+                Err(BeamformError::NoAdapter) => Ok(Self::Cpu(Box::new(CpuBeamformer::new(config)?))),
+                Err(err) => Err(err),
+            },
+        }
+    }
+
+    /// Human-readable name of the device doing the work.
+    pub fn device_name(&self) -> String {
+        match self {
+            Self::Gpu(gpu) => gpu.adapter_info().name.clone(),
+            Self::Cpu(_) => "CPU reference".to_string(),
+        }
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        match self {
+            Self::Gpu(gpu) => gpu.config(),
+            Self::Cpu(cpu) => cpu.config(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: BeamformingConfig) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_config(config),
+            Self::Cpu(cpu) => cpu.set_config(config),
+        }
+    }
+
+    pub fn set_apodization(&mut self, apodization: Apodization) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_apodization(apodization),
+            Self::Cpu(cpu) => cpu.set_apodization(apodization),
+        }
+    }
+
+    /// Sets the transmit events of a frame and `config.num_transmits` to match.
+    pub fn set_transmits(&mut self, transmits: &[TransmitEvent]) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_transmits(transmits),
+            Self::Cpu(cpu) => cpu.set_transmits(transmits),
+        }
+    }
+
+    /// Sets the FIR filter applied along the samples of every trace before
+    /// beamforming, and `config.num_filter_taps` to match. An empty slice
+    /// disables it. See [`filters`](crate::filters) for bandpass and matched
+    /// filters.
+    pub fn set_filter(&mut self, taps: &[f32]) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_filter(taps),
+            Self::Cpu(cpu) => cpu.set_filter(taps),
+        }
+    }
+
+    /// Demodulates RF input to IQ before beamforming: mixes it down by
+    /// `config.demodulation_frequency`, low-pass filters it with `taps` and
+    /// keeps every `decimation`-th sample. A decimation of 0 and no taps
+    /// disables it. Frames then come from [`process_iq`](Self::process_iq).
+    pub fn set_demodulation(&mut self, decimation: u32, taps: &[f32]) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_demodulation(decimation, taps),
+            Self::Cpu(cpu) => cpu.set_demodulation(decimation, taps),
+        }
+    }
+
+    /// Sets the time-gain compensation of the channel data, applied after
+    /// filtering and demodulation: an attenuation coefficient, or a gain
+    /// curve over depth.
+    pub fn set_tgc(&mut self, tgc: Tgc) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_tgc(tgc),
+            Self::Cpu(cpu) => cpu.set_tgc(tgc),
+        }
+    }
+
+    /// Beamforms onto arbitrary pixel positions `(x, z)`, laid out `width`
+    /// per row, instead of a computed grid, e.g. a few points of a region of
+    /// interest. Frames then have `width` columns.
+    pub fn set_pixels(&mut self, width: u32, positions: &[(f32, f32)]) -> Result<()> {
+        match self {
+            Self::Gpu(gpu) => gpu.set_pixels(width, positions),
+            Self::Cpu(cpu) => cpu.set_pixels(width, positions),
+        }
+    }
+
+    /// Beamforms only the points of a pulsed-wave Doppler gate, one row of
+    /// [`SampleVolume::positions`]. Each IQ frame then reduces to one
+    /// slow-time sample with [`SampleVolume::slow_time_sample`].
+    pub fn set_sample_volume(&mut self, volume: &SampleVolume) -> Result<()> {
+        let positions = volume.positions(self.config());
+        self.set_pixels(positions.len() as u32, &positions)
+    }
+
+    /// Beamforms one frame of RF data laid out as `[transmit][channel][sample]`.
+    pub fn process(&mut self, rf: &[f32]) -> Result<Frame> {
+        match self {
+            Self::Gpu(gpu) => gpu.process(rf),
+            Self::Cpu(cpu) => cpu.process(rf),
+        }
+    }
+    /// Beamforms one frame of IQ data in the layout given by
+    /// `config.input_format`, or of RF data demodulated with
+    /// [`set_demodulation`](Self::set_demodulation).
+    pub fn process_iq(&mut self, iq: &[f32]) -> Result<IqFrame> {
+        match self {
+            Self::Gpu(gpu) => gpu.process_iq(iq),
+            Self::Cpu(cpu) => cpu.process_iq(iq),
+        }
+    }
+
+    /// Beamforms one frame of RF or IQ data and turns it into a B-mode image:
+    /// envelope, log compressed with `config.dynamic_range` and `config.gain`,
+    /// normalized to `[0, 1]`.
+    pub fn process_bmode(&mut self, input: &[f32]) -> Result<Frame> {
+        match self {
+            Self::Gpu(gpu) => gpu.process_bmode(input),
+            Self::Cpu(cpu) => cpu.process_bmode(input),
+        }
+    }
+
+    /// [`process_bmode`](Self::process_bmode) with 8-bit grey levels.
+    pub fn process_bmode_u8(&mut self, input: &[f32]) -> Result<Frame<u8>> {
+        match self {
+            Self::Gpu(gpu) => gpu.process_bmode_u8(input),
+            Self::Cpu(cpu) => cpu.process_bmode_u8(input),
+        }
+    }
+}
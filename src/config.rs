@@ -0,0 +1,436 @@
+use crate::error::{BeamformError, Result};
+use shader::glam::Vec2;
+use shared::{grid_geometry, input_format, interpolation, tgc, transmit_model, window, BeamformingConfig, Raster, TransmitEvent};
+
+/// Everything uploaded next to the config whose size the config describes.
+#[derive(Clone, Debug)]
+pub(crate) struct Tables {
+    /// Custom apodization weights, empty unless `window::CUSTOM` is selected.
+    pub apodization: Vec<f32>,
+    /// Transmit events of a frame, `config.num_transmits` of them.
+    pub transmits: Vec<TransmitEvent>,
+    /// FIR filter taps, `config.num_filter_taps` of them.
+    pub filter: Vec<f32>,
+    /// Low-pass taps of the demodulation stage, `config.num_demodulation_taps`
+    /// of them.
+    pub demodulation: Vec<f32>,
+    /// `(depth, gain)` points of the TGC curve, `config.num_tgc_points` of
+    /// them.
+    pub tgc: Vec<Vec2>,
+    /// Pixel positions, one per pixel of the grid unless
+    /// `grid_geometry::POINTS` is selected.
+    pub pixels: Vec<Vec2>,
+}
+
+impl Default for Tables {
+    /// A single 0° plane wave, without custom apodization, filters, TGC
+    /// curve or pixel positions.
+    fn default() -> Self {
+        Self {
+            apodization: Vec::new(),
+            transmits: vec![TransmitEvent::default()],
+            filter: Vec::new(),
+            demodulation: Vec::new(),
+            tgc: Vec::new(),
+            pixels: Vec::new(),
+        }
+    }
+}
+
+/// Rejects configs the kernels cannot run, before they reach the device,
+/// along with tables that do not match them.
+pub(crate) fn validate(config: &BeamformingConfig, tables: &Tables) -> Result<()> {
+    let Tables { apodization, transmits, filter, demodulation, tgc: curve, pixels } = tables;
+    if config.num_channels == 0 || config.num_samples == 0 {
+        return invalid(format!(
+            "channel data needs at least one channel and one sample, got {} channels of {} samples",
+            config.num_channels, config.num_samples,
+        ));
+    }
+    if !(config.speed_of_sound > 0.0 && config.speed_of_sound.is_finite()) {
+        return invalid(format!("speed of sound must be positive and finite, got {} m/s", config.speed_of_sound));
+    }
+    if !(config.sampling_frequency > 0.0 && config.sampling_frequency.is_finite()) {
+        return invalid(format!("sampling frequency must be positive and finite, got {} Hz", config.sampling_frequency));
+    }
+    if config.apodization_window == window::CUSTOM && apodization.len() != config.num_channels as usize {
+        return invalid(format!(
+            "custom apodization has {} weights for {} channels",
+            apodization.len(),
+            config.num_channels,
+        ));
+    }
+    if config.apodization_window == window::TUKEY && !(0.0..=1.0).contains(&config.tukey_alpha) {
+        return invalid(format!("Tukey alpha must be between 0 and 1, got {}", config.tukey_alpha));
+    }
+    if config.apodization_window > window::CUSTOM {
+        return invalid(format!("unknown apodization window {}", config.apodization_window));
+    }
+    if !(config.f_number >= 0.0 && config.f_number.is_finite()) {
+        return invalid(format!("F-number must be finite and non-negative, got {}", config.f_number));
+    }
+    if config.interpolation > interpolation::SINC {
+        return invalid(format!("unknown interpolation mode {}", config.interpolation));
+    }
+    if config.input_format > input_format::IQ_PLANAR {
+        return invalid(format!("unknown input format {}", config.input_format));
+    }
+    validate_grid(config)?;
+    if config.grid_geometry == grid_geometry::POINTS {
+        let expected = shader::pixel_count(config);
+        if pixels.len() != expected {
+            return invalid(format!(
+                "{}x{} grid needs {expected} pixel positions but {} are set; use set_pixels to change them",
+                config.grid_width,
+                config.grid_depth,
+                pixels.len(),
+            ));
+        }
+        if let Some(pixel) = pixels.iter().find(|pixel| !pixel.is_finite()) {
+            return invalid(format!("pixel positions must be finite, got ({}, {})", pixel.x, pixel.y));
+        }
+    }
+    if !(config.dynamic_range > 0.0 && config.dynamic_range.is_finite()) {
+        return invalid(format!("dynamic range must be positive and finite, got {} dB", config.dynamic_range));
+    }
+    if !config.gain.is_finite() {
+        return invalid(format!("gain must be finite, got {} dB", config.gain));
+    }
+    if config.mvdr_subarray > config.num_channels {
+        return invalid(format!(
+            "minimum-variance subarrays of {} elements exceed the {} channels",
+            config.mvdr_subarray, config.num_channels,
+        ));
+    }
+    if config.mvdr_subarray > 0 && !(config.mvdr_loading > 0.0 && config.mvdr_loading.is_finite()) {
+        return invalid(format!("diagonal loading must be positive and finite, got {}", config.mvdr_loading));
+    }
+    if transmits.is_empty() {
+        return invalid("a frame needs at least one transmit event".to_string());
+    }
+    if transmits.len() != config.num_transmits as usize {
+        return invalid(format!(
+            "config has {} transmits but {} transmit events are set; use set_transmits to change them",
+            config.num_transmits,
+            transmits.len(),
+        ));
+    }
+    for transmit in transmits {
+        match transmit.model {
+            transmit_model::PLANE_WAVE => {
+                if transmit.angle.is_nan() || transmit.angle.abs() >= std::f32::consts::FRAC_PI_2 {
+                    return invalid(format!("steering angle must be within +-90 degrees, got {} rad", transmit.angle));
+                }
+            }
+            transmit_model::DIVERGING => {
+                if !transmit.source_z.is_finite() || transmit.source_z >= 0.0 {
+                    return invalid(format!("diverging virtual source must be behind the array, got z = {}", transmit.source_z));
+                }
+            }
+            transmit_model::FOCUSED => {
+                if !transmit.source_z.is_finite() || transmit.source_z <= 0.0 {
+                    return invalid(format!("focus must be in front of the array, got z = {}", transmit.source_z));
+                }
+            }
+            transmit_model::ELEMENT => {
+                if !(transmit.source_x.is_finite() && transmit.source_z.is_finite()) {
+                    return invalid(format!("element position must be finite, got ({}, {})", transmit.source_x, transmit.source_z));
+                }
+            }
+            model => return invalid(format!("unknown transmit model {model}")),
+        }
+    }
+    if filter.len() != config.num_filter_taps as usize {
+        return invalid(format!(
+            "config has {} filter taps but {} are set; use set_filter to change them",
+            config.num_filter_taps,
+            filter.len(),
+        ));
+    }
+    if let Some(tap) = filter.iter().find(|tap| !tap.is_finite()) {
+        return invalid(format!("filter taps must be finite, got {tap}"));
+    }
+    if demodulation.len() != config.num_demodulation_taps as usize {
+        return invalid(format!(
+            "config has {} demodulation taps but {} are set; use set_demodulation to change them",
+            config.num_demodulation_taps,
+            demodulation.len(),
+        ));
+    }
+    validate_tgc(config, curve)?;
+    if config.decimation == 0 {
+        if !demodulation.is_empty() {
+            return invalid("demodulation taps are set but decimation is 0".to_string());
+        }
+        return Ok(());
+    }
+    if is_iq(config) {
+        return invalid("demodulation needs the RF input format".to_string());
+    }
+    if !(config.demodulation_frequency > 0.0 && config.demodulation_frequency.is_finite()) {
+        return invalid(format!(
+            "demodulation frequency must be positive and finite, got {} Hz",
+            config.demodulation_frequency,
+        ));
+    }
+    if demodulation.is_empty() {
+        return invalid("demodulation needs at least one low-pass tap".to_string());
+    }
+    if let Some(tap) = demodulation.iter().find(|tap| !tap.is_finite()) {
+        return invalid(format!("demodulation taps must be finite, got {tap}"));
+    }
+    Ok(())
+}
+
+fn validate_tgc(config: &BeamformingConfig, curve: &[Vec2]) -> Result<()> {
+    if curve.len() != config.num_tgc_points as usize {
+        return invalid(format!(
+            "config has {} TGC points but {} are set; use set_tgc to change them",
+            config.num_tgc_points,
+            curve.len(),
+        ));
+    }
+    match config.tgc {
+        tgc::OFF => Ok(()),
+        tgc::ATTENUATION => {
+            if !(config.tgc_attenuation >= 0.0 && config.tgc_attenuation.is_finite()) {
+                return invalid(format!(
+                    "attenuation must be finite and non-negative, got {} dB/cm/MHz",
+                    config.tgc_attenuation,
+                ));
+            }
+            if !(config.tgc_frequency > 0.0 && config.tgc_frequency.is_finite()) {
+                return invalid(format!("TGC frequency must be positive and finite, got {} Hz", config.tgc_frequency));
+            }
+            Ok(())
+        }
+        tgc::CURVE => {
+            if curve.is_empty() {
+                return invalid("a TGC curve needs at least one point".to_string());
+            }
+            if let Some(point) = curve.iter().find(|point| !point.is_finite()) {
+                return invalid(format!("TGC points must be finite, got ({}, {})", point.x, point.y));
+            }
+            if curve.windows(2).any(|pair| pair[1].x < pair[0].x) {
+                return invalid("TGC points must be sorted by depth".to_string());
+            }
+            Ok(())
+        }
+        mode => invalid(format!("unknown TGC mode {mode}")),
+    }
+}
+
+fn validate_grid(config: &BeamformingConfig) -> Result<()> {
+    if config.grid_geometry > grid_geometry::POINTS {
+        return invalid(format!("unknown grid geometry {}", config.grid_geometry));
+    }
+    // Pixels are numbered with 32-bit indices on the device
+    if config.grid_width.checked_mul(config.grid_depth).is_none() {
+        return invalid(format!("{}x{} grid has too many pixels", config.grid_width, config.grid_depth));
+    }
+    if config.grid_geometry == grid_geometry::POLAR {
+        let last_angle = config.grid_origin_x + config.grid_width.saturating_sub(1) as f32 * config.grid_spacing_x;
+        let max_angle = std::f32::consts::FRAC_PI_2;
+        if !(config.grid_origin_x.abs() < max_angle && last_angle.abs() < max_angle) {
+            return invalid(format!(
+                "polar grid angles must be within +-90 degrees, got {} to {last_angle} rad",
+                config.grid_origin_x,
+            ));
+        }
+        if config.grid_origin_z.is_nan() || config.grid_origin_z < 0.0 {
+            return invalid(format!("polar grid radii must be non-negative, got {}", config.grid_origin_z));
+        }
+        if !config.apex_z.is_finite() {
+            return invalid(format!("apex depth must be finite, got {}", config.apex_z));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects rasters that cannot be scan-converted from the grid of `config`.
+pub(crate) fn validate_raster(config: &BeamformingConfig, raster: &Raster) -> Result<()> {
+    validate_grid(config)?;
+    if config.grid_geometry == grid_geometry::POINTS {
+        return invalid("scan conversion needs a Cartesian or polar grid, not pixel positions".to_string());
+    }
+    if config.grid_width == 0 || config.grid_depth == 0 {
+        return invalid("scan conversion needs a non-empty grid".to_string());
+    }
+    let spacings = [config.grid_spacing_x, config.grid_spacing_z];
+    if spacings.iter().any(|spacing| !(spacing.is_finite() && *spacing != 0.0)) {
+        return invalid(format!("grid spacings must be finite and non-zero, got {spacings:?}"));
+    }
+    if raster.width == 0 || raster.depth == 0 {
+        return invalid(format!("raster must have at least one pixel, got {}x{}", raster.width, raster.depth));
+    }
+    if raster.width.checked_mul(raster.depth).is_none() {
+        return invalid(format!("{}x{} raster has too many pixels", raster.width, raster.depth));
+    }
+    if !(raster.spacing_x > 0.0 && raster.spacing_x.is_finite() && raster.spacing_z > 0.0 && raster.spacing_z.is_finite()) {
+        return invalid(format!(
+            "raster spacings must be positive and finite, got ({}, {})",
+            raster.spacing_x, raster.spacing_z,
+        ));
+    }
+    if !(raster.origin_x.is_finite() && raster.origin_z.is_finite()) {
+        return invalid(format!("raster origin must be finite, got ({}, {})", raster.origin_x, raster.origin_z));
+    }
+    Ok(())
+}
+
+/// Rejects configs whose ensembles of frames cannot be processed.
+pub(crate) fn validate_ensemble(config: &BeamformingConfig) -> Result<()> {
+    if shader::pixel_count(config) == 0 {
+        return invalid("Doppler processing needs a non-empty grid".to_string());
+    }
+    if config.ensemble_length < 2 {
+        return invalid(format!("a Doppler ensemble needs at least 2 frames, got {}", config.ensemble_length));
+    }
+    Ok(())
+}
+
+/// Rejects configs whose Doppler phases cannot be converted to velocities.
+pub(crate) fn validate_velocity_scale(config: &BeamformingConfig) -> Result<()> {
+    if !(config.pulse_repetition_frequency > 0.0 && config.pulse_repetition_frequency.is_finite()) {
+        return invalid(format!(
+            "pulse repetition frequency must be positive and finite, got {} Hz",
+            config.pulse_repetition_frequency,
+        ));
+    }
+    if !(config.center_frequency > 0.0 && config.center_frequency.is_finite()) {
+        return invalid(format!("centre frequency must be positive and finite, got {} Hz", config.center_frequency));
+    }
+    Ok(())
+}
+
+/// Rejects configs whose Doppler ensembles cannot be processed.
+pub(crate) fn validate_doppler(config: &BeamformingConfig) -> Result<()> {
+    validate_ensemble(config)?;
+    validate_velocity_scale(config)?;
+    let order = config.clutter_filter_order;
+    if order > shader::doppler::MAX_CLUTTER_FILTER_ORDER {
+        return invalid(format!(
+            "clutter filter order must be at most {}, got {order}",
+            shader::doppler::MAX_CLUTTER_FILTER_ORDER,
+        ));
+    }
+    if order >= config.ensemble_length {
+        return invalid(format!(
+            "a clutter filter of order {order} removes all of an ensemble of {} frames",
+            config.ensemble_length,
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the input is IQ.
+pub(crate) fn is_iq(config: &BeamformingConfig) -> bool {
+    config.input_format != input_format::RF
+}
+
+/// Whether the beamformed frame is complex: IQ input, or RF input
+/// demodulated on the device.
+pub(crate) fn is_complex(config: &BeamformingConfig) -> bool {
+    is_iq(config) || config.decimation > 0
+}
+
+/// Number of `f32` values of the demodulated IQ data, or 0 without
+/// demodulation.
+pub(crate) fn demodulated_len(config: &BeamformingConfig) -> usize {
+    if config.decimation == 0 {
+        return 0;
+    }
+    shader::demodulate::demodulate_threads(config) * 2
+}
+
+/// Number of `f32` values in one frame of channel data, over all transmits.
+pub(crate) fn input_len(config: &BeamformingConfig) -> usize {
+    let components = if is_iq(config) { 2 } else { 1 };
+    config.num_transmits as usize * config.num_channels as usize * config.num_samples as usize * components
+}
+
+/// Rejects configs whose buffers or dispatch exceed the limits of the device,
+/// which large synthetic-aperture frames easily do.
+pub(crate) fn check_limits(config: &BeamformingConfig, limits: &wgpu::Limits) -> Result<()> {
+    let max_binding = max_binding(limits);
+    let buffers = [("input", input_len(config)), ("demodulated", demodulated_len(config)), ("output", output_len(config))];
+    for (name, len) in buffers {
+        let size = len as u64 * 4;
+        if size > max_binding {
+            return invalid(format!("{name} frame of {size} bytes exceeds the device limit of {max_binding} bytes"));
+        }
+    }
+    let size = pixel_positions_len(config) as u64 * 4;
+    if size > max_binding {
+        return invalid(format!("pixel positions of {size} bytes exceed the device limit of {max_binding} bytes"));
+    }
+    if config.mvdr_subarray > 0 && mvdr_slots(config, limits) == 0 {
+        let size = shader::mvdr::mvdr_scratch_len(config) as u64 * 8 * shader::WORKGROUP_SIZE as u64;
+        return invalid(format!(
+            "minimum-variance scratch of {size} bytes per workgroup exceeds the device limit of {max_binding} bytes",
+        ));
+    }
+    Ok(())
+}
+
+/// Largest storage buffer the device binds.
+pub(crate) fn max_binding(limits: &wgpu::Limits) -> u64 {
+    (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size)
+}
+
+/// Scratch slots of the minimum-variance stage, one per thread it runs: a
+/// workgroup's worth per 64 pixels, as far as they fit in a storage buffer
+/// and a 1D dispatch. 0 when it is disabled or not even one workgroup fits.
+pub(crate) fn mvdr_slots(config: &BeamformingConfig, limits: &wgpu::Limits) -> usize {
+    if config.mvdr_subarray == 0 {
+        return 0;
+    }
+    let size = shader::mvdr::mvdr_scratch_len(config) as u64 * 8;
+    let groups = (shader::pixel_count(config).max(1).div_ceil(shader::WORKGROUP_SIZE) as u64)
+        .min(limits.max_compute_workgroups_per_dimension as u64)
+        .min(max_binding(limits) / (size * shader::WORKGROUP_SIZE as u64));
+    groups as usize * shader::WORKGROUP_SIZE
+}
+
+/// Number of `f32` values of the pixel positions, or 0 unless
+/// `grid_geometry::POINTS` is selected.
+pub(crate) fn pixel_positions_len(config: &BeamformingConfig) -> usize {
+    if config.grid_geometry != grid_geometry::POINTS {
+        return 0;
+    }
+    shader::pixel_count(config) * 2
+}
+
+/// Number of `f32` values in one beamformed frame.
+pub(crate) fn output_len(config: &BeamformingConfig) -> usize {
+    let components = if is_complex(config) { 2 } else { 1 };
+    config.grid_width as usize * config.grid_depth as usize * components
+}
+
+/// Checks that the beamformed frame is real when `complex` is false, and
+/// complex otherwise.
+pub(crate) fn check_format(config: &BeamformingConfig, complex: bool) -> Result<()> {
+    if complex != is_complex(config) {
+        return invalid(if complex {
+            "process_iq needs an IQ input format or demodulation; use process for RF data".to_string()
+        } else {
+            "process needs RF input without demodulation; use process_iq for IQ or demodulated data".to_string()
+        });
+    }
+    Ok(())
+}
+
+/// Checks that `input` matches the format and size the config describes.
+/// `complex` says whether the caller expects complex output.
+pub(crate) fn check_input(config: &BeamformingConfig, input: &[f32], complex: bool) -> Result<()> {
+    check_format(config, complex)?;
+    let expected = input_len(config);
+    if input.len() != expected {
+        return Err(BeamformError::InvalidInput { expected, actual: input.len() });
+    }
+    Ok(())
+}
+
+fn invalid(reason: String) -> Result<()> {
+    Err(BeamformError::InvalidConfig(reason))
+}
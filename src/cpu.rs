@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+use std::thread;
+
+use crate::apodization::Apodization;
+use crate::config::{self, Tables};
+use crate::error::Result;
+use crate::frame::{Complex, Frame, IqFrame};
+use crate::time_gain::Tgc;
+use shader::glam::Vec2;
+use shared::{grid_geometry, tgc, BeamformingConfig, TransmitEvent};
+
+/// CPU delay-and-sum beamformer, or minimum-variance when
+/// `config.mvdr_subarray` is set.
+///
+/// Runs the per-pixel functions of the `shader` crate as ordinary Rust, so it
+/// computes the same image as [`GpuBeamformer`](crate::GpuBeamformer) and
+/// serves as its reference. Pixels are split across all available cores.
+pub struct CpuBeamformer {
+    config: BeamformingConfig,
+    tables: Tables,
+}
+
+impl CpuBeamformer {
+    /// Validates `config` and builds a beamformer with the default tables.
+    pub fn new(config: BeamformingConfig) -> Result<Self> {
+        let tables = Tables::default();
+        config::validate(&config, &tables)?;
+        Ok(Self { config, tables })
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: BeamformingConfig) -> Result<()> {
+        self.update(config, self.tables.clone())
+    }
+
+    pub fn set_apodization(&mut self, apodization: Apodization) -> Result<()> {
+        let mut config = self.config;
+        let weights = apodization.apply(&mut config);
+        self.update(config, Tables { apodization: weights, ..self.tables.clone() })
+    }
+
+    /// Sets the transmit events of a frame and `config.num_transmits` to match.
+    pub fn set_transmits(&mut self, transmits: &[TransmitEvent]) -> Result<()> {
+        let config = BeamformingConfig { num_transmits: transmits.len() as u32, ..self.config };
+        self.update(config, Tables { transmits: transmits.to_vec(), ..self.tables.clone() })
+    }
+
+    /// Sets the FIR filter applied along the samples of every trace before
+    /// beamforming, and `config.num_filter_taps` to match. An empty slice
+    /// disables it.
+    pub fn set_filter(&mut self, taps: &[f32]) -> Result<()> {
+        let config = BeamformingConfig { num_filter_taps: taps.len() as u32, ..self.config };
+        self.update(config, Tables { filter: taps.to_vec(), ..self.tables.clone() })
+    }
+
+    /// Demodulates RF input to IQ before beamforming: mixes it down by
+    /// `config.demodulation_frequency`, low-pass filters it with `taps` and
+    /// keeps every `decimation`-th sample. A decimation of 0 and no taps
+    /// disables it.
+    pub fn set_demodulation(&mut self, decimation: u32, taps: &[f32]) -> Result<()> {
+        let config = BeamformingConfig { decimation, num_demodulation_taps: taps.len() as u32, ..self.config };
+        self.update(config, Tables { demodulation: taps.to_vec(), ..self.tables.clone() })
+    }
+
+    /// Sets the time-gain compensation of the channel data, applied after
+    /// filtering and demodulation.
+    pub fn set_tgc(&mut self, tgc: Tgc) -> Result<()> {
+        let mut config = self.config;
+        let points = tgc.apply(&mut config);
+        self.update(config, Tables { tgc: points, ..self.tables.clone() })
+    }
+
+    /// Beamforms onto arbitrary pixel positions `(x, z)`, laid out `width`
+    /// per row, instead of a computed grid, and sets `config.grid_geometry`,
+    /// `grid_width` and `grid_depth` to match.
+    pub fn set_pixels(&mut self, width: u32, positions: &[(f32, f32)]) -> Result<()> {
+        let depth = (positions.len() as u32).checked_div(width).unwrap_or(0);
+        let config =
+            BeamformingConfig { grid_geometry: grid_geometry::POINTS, grid_width: width, grid_depth: depth, ..self.config };
+        let pixels = positions.iter().map(|&(x, z)| Vec2::new(x, z)).collect();
+        self.update(config, Tables { pixels, ..self.tables.clone() })
+    }
+
+    fn update(&mut self, config: BeamformingConfig, tables: Tables) -> Result<()> {
+        config::validate(&config, &tables)?;
+        self.config = config;
+        self.tables = tables;
+        Ok(())
+    }
+
+    /// Beamforms one frame of RF data laid out as `[transmit][channel][sample]`.
+    pub fn process(&mut self, rf: &[f32]) -> Result<Frame> {
+        config::check_input(&self.config, rf, false)?;
+        self.beamform(rf, |sum| sum.x)
+    }
+
+    /// Beamforms one frame of IQ data in the layout given by
+    /// `config.input_format`, or of RF data demodulated with
+    /// [`set_demodulation`](Self::set_demodulation).
+    pub fn process_iq(&mut self, iq: &[f32]) -> Result<IqFrame> {
+        config::check_input(&self.config, iq, true)?;
+        self.beamform(iq, |sum| Complex::new(sum.x, sum.y))
+    }
+
+    /// Beamforms one frame of RF or IQ data and turns it into a B-mode image:
+    /// envelope, log compressed with `config.dynamic_range` and `config.gain`,
+    /// normalized to `[0, 1]`.
+    pub fn process_bmode(&mut self, input: &[f32]) -> Result<Frame> {
+        self.bmode(input, |value| value)
+    }
+
+    /// [`process_bmode`](Self::process_bmode) with 8-bit grey levels.
+    pub fn process_bmode_u8(&mut self, input: &[f32]) -> Result<Frame<u8>> {
+        self.bmode(input, |value| shader::image::quantize(value) as u8)
+    }
+
+    /// Runs the image stage of the shader crate on the beamformed frame, laid
+    /// out like the GPU output buffer, and converts each normalized pixel with
+    /// `pixel`.
+    fn bmode<T: Copy>(&self, input: &[f32], pixel: impl Fn(f32) -> T) -> Result<Frame<T>> {
+        let complex = config::is_complex(&self.config);
+        config::check_input(&self.config, input, complex)?;
+        let beamformed: Vec<f32> = if complex {
+            self.beamform(input, |sum| [sum.x, sum.y])?.data.concat()
+        } else {
+            self.beamform(input, |sum| sum.x)?.data
+        };
+
+        let config = &shader::demodulate::demodulated_config(&self.config);
+        let width = config.grid_width as usize;
+        let depth = config.grid_depth as usize;
+        let envelope: Vec<f32> = (0..width * depth)
+            .map(|i| shader::image::envelope(&beamformed, config, i % width, i / width))
+            .collect();
+        let peak = envelope.iter().fold(0.0f32, |m, &v| m.max(v));
+        let data = envelope.iter().map(|&v| pixel(shader::image::log_compress(config, v, peak))).collect();
+        Ok(Frame::new(width, depth, data))
+    }
+
+    /// Runs the stages before beamforming on the channel data: the FIR
+    /// filter, demodulation, then TGC.
+    fn preprocess<'a>(&self, input: &'a [f32]) -> Cow<'a, [f32]> {
+        let config = &self.config;
+        let mut data = Cow::Borrowed(input);
+        if config.num_filter_taps > 0 {
+            let taps = &self.tables.filter;
+            data = (0..data.len()).map(|i| shader::filter::filter_sample(&data, taps, config, i)).collect();
+        }
+        if config.decimation > 0 {
+            let taps = &self.tables.demodulation;
+            data = (0..shader::demodulate::demodulate_threads(config))
+                .flat_map(|i| shader::demodulate::demodulate_sample(&data, taps, config, i).to_array())
+                .collect();
+        }
+        if config.tgc != tgc::OFF {
+            // Gains follow the sample times of the data the beamformer sees
+            let config = &shader::demodulate::demodulated_config(config);
+            let curve = &self.tables.tgc;
+            data = (0..data.len()).map(|i| shader::time_gain::compensate_sample(&data, curve, config, i)).collect();
+        }
+        data
+    }
+
+    /// Beamforms every pixel and converts its `(re, im)` sum with `pixel`.
+    fn beamform<T>(&self, input: &[f32], pixel: impl Fn(Vec2) -> T + Sync) -> Result<Frame<T>>
+    where
+        T: Copy + Default + Send,
+    {
+        // The beamformer sees demodulated data as IQ at the decimated rate
+        let config = &shader::demodulate::demodulated_config(&self.config);
+        let apodization = &self.tables.apodization;
+        let transmits = &self.tables.transmits;
+        let pixels = &self.tables.pixels;
+
+        let width = config.grid_width as usize;
+        let depth = config.grid_depth as usize;
+        let mut data = vec![T::default(); width * depth];
+        if data.is_empty() {
+            return Ok(Frame::new(width, depth, data));
+        }
+        let input = &*self.preprocess(input);
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let pixels_per_chunk = data.len().div_ceil(threads);
+        let pixel = &pixel;
+        thread::scope(|scope| {
+            for (chunk, values) in data.chunks_mut(pixels_per_chunk).enumerate() {
+                scope.spawn(move || {
+                    // Minimum-variance beamforming works in one scratch slot per thread
+                    let mut scratch = vec![Vec2::ZERO; shader::mvdr::mvdr_scratch_len(config)];
+                    for (i, value) in values.iter_mut().enumerate() {
+                        let position = shader::pixel_at(config, pixels, chunk * pixels_per_chunk + i);
+                        let (x, z) = (position.x, position.y);
+                        *value = pixel(if config.mvdr_subarray > 0 {
+                            shader::mvdr::mvdr_pixel(input, transmits, config, x, z, &mut scratch, 0)
+                        } else {
+                            shader::beamform_pixel(input, apodization, transmits, config, x, z)
+                        });
+                    }
+                });
+            }
+        });
+
+        Ok(Frame::new(width, depth, data))
+    }
+}
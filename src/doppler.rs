@@ -0,0 +1,260 @@
+//! Colour-flow and power Doppler on ensembles of beamformed IQ frames, on the
+//! GPU with a CPU reference running the same kernel code.
+//!
+//! An ensemble is `config.ensemble_length` frames of the same grid, acquired
+//! at `config.pulse_repetition_frequency`, e.g. from
+//! [`process_iq`](crate::Beamformer::process_iq) on successive acquisitions.
+//! Both modes see it through the wall filter of
+//! `config.clutter_filter_order`.
+
+use crate::config;
+use crate::error::{BeamformError, Result};
+use crate::frame::{Complex, Frame, IqFrame};
+use crate::gpu::{self, GpuContext};
+use shader::glam::Vec2;
+use shared::BeamformingConfig;
+
+/// Per-pixel estimates of an ensemble, on the grid of its frames.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DopplerMaps {
+    /// Mean axial velocity (m/s), positive towards the probe.
+    pub velocity: Frame,
+    /// Normalized variance `1 - |R(1)| / R(0)`: 0 for uniform flow, up to 1
+    /// for noise.
+    pub variance: Frame,
+    /// Mean power of the wall-filtered ensemble `R(0)`.
+    pub power: Frame,
+}
+
+impl DopplerMaps {
+    /// Splits the velocity, variance and power planes of the Doppler stage.
+    fn from_planes(config: &BeamformingConfig, planes: &[f32]) -> Self {
+        let (width, depth) = (config.grid_width as usize, config.grid_depth as usize);
+        let mut maps = planes.chunks(width * depth).map(|plane| Frame::new(width, depth, plane.to_vec()));
+        Self { velocity: maps.next().unwrap(), variance: maps.next().unwrap(), power: maps.next().unwrap() }
+    }
+}
+
+/// Velocity beyond which the Doppler phase wraps around, `c PRF / (4 f0)`.
+pub fn nyquist_velocity(config: &BeamformingConfig) -> f32 {
+    config.speed_of_sound * config.pulse_repetition_frequency / (4.0 * config.center_frequency)
+}
+
+/// Checks that `ensemble` holds `config.ensemble_length` frames of the grid
+/// and flattens it as `[frame][pixel]`.
+pub(crate) fn flatten(config: &BeamformingConfig, ensemble: &[IqFrame]) -> Result<Vec<Complex>> {
+    let pixels = shader::pixel_count(config);
+    let expected = config.ensemble_length as usize * pixels;
+    let actual = ensemble.iter().map(|frame| frame.data.len()).sum();
+    let shapes_match = ensemble
+        .iter()
+        .all(|frame| (frame.width, frame.depth) == (config.grid_width as usize, config.grid_depth as usize));
+    if ensemble.len() != config.ensemble_length as usize || !shapes_match {
+        return Err(BeamformError::InvalidInput { expected, actual });
+    }
+    Ok(ensemble.iter().flat_map(|frame| frame.data.iter().copied()).collect())
+}
+
+/// Records the copy of IQ frame `index` of an ensemble from `frame`, a
+/// buffer on the device, into `ensemble`, laid out like [`flatten`].
+pub(crate) fn copy_frame(
+    context: &GpuContext,
+    encoder: &mut wgpu::CommandEncoder,
+    config: &BeamformingConfig,
+    ensemble: &wgpu::Buffer,
+    index: usize,
+    frame: &wgpu::Buffer,
+) -> Result<()> {
+    let frames = config.ensemble_length as usize;
+    if index >= frames {
+        return Err(BeamformError::InvalidConfig(format!("frame {index} is outside an ensemble of {frames} frames")));
+    }
+    let pixels = shader::pixel_count(config);
+    context.copy_values::<Complex>(encoder, frame, ensemble, index * pixels, pixels)
+}
+
+/// CPU reference Doppler processor, running the kernel function of the
+/// `shader` crate one pixel at a time.
+pub struct CpuDoppler {
+    config: BeamformingConfig,
+}
+
+impl CpuDoppler {
+    /// Processes ensembles of `config.ensemble_length` frames on the grid of
+    /// `config`.
+    pub fn new(config: BeamformingConfig) -> Result<Self> {
+        config::validate_doppler(&config)?;
+        Ok(Self { config })
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    /// Estimates velocity, variance and power from an ensemble of IQ frames.
+    pub fn process(&self, ensemble: &[IqFrame]) -> Result<DopplerMaps> {
+        let ensemble: Vec<Vec2> = flatten(&self.config, ensemble)?.iter().map(|c| Vec2::new(c.re, c.im)).collect();
+        let estimates: Vec<_> = (0..shader::doppler::doppler_threads(&self.config))
+            .map(|pixel| shader::doppler::kasai(&ensemble, &self.config, pixel))
+            .collect();
+        let planes: Vec<f32> = [0, 1, 2].iter().flat_map(|&i| estimates.iter().map(move |e| e[i])).collect();
+        Ok(DopplerMaps::from_planes(&self.config, &planes))
+    }
+
+    /// Power Doppler map of an ensemble of IQ frames: the root mean square
+    /// amplitude of every wall-filtered pixel, log compressed like a B-mode
+    /// image onto `[0, 1]` with `config.dynamic_range` and `config.gain`.
+    pub fn process_power(&self, ensemble: &[IqFrame]) -> Result<Frame> {
+        let ensemble: Vec<Vec2> = flatten(&self.config, ensemble)?.iter().map(|c| Vec2::new(c.re, c.im)).collect();
+        let amplitudes: Vec<f32> = (0..shader::doppler::doppler_threads(&self.config))
+            .map(|pixel| shader::doppler::power_amplitude(&ensemble, &self.config, pixel))
+            .collect();
+        let peak = amplitudes.iter().fold(0.0f32, |m, &v| m.max(v));
+        let data = amplitudes.iter().map(|&v| shader::image::log_compress(&self.config, v, peak)).collect();
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+}
+
+struct Pipelines {
+    colour: wgpu::ComputePipeline,
+    power: wgpu::ComputePipeline,
+    peak: wgpu::ComputePipeline,
+    log_compress: wgpu::ComputePipeline,
+}
+
+/// GPU Doppler processor. The pipelines, buffers and bind group are created
+/// once; each call uploads the ensemble, dispatches one thread per pixel and
+/// reads the maps back. [`encode_frame`](GpuDoppler::encode_frame) gathers
+/// the ensemble from frames already on the device instead, e.g. those of a
+/// [`GpuBeamformer`](crate::GpuBeamformer) on the same [`GpuContext`].
+pub struct GpuDoppler {
+    context: GpuContext,
+    config: BeamformingConfig,
+    pipelines: Pipelines,
+    input: wgpu::Buffer,
+    maps: wgpu::Buffer,
+    power: wgpu::Buffer,
+    staging: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    workgroups: (u32, u32),
+}
+
+impl GpuDoppler {
+    /// Requests a GPU adapter and prepares the processing of ensembles of
+    /// `config.ensemble_length` frames on the grid of `config`.
+    pub async fn new(config: BeamformingConfig) -> Result<Self> {
+        Self::with_context(&GpuContext::new().await?, config)
+    }
+
+    pub fn with_context(context: &GpuContext, config: BeamformingConfig) -> Result<Self> {
+        config::validate_doppler(&config)?;
+        let pixels = shader::pixel_count(&config);
+        let input_size = (config.ensemble_length as usize * pixels * 8) as u64;
+        let maps_size = (3 * pixels * 4) as u64;
+        context.check_binding("Doppler ensemble", input_size)?;
+        let device = context.device();
+
+        gpu::push_error_scopes(device);
+
+        let shader = gpu::shader_module(device);
+        // Laid out like the image stage, so that power Doppler hands its
+        // amplitudes to the B-mode peak and log compression kernels as
+        // envelopes in the first plane of the maps
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                gpu::storage_entry(0, true),
+                gpu::uniform_entry(1),
+                gpu::storage_entry(2, false),
+                gpu::storage_entry(3, false),
+                gpu::storage_entry(4, false),
+            ],
+        });
+        let pipelines = Pipelines {
+            colour: gpu::compute_pipeline(device, &shader, &layout, "doppler_shader"),
+            power: gpu::compute_pipeline(device, &shader, &layout, "power_doppler_shader"),
+            peak: gpu::compute_pipeline(device, &shader, &layout, "peak_shader"),
+            log_compress: gpu::compute_pipeline(device, &shader, &layout, "log_compress_shader"),
+        };
+
+        let input = context.buffer(input_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let maps = context.buffer(maps_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let peak = context.buffer(4, wgpu::BufferUsages::STORAGE);
+        let power = context.buffer((pixels * 4) as u64, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let staging = context.buffer(maps_size, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+        let config_buffer = context.uniform(&config);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: input.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: config_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: maps.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: peak.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: power.as_entire_binding() },
+            ],
+        });
+        let threads = shader::doppler::doppler_threads(&config);
+        let workgroups = gpu::workgroups(threads, device.limits().max_compute_workgroups_per_dimension);
+
+        gpu::pop_error_scopes(device)?;
+
+        Ok(Self { context: context.clone(), config, pipelines, input, maps, power, staging, bind_group, workgroups })
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    /// Estimates velocity, variance and power from an ensemble of IQ frames.
+    pub fn process(&mut self, ensemble: &[IqFrame]) -> Result<DopplerMaps> {
+        self.context.upload(&self.input, &flatten(&self.config, ensemble)?)?;
+        self.process_encoded(self.context.encoder())
+    }
+
+    /// Power Doppler map of an ensemble of IQ frames, see
+    /// [`CpuDoppler::process_power`].
+    pub fn process_power(&mut self, ensemble: &[IqFrame]) -> Result<Frame> {
+        self.context.upload(&self.input, &flatten(&self.config, ensemble)?)?;
+        self.process_power_encoded(self.context.encoder())
+    }
+
+    /// Records the copy of frame `index` of the ensemble from a buffer on the
+    /// device, one interleaved `(re, im)` pair per pixel like the
+    /// [`output_buffer`](crate::GpuBeamformer::output_buffer) of a beamformer
+    /// of IQ data on the same context. A beamformer overwrites its output
+    /// with every frame, so submit each copy before beamforming the next.
+    pub fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, index: usize, frame: &wgpu::Buffer) -> Result<()> {
+        copy_frame(&self.context, encoder, &self.config, &self.input, index, frame)
+    }
+
+    /// Submits `encoder` with the Doppler stage, for an ensemble gathered
+    /// with [`encode_frame`](Self::encode_frame), and reads the maps back.
+    pub fn process_encoded(&mut self, encoder: wgpu::CommandEncoder) -> Result<DopplerMaps> {
+        let planes = self.run(encoder, false)?;
+        Ok(DopplerMaps::from_planes(&self.config, &planes))
+    }
+
+    /// [`process_encoded`](Self::process_encoded) for power Doppler.
+    pub fn process_power_encoded(&mut self, encoder: wgpu::CommandEncoder) -> Result<Frame> {
+        let data = self.run(encoder, true)?;
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// Records the Doppler stage into `encoder`, submits it and reads back
+    /// either the three maps of colour Doppler or the log-compressed map of
+    /// power Doppler.
+    fn run(&self, mut encoder: wgpu::CommandEncoder, power: bool) -> Result<Vec<f32>> {
+        let (pipelines, groups) = (&self.pipelines, self.workgroups);
+        let (source, steps) = if power {
+            // Amplitudes, their peak, then log compression against it
+            let steps = vec![(&pipelines.power, groups), (&pipelines.peak, (1, 1)), (&pipelines.log_compress, groups)];
+            (&self.power, steps)
+        } else {
+            (&self.maps, vec![(&pipelines.colour, groups)])
+        };
+        self.context.dispatch(&mut encoder, &self.bind_group, &steps)?;
+        self.context.read_back(encoder, source, &self.staging, source.size())
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+/// Everything that can go wrong while setting up or running the beamformer.
+#[derive(Debug)]
+pub enum BeamformError {
+    /// No adapter matched the requested options.
+    NoAdapter,
+    /// The adapter refused to create a device.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The device was lost, e.g. after a driver reset. The beamformer must be
+    /// recreated.
+    DeviceLost(String),
+    /// wgpu rejected a resource or command, which points to a bug or to a
+    /// config the device cannot handle.
+    Validation(String),
+    /// The device ran out of memory while allocating a resource.
+    OutOfMemory(String),
+    /// Mapping the readback buffer failed.
+    BufferMap(wgpu::BufferAsyncError),
+    /// The input slice does not match the transmit, channel and sample counts
+    /// (and, for IQ, the two components) of the config, the length and batch
+    /// of an FFT plan, the grid of an image to scan-convert, or the frames of
+    /// a Doppler ensemble.
+    InvalidInput { expected: usize, actual: usize },
+    /// The config or one of its companion buffers is inconsistent.
+    InvalidConfig(String),
+}
+
+pub type Result<T> = std::result::Result<T, BeamformError>;
+
+impl fmt::Display for BeamformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoAdapter => write!(f, "no suitable GPU adapter found"),
+            Self::RequestDevice(err) => write!(f, "failed to create device: {err}"),
+            Self::DeviceLost(reason) => write!(f, "device lost: {reason}"),
+            Self::Validation(description) => write!(f, "validation error: {description}"),
+            Self::OutOfMemory(description) => write!(f, "out of GPU memory: {description}"),
+            Self::BufferMap(err) => write!(f, "failed to map readback buffer: {err}"),
+            Self::InvalidInput { expected, actual } => {
+                write!(f, "expected {expected} input values, got {actual}")
+            }
+            Self::InvalidConfig(reason) => write!(f, "invalid config: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for BeamformError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::RequestDevice(err) => Some(err),
+            Self::BufferMap(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for BeamformError {
+    fn from(err: wgpu::RequestDeviceError) -> Self {
+        Self::RequestDevice(err)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for BeamformError {
+    fn from(err: wgpu::BufferAsyncError) -> Self {
+        Self::BufferMap(err)
+    }
+}
+
+impl From<wgpu::Error> for BeamformError {
+    fn from(err: wgpu::Error) -> Self {
+        match err {
+            wgpu::Error::OutOfMemory { source } => Self::OutOfMemory(source.to_string()),
+            wgpu::Error::Validation { description, .. } => Self::Validation(description),
+        }
+    }
+}
@@ -0,0 +1,510 @@
+//! Batched 1D FFTs on the GPU, with a CPU reference running the same kernel
+//! code.
+//!
+//! Transforms are stored one after another, so an RF frame laid out as
+//! `[trace][sample]` is transformed along its sample axis with
+//! `batch = traces` and `len = samples`.
+
+use std::f64::consts::PI;
+
+use crate::error::{BeamformError, Result};
+use crate::frame::Complex;
+use crate::gpu::{self, GpuContext};
+use shader::glam::Vec2;
+use shared::FftParams;
+
+/// Direction of a transform. Forward transforms are unscaled and inverse
+/// ones scaled by `1 / len`, so a round trip returns the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FftDirection {
+    Forward,
+    Inverse,
+}
+
+/// Host-side plan of a batched FFT of a fixed length.
+///
+/// Power-of-two lengths run as radix-4 passes followed by at most one
+/// radix-2 pass. Other lengths use Bluestein's algorithm: a circular
+/// convolution with a chirp, computed with power-of-two transforms of at
+/// least `2 * len - 1` samples.
+#[derive(Clone, Debug)]
+pub struct FftPlan {
+    len: usize,
+    batch: usize,
+    /// Length of the radix passes: `len`, or the Bluestein convolution length.
+    padded_len: usize,
+    radices: Vec<u32>,
+    /// `exp(-2 pi i k / padded_len)`.
+    twiddles: Vec<Vec2>,
+    /// Bluestein chirp `exp(-pi i j^2 / len)`, empty for power-of-two lengths.
+    chirp: Vec<Vec2>,
+    /// Transformed conjugate chirp for forward transforms, then for inverse ones.
+    spectrum: Vec<Vec2>,
+}
+
+/// Kernel run by one step of a plan.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kernel {
+    Radix,
+    Premultiply,
+    Multiply,
+    Postmultiply,
+}
+
+/// Buffer a step reads or writes: the input, or one of two work buffers the
+/// passes ping-pong between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Slot {
+    Input,
+    Work(usize),
+}
+
+impl Slot {
+    /// The work buffer a step reading from `self` writes to.
+    fn next(self) -> Self {
+        match self {
+            Self::Work(0) => Self::Work(1),
+            _ => Self::Work(0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    kernel: Kernel,
+    params: FftParams,
+    src: Slot,
+    dst: Slot,
+}
+
+impl Step {
+    fn threads(&self) -> usize {
+        match self.kernel {
+            Kernel::Radix => shader::fft::radix_pass_threads(&self.params),
+            Kernel::Premultiply | Kernel::Multiply => shader::fft::padded_threads(&self.params),
+            Kernel::Postmultiply => shader::fft::signal_threads(&self.params),
+        }
+    }
+}
+
+impl FftPlan {
+    /// Plans `batch` transforms of `len` samples each.
+    pub fn new(len: usize, batch: usize) -> Result<Self> {
+        if len == 0 || batch == 0 {
+            return Err(BeamformError::InvalidConfig(format!("cannot plan {batch} FFTs of {len} samples")));
+        }
+        if len.is_power_of_two() {
+            return Ok(Self::radix(len, batch));
+        }
+
+        // Circular convolution of the chirped signal with the conjugate chirp,
+        // long enough that it does not wrap onto the first `len` outputs
+        let padded_len = (2 * len - 1).next_power_of_two();
+        let chirp: Vec<Vec2> = (0..len as u64)
+            .map(|j| {
+                // j^2 mod 2 len keeps the angle small and exact
+                let angle = -PI * ((j * j) % (2 * len as u64)) as f64 / len as f64;
+                Vec2::new(angle.cos() as f32, angle.sin() as f32)
+            })
+            .collect();
+        let mut conjugate_chirp = vec![Vec2::ZERO; padded_len];
+        for (j, w) in chirp.iter().enumerate() {
+            conjugate_chirp[j] = Vec2::new(w.x, -w.y);
+            conjugate_chirp[(padded_len - j) % padded_len] = Vec2::new(w.x, -w.y);
+        }
+        let chirp_for_inverse: Vec<Vec2> = conjugate_chirp.iter().map(|w| Vec2::new(w.x, -w.y)).collect();
+
+        let padded = CpuFft { plan: Self::radix(padded_len, 1) };
+        let mut spectrum = padded.run(&conjugate_chirp, FftDirection::Forward);
+        spectrum.extend(padded.run(&chirp_for_inverse, FftDirection::Forward));
+
+        Ok(Self { len, batch, chirp, spectrum, ..Self::radix(padded_len, batch) })
+    }
+
+    /// Plan of a power-of-two length, without Bluestein tables.
+    fn radix(len: usize, batch: usize) -> Self {
+        let mut radices = Vec::new();
+        let mut remaining = len;
+        while remaining >= 4 {
+            radices.push(4);
+            remaining /= 4;
+        }
+        if remaining == 2 {
+            radices.push(2);
+        }
+        let twiddles = (0..len)
+            .map(|k| {
+                let angle = -2.0 * PI * k as f64 / len as f64;
+                Vec2::new(angle.cos() as f32, angle.sin() as f32)
+            })
+            .collect();
+        Self { len, batch, padded_len: len, radices, twiddles, chirp: Vec::new(), spectrum: Vec::new() }
+    }
+
+    /// Samples per transform.
+    pub fn transform_len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of transforms.
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+
+    /// Whether the length is not a power of two, so Bluestein's algorithm is used.
+    pub fn is_bluestein(&self) -> bool {
+        !self.chirp.is_empty()
+    }
+
+    /// Radices of the passes of each power-of-two transform, in order.
+    pub fn radices(&self) -> &[u32] {
+        &self.radices
+    }
+
+    /// Steps of one transform and the slot holding its result.
+    fn steps(&self, direction: FftDirection) -> (Vec<Step>, Slot) {
+        let inverse = direction == FftDirection::Inverse;
+        let base = FftParams {
+            len: self.padded_len as u32,
+            signal_len: self.len as u32,
+            batch: self.batch as u32,
+            inverse: inverse as u32,
+            scale: 1.0,
+            ..Default::default()
+        };
+        let mut steps = Vec::new();
+        let mut current = Slot::Input;
+        let passes = |steps: &mut Vec<Step>, current: &mut Slot, inverse: bool, scale: f32| {
+            let mut span = 1;
+            for (i, &radix) in self.radices.iter().enumerate() {
+                let last = i + 1 == self.radices.len();
+                let params = FftParams {
+                    span,
+                    radix,
+                    inverse: inverse as u32,
+                    scale: if last { scale } else { 1.0 },
+                    ..base
+                };
+                steps.push(Step { kernel: Kernel::Radix, params, src: *current, dst: current.next() });
+                *current = current.next();
+                span *= radix;
+            }
+        };
+
+        if !self.is_bluestein() {
+            let scale = if inverse { 1.0 / self.len as f32 } else { 1.0 };
+            passes(&mut steps, &mut current, inverse, scale);
+            return (steps, current);
+        }
+
+        steps.push(Step { kernel: Kernel::Premultiply, params: base, src: current, dst: current.next() });
+        current = current.next();
+        passes(&mut steps, &mut current, false, 1.0);
+        // In place, so the source slot is unused
+        steps.push(Step { kernel: Kernel::Multiply, params: base, src: Slot::Input, dst: current });
+        passes(&mut steps, &mut current, true, 1.0);
+        // The inverse convolution transform is unscaled, so scale by 1 / padded_len here
+        let scale = if inverse { 1.0 / (self.padded_len * self.len) as f32 } else { 1.0 / self.padded_len as f32 };
+        let params = FftParams { scale, ..base };
+        steps.push(Step { kernel: Kernel::Postmultiply, params, src: current, dst: current.next() });
+        (steps, current.next())
+    }
+
+    fn check_input(&self, len: usize) -> Result<()> {
+        let expected = self.len * self.batch;
+        if len != expected {
+            return Err(BeamformError::InvalidInput { expected, actual: len });
+        }
+        Ok(())
+    }
+}
+
+/// CPU reference FFT, running the kernel functions of the `shader` crate one
+/// thread index at a time.
+pub struct CpuFft {
+    plan: FftPlan,
+}
+
+impl CpuFft {
+    pub fn new(len: usize, batch: usize) -> Result<Self> {
+        Ok(Self { plan: FftPlan::new(len, batch)? })
+    }
+
+    pub fn plan(&self) -> &FftPlan {
+        &self.plan
+    }
+
+    /// Transforms `batch` complex signals of `len` samples, stored one after another.
+    pub fn process(&self, data: &[Complex], direction: FftDirection) -> Result<Vec<Complex>> {
+        self.plan.check_input(data.len())?;
+        let input: Vec<Vec2> = data.iter().map(|c| Vec2::new(c.re, c.im)).collect();
+        Ok(self.run(&input, direction).into_iter().map(|v| Complex::new(v.x, v.y)).collect())
+    }
+
+    /// Transforms `batch` real signals of `len` samples, e.g. an RF frame
+    /// along its sample axis.
+    pub fn process_real(&self, data: &[f32], direction: FftDirection) -> Result<Vec<Complex>> {
+        self.process(&to_complex(data), direction)
+    }
+
+    fn run(&self, input: &[Vec2], direction: FftDirection) -> Vec<Vec2> {
+        let plan = &self.plan;
+        let work_len = plan.batch * plan.padded_len.max(plan.len);
+        let mut work = [vec![Vec2::ZERO; work_len], vec![Vec2::ZERO; work_len]];
+        let (steps, result) = plan.steps(direction);
+
+        for step in &steps {
+            let Slot::Work(dst) = step.dst else { unreachable!("steps never write the input") };
+            let (src, dst): (&[Vec2], &mut [Vec2]) = match step.src {
+                Slot::Input => (input, &mut work[dst]),
+                Slot::Work(src) => {
+                    let [first, second] = &mut work;
+                    if src == 0 { (&*first, second) } else { (&*second, first) }
+                }
+            };
+            let params = &step.params;
+            for thread in 0..step.threads() {
+                match step.kernel {
+                    Kernel::Radix => shader::fft::radix_pass(params, src, dst, &plan.twiddles, thread),
+                    Kernel::Premultiply => shader::fft::bluestein_premultiply(params, src, dst, &plan.chirp, thread),
+                    Kernel::Multiply => shader::fft::bluestein_multiply(params, dst, &plan.spectrum, thread),
+                    Kernel::Postmultiply => shader::fft::bluestein_postmultiply(params, src, dst, &plan.chirp, thread),
+                }
+            }
+        }
+
+        match result {
+            Slot::Input => input.to_vec(),
+            Slot::Work(i) => work[i][..plan.batch * plan.len].to_vec(),
+        }
+    }
+}
+
+/// GPU batched FFT. The plan, pipelines, buffers and bind groups of both
+/// directions are created once; each call uploads, dispatches every step in
+/// one compute pass and reads the result back, or with
+/// [`encode`](GpuFft::encode) transforms a buffer already on the device.
+pub struct GpuFft {
+    context: GpuContext,
+    plan: FftPlan,
+    pipelines: FftPipelines,
+    input: wgpu::Buffer,
+    work: [wgpu::Buffer; 2],
+    staging: wgpu::Buffer,
+    forward: Vec<EncodedStep>,
+    inverse: Vec<EncodedStep>,
+    /// Result slots of the forward and inverse steps.
+    results: [Slot; 2],
+}
+
+struct FftPipelines {
+    radix: wgpu::ComputePipeline,
+    premultiply: wgpu::ComputePipeline,
+    multiply: wgpu::ComputePipeline,
+    postmultiply: wgpu::ComputePipeline,
+}
+
+/// A step with its own params uniform bound, ready to dispatch.
+struct EncodedStep {
+    kernel: Kernel,
+    bind_group: wgpu::BindGroup,
+    workgroups: (u32, u32),
+}
+
+impl GpuFft {
+    /// Requests a GPU adapter and plans `batch` transforms of `len` samples on it.
+    pub async fn new(len: usize, batch: usize) -> Result<Self> {
+        Self::with_context(&GpuContext::new().await?, len, batch)
+    }
+
+    /// Plans `batch` transforms of `len` samples on the device of `context`,
+    /// e.g. to transform the channel data of a
+    /// [`GpuBeamformer`](crate::GpuBeamformer) with its `encode_fft`.
+    pub fn with_context(context: &GpuContext, len: usize, batch: usize) -> Result<Self> {
+        let plan = FftPlan::new(len, batch)?;
+        let input_size = (plan.batch * plan.len * 8) as u64;
+        let work_size = (plan.batch * plan.padded_len.max(plan.len) * 8) as u64;
+        context.check_binding("FFT work buffer", work_size)?;
+        let (device, queue) = (context.device(), context.queue());
+
+        gpu::push_error_scopes(device);
+
+        let shader = gpu::shader_module(device);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                gpu::uniform_entry(0),
+                gpu::storage_entry(1, true),
+                gpu::storage_entry(2, false),
+                gpu::storage_entry(3, true),
+                gpu::storage_entry(4, true),
+                gpu::storage_entry(5, true),
+            ],
+        });
+        let pipelines = FftPipelines {
+            radix: gpu::compute_pipeline(device, &shader, &layout, "fft_radix_shader"),
+            premultiply: gpu::compute_pipeline(device, &shader, &layout, "fft_premultiply_shader"),
+            multiply: gpu::compute_pipeline(device, &shader, &layout, "fft_multiply_shader"),
+            postmultiply: gpu::compute_pipeline(device, &shader, &layout, "fft_postmultiply_shader"),
+        };
+
+        let storage = |size: u64, usage: wgpu::BufferUsages| context.buffer(size, wgpu::BufferUsages::STORAGE | usage);
+        let input = storage(input_size, wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST);
+        let work = [
+            storage(work_size, wgpu::BufferUsages::COPY_SRC),
+            storage(work_size, wgpu::BufferUsages::COPY_SRC),
+        ];
+        // Tables are bound to every step, so even unused ones need a buffer
+        let table = |values: &[Vec2]| {
+            let bytes: Vec<f32> = values.iter().flat_map(|v| [v.x, v.y]).collect();
+            let buffer = storage((bytes.len().max(2) * 4) as u64, wgpu::BufferUsages::COPY_DST);
+            queue.write_buffer(&buffer, 0, bytemuck::cast_slice(&bytes));
+            buffer
+        };
+        let twiddles = table(&plan.twiddles);
+        let chirp = table(&plan.chirp);
+        let spectrum = table(&plan.spectrum);
+        let staging = context.buffer(input_size, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        let encode = |direction: FftDirection| -> (Vec<EncodedStep>, Slot) {
+            let (steps, result) = plan.steps(direction);
+            let slot = |slot: Slot| match slot {
+                Slot::Input => &input,
+                Slot::Work(i) => &work[i],
+            };
+            let encoded = steps
+                .iter()
+                .map(|step| {
+                    let params = context.uniform(&step.params);
+                    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: None,
+                        layout: &layout,
+                        entries: &[
+                            wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                            wgpu::BindGroupEntry { binding: 1, resource: slot(step.src).as_entire_binding() },
+                            wgpu::BindGroupEntry { binding: 2, resource: slot(step.dst).as_entire_binding() },
+                            wgpu::BindGroupEntry { binding: 3, resource: twiddles.as_entire_binding() },
+                            wgpu::BindGroupEntry { binding: 4, resource: chirp.as_entire_binding() },
+                            wgpu::BindGroupEntry { binding: 5, resource: spectrum.as_entire_binding() },
+                        ],
+                    });
+                    EncodedStep { kernel: step.kernel, bind_group, workgroups: gpu::workgroups(step.threads(), max_groups) }
+                })
+                .collect();
+            (encoded, result)
+        };
+        let (forward, forward_result) = encode(FftDirection::Forward);
+        let (inverse, inverse_result) = encode(FftDirection::Inverse);
+
+        gpu::pop_error_scopes(device)?;
+
+        Ok(Self {
+            context: context.clone(),
+            plan,
+            pipelines,
+            input,
+            work,
+            staging,
+            forward,
+            inverse,
+            results: [forward_result, inverse_result],
+        })
+    }
+
+    pub fn plan(&self) -> &FftPlan {
+        &self.plan
+    }
+
+    /// Context the plan runs on, whose device the buffers handed to
+    /// [`encode`](Self::encode) must belong to.
+    pub fn context(&self) -> &GpuContext {
+        &self.context
+    }
+
+    /// Transforms `batch` complex signals of `len` samples, stored one after another.
+    pub fn process(&mut self, data: &[Complex], direction: FftDirection) -> Result<Vec<Complex>> {
+        self.plan.check_input(data.len())?;
+        self.context.upload(&self.input, data)?;
+        let mut encoder = self.context.encoder();
+        let source = self.context.scoped(|| self.record(&mut encoder, direction))?;
+        self.context.read_back(encoder, source, &self.staging, self.staging.size())
+    }
+
+    /// Records into `encoder` the transform, in place, of the `batch` complex
+    /// signals of `len` samples stored one after another from byte `offset`
+    /// of `buffer`, e.g. IQ channel data along its sample axis. The data never
+    /// leaves the device: the region is copied into the plan's buffers,
+    /// transformed and copied back, so later passes recorded in the same
+    /// encoder see the spectrum. `buffer` must belong to the device of
+    /// [`context`](Self::context) and allow copies both ways, and `offset` be
+    /// a multiple of 4.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        buffer: &wgpu::Buffer,
+        offset: u64,
+        direction: FftDirection,
+    ) -> Result<()> {
+        let usage = wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        if !buffer.usage().contains(usage) {
+            return Err(BeamformError::InvalidConfig(format!(
+                "FFT buffer needs COPY_SRC and COPY_DST usage, got {:?}",
+                buffer.usage(),
+            )));
+        }
+        if !offset.is_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT) {
+            return Err(BeamformError::InvalidConfig(format!(
+                "FFT buffer offset must be a multiple of {} bytes, got {offset}",
+                wgpu::COPY_BUFFER_ALIGNMENT,
+            )));
+        }
+        let size = self.input.size();
+        if offset.checked_add(size).is_none_or(|end| end > buffer.size()) {
+            let available = buffer.size().saturating_sub(offset) / 8;
+            return Err(BeamformError::InvalidInput { expected: self.plan.len * self.plan.batch, actual: available as usize });
+        }
+
+        self.context.scoped(|| {
+            encoder.copy_buffer_to_buffer(buffer, offset, &self.input, 0, size);
+            let result = self.record(encoder, direction);
+            encoder.copy_buffer_to_buffer(result, 0, buffer, offset, size);
+        })
+    }
+
+    /// Records every step of `direction` in one compute pass, reading the
+    /// input buffer, and returns the buffer the result ends up in.
+    fn record(&self, encoder: &mut wgpu::CommandEncoder, direction: FftDirection) -> &wgpu::Buffer {
+        let (steps, result) = match direction {
+            FftDirection::Forward => (&self.forward, self.results[0]),
+            FftDirection::Inverse => (&self.inverse, self.results[1]),
+        };
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+            for step in steps {
+                compute_pass.set_pipeline(match step.kernel {
+                    Kernel::Radix => &self.pipelines.radix,
+                    Kernel::Premultiply => &self.pipelines.premultiply,
+                    Kernel::Multiply => &self.pipelines.multiply,
+                    Kernel::Postmultiply => &self.pipelines.postmultiply,
+                });
+                compute_pass.set_bind_group(0, &step.bind_group, &[]);
+                compute_pass.dispatch_workgroups(step.workgroups.0, step.workgroups.1, 1);
+            }
+        }
+        match result {
+            Slot::Input => &self.input,
+            Slot::Work(i) => &self.work[i],
+        }
+    }
+
+    /// Transforms `batch` real signals of `len` samples, e.g. an RF frame
+    /// along its sample axis.
+    pub fn process_real(&mut self, data: &[f32], direction: FftDirection) -> Result<Vec<Complex>> {
+        self.process(&to_complex(data), direction)
+    }
+}
+
+fn to_complex(data: &[f32]) -> Vec<Complex> {
+    data.iter().map(|&re| Complex::new(re, 0.0)).collect()
+}
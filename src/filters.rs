@@ -0,0 +1,73 @@
+//! Ready-made FIR filters for [`set_filter`](crate::Beamformer::set_filter),
+//! and the coded excitations they compress.
+
+use std::f64::consts::PI;
+
+use shared::{window, BeamformingConfig};
+
+/// Bandpass from `low_frequency` to `high_frequency` (Hz) with `num_taps`
+/// Hamming-windowed sinc taps at `config.sampling_frequency`. It is the
+/// difference of two low-passes of unit DC gain, so DC offsets are removed
+/// exactly. Use an odd number of taps so the filter does not delay the
+/// echoes.
+pub fn bandpass(config: &BeamformingConfig, low_frequency: f32, high_frequency: f32, num_taps: usize) -> Vec<f32> {
+    let high = windowed_sinc(config, high_frequency, num_taps);
+    let low = windowed_sinc(config, low_frequency, num_taps);
+    high.iter().zip(&low).map(|(h, l)| (h - l) as f32).collect()
+}
+
+/// Low-pass with a cutoff of `cutoff` (Hz) and `num_taps` Hamming-windowed
+/// sinc taps at `config.sampling_frequency`, with unit DC gain. Suited to IQ
+/// data, whose band sits around 0 Hz.
+pub fn low_pass(config: &BeamformingConfig, cutoff: f32, num_taps: usize) -> Vec<f32> {
+    windowed_sinc(config, cutoff, num_taps).into_iter().map(|tap| tap as f32).collect()
+}
+
+/// Hamming-windowed sinc low-pass taps, normalized to unit DC gain. A cutoff
+/// of 0 leaves the normalized window, a moving average.
+fn windowed_sinc(config: &BeamformingConfig, cutoff: f32, num_taps: usize) -> Vec<f64> {
+    let center = (num_taps as f64 - 1.0) * 0.5;
+    let cutoff = 2.0 * cutoff as f64 / config.sampling_frequency as f64;
+    let taps: Vec<f64> = (0..num_taps)
+        .map(|k| {
+            let window = if num_taps > 1 { 0.54 - 0.46 * (2.0 * PI * k as f64 / (num_taps - 1) as f64).cos() } else { 1.0 };
+            let x = PI * cutoff * (k as f64 - center);
+            let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+            window * sinc
+        })
+        .collect();
+    let sum: f64 = taps.iter().sum();
+    taps.iter().map(|tap| tap / sum).collect()
+}
+
+/// Matched filter of a transmitted `pulse` sampled at the RF rate: the pulse
+/// reversed in time, divided by its energy so an echo of the pulse scaled by
+/// `a` compresses to a peak of `a`. Echoes are timed from the centre of the
+/// pulse, which should therefore have an odd length.
+pub fn matched(pulse: &[f32]) -> Vec<f32> {
+    let energy: f32 = pulse.iter().map(|p| p * p).sum();
+    if energy == 0.0 {
+        return vec![0.0; pulse.len()];
+    }
+    pulse.iter().rev().map(|p| p / energy).collect()
+}
+
+/// Linear frequency-modulated excitation sweeping from `start_frequency` to
+/// `end_frequency` (Hz) over `duration` (s), sampled at
+/// `config.sampling_frequency` with an odd number of samples and tapered by a
+/// Tukey window over 20 % of its length to lower the range sidelobes after
+/// compression.
+pub fn chirp(config: &BeamformingConfig, start_frequency: f32, end_frequency: f32, duration: f32) -> Vec<f32> {
+    let fs = config.sampling_frequency as f64;
+    let len = (duration as f64 * fs).round() as usize | 1;
+    let length = (len - 1).max(1) as f64 / fs;
+    let sweep_rate = (end_frequency - start_frequency) as f64 / length;
+    (0..len)
+        .map(|k| {
+            let t = k as f64 / fs;
+            let phase = 2.0 * PI * (start_frequency as f64 * t + 0.5 * sweep_rate * t * t);
+            let taper = shader::window_weight(window::TUKEY, 0.2, (t / length) as f32);
+            phase.cos() as f32 * taper
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+use bytemuck::{Pod, Zeroable};
+
+/// A complex sample, laid out as `[re, im]` like the GPU output.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Pod, Zeroable)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn norm(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+
+    /// Phase in radians, in `(-pi, pi]`.
+    pub fn arg(self) -> f32 {
+        self.im.atan2(self.re)
+    }
+}
+
+/// A beamformed image, stored row by row as `[depth][width]`. RF input
+/// produces real pixels, IQ input complex ones ([`IqFrame`]).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame<T = f32> {
+    pub width: usize,
+    pub depth: usize,
+    pub data: Vec<T>,
+}
+
+pub type IqFrame = Frame<Complex>;
+
+impl<T: Copy> Frame<T> {
+    pub fn new(width: usize, depth: usize, data: Vec<T>) -> Self {
+        assert_eq!(data.len(), width * depth, "frame data must hold width * depth pixels");
+        Self { width, depth, data }
+    }
+
+    pub fn get(&self, x: usize, z: usize) -> T {
+        self.data[z * self.width + x]
+    }
+}
+
+impl Frame {
+    /// Column, row and value of the brightest pixel.
+    pub fn peak(&self) -> (usize, usize, f32) {
+        let (idx, value) = self
+            .data
+            .iter()
+            .enumerate()
+            .fold((0, f32::MIN), |best, (i, &v)| if v > best.1 { (i, v) } else { best });
+        (idx % self.width, idx / self.width, value)
+    }
+}
+
+impl IqFrame {
+    /// Per-pixel magnitude of the complex image.
+    pub fn magnitude(&self) -> Frame {
+        Frame::new(self.width, self.depth, self.data.iter().map(|c| c.norm()).collect())
+    }
+}
@@ -0,0 +1,1045 @@
+use std::sync::{Arc, Mutex};
+
+use crate::apodization::Apodization;
+use crate::config::{self, Tables};
+use crate::error::{BeamformError, Result};
+use crate::fft::{FftDirection, GpuFft};
+use crate::frame::{Frame, IqFrame};
+use crate::time_gain::Tgc;
+use shader::glam::Vec2;
+use shared::{grid_geometry, input_format, tgc, BeamformingConfig, TransmitEvent};
+
+/// GPU delay-and-sum beamformer, or minimum-variance when
+/// `config.mvdr_subarray` is set.
+///
+/// The device, shader module, pipeline and buffers are created once, so
+/// repeated calls to [`GpuBeamformer::process`] only upload RF data, dispatch
+/// and read back the image. The `encode` methods record the same stages into
+/// a caller's command encoder instead, leaving the frame in
+/// [`output_buffer`](GpuBeamformer::output_buffer) for other stages on the
+/// same [`GpuContext`].
+pub struct GpuBeamformer {
+    context: GpuContext,
+    pipeline: wgpu::ComputePipeline,
+    /// Minimum-variance beamforming, run instead of `main_shader` when
+    /// `config.mvdr_subarray` is set.
+    mvdr_pipeline: wgpu::ComputePipeline,
+    channel_pipelines: ChannelPipelines,
+    image_pipelines: ImagePipelines,
+    layouts: Layouts,
+    buffers: Buffers,
+    config: BeamformingConfig,
+    tables: Tables,
+}
+
+/// Pipelines of the stages run on the channel data before `main_shader`.
+struct ChannelPipelines {
+    filter: wgpu::ComputePipeline,
+    demodulate: wgpu::ComputePipeline,
+    time_gain: wgpu::ComputePipeline,
+}
+
+/// Pipelines of the B-mode image stage, run after `main_shader`.
+struct ImagePipelines {
+    envelope: wgpu::ComputePipeline,
+    peak: wgpu::ComputePipeline,
+    log_compress: wgpu::ComputePipeline,
+    log_compress_u8: wgpu::ComputePipeline,
+}
+
+struct Layouts {
+    beamform: wgpu::BindGroupLayout,
+    channel: wgpu::BindGroupLayout,
+    time_gain: wgpu::BindGroupLayout,
+    image: wgpu::BindGroupLayout,
+}
+
+/// Buffers whose sizes depend on the config, recreated when it changes.
+struct Buffers {
+    input: wgpu::Buffer,
+    /// Outputs of the filter and demodulation stages, if they run.
+    filtered: Option<wgpu::Buffer>,
+    demodulated: Option<wgpu::Buffer>,
+    output: wgpu::Buffer,
+    /// Config of the channel data stages.
+    config: wgpu::Buffer,
+    /// Config of the data `main_shader` reads, which differs from `config`
+    /// once demodulated.
+    beamform_config: wgpu::Buffer,
+    apodization: wgpu::Buffer,
+    transmits: wgpu::Buffer,
+    filter: wgpu::Buffer,
+    demodulation: wgpu::Buffer,
+    /// Points of the TGC curve.
+    tgc: wgpu::Buffer,
+    /// Pixel positions of `grid_geometry::POINTS`.
+    pixels: wgpu::Buffer,
+    /// B-mode image, as `f32` or packed 8-bit pixels.
+    image: wgpu::Buffer,
+    /// Threads `mvdr_shader` runs, each in its own slot of scratch space.
+    mvdr_slots: usize,
+    staging: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Filters the input, if filter taps are set.
+    filter_bind_group: Option<wgpu::BindGroup>,
+    /// Demodulates the (filtered) input, if enabled.
+    demodulate_bind_group: Option<wgpu::BindGroup>,
+    /// Applies TGC in place to the data `main_shader` reads, if enabled.
+    time_gain_bind_group: Option<wgpu::BindGroup>,
+    image_bind_group: wgpu::BindGroup,
+}
+
+/// What [`GpuBeamformer::run`] reads back.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Readback {
+    /// The beamformed frame, real for RF and complex for IQ.
+    Beamformed,
+    /// The log-compressed image, normalized to `[0, 1]`.
+    Bmode,
+    /// The log-compressed image as 8-bit grey levels.
+    BmodeU8,
+}
+
+impl GpuBeamformer {
+    /// Requests a GPU adapter and builds a beamformer on it. The `WGPU_BACKEND`
+    /// and `WGPU_POWER_PREF` environment variables narrow the choice, e.g.
+    /// `WGPU_BACKEND=vulkan` to pick a software Vulkan driver such as lavapipe.
+    pub async fn new(config: BeamformingConfig) -> Result<Self> {
+        Self::with_context(&GpuContext::new().await?, config)
+    }
+
+    /// Builds a beamformer on the device of `context`, e.g. one shared with a
+    /// renderer or with the stages that process its frames.
+    pub fn with_context(context: &GpuContext, config: BeamformingConfig) -> Result<Self> {
+        let tables = Tables::default();
+        config::validate(&config, &tables)?;
+        let (device, queue) = (context.device(), context.queue());
+        config::check_limits(&config, &device.limits())?;
+
+        push_error_scopes(device);
+
+        let shader = shader_module(device);
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, false),
+                uniform_entry(2),
+                storage_entry(3, true),
+                storage_entry(4, true),
+                storage_entry(5, true),
+                storage_entry(6, false),
+            ],
+        });
+        let pipeline = compute_pipeline(device, &shader, &bind_group_layout, "main_shader");
+        let mvdr_pipeline = compute_pipeline(device, &shader, &bind_group_layout, "mvdr_shader");
+
+        // Filter and demodulation of the channel data, each from a source
+        // buffer into a destination one, feeding main_shader
+        let channel_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[storage_entry(0, true), uniform_entry(1), storage_entry(2, true), storage_entry(3, false)],
+        });
+        // TGC works in place, on whichever buffer main_shader reads
+        let time_gain_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[uniform_entry(1), storage_entry(2, true), storage_entry(3, false)],
+        });
+        let channel_pipelines = ChannelPipelines {
+            filter: compute_pipeline(device, &shader, &channel_bind_group_layout, "filter_shader"),
+            demodulate: compute_pipeline(device, &shader, &channel_bind_group_layout, "demodulate_shader"),
+            time_gain: compute_pipeline(device, &shader, &time_gain_bind_group_layout, "time_gain_shader"),
+        };
+
+        // Envelope detection and log compression, reading the beamformed frame
+        let image_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                storage_entry(0, true),
+                uniform_entry(1),
+                storage_entry(2, false),
+                storage_entry(3, false),
+                storage_entry(4, false),
+            ],
+        });
+        let image_pipelines = ImagePipelines {
+            envelope: compute_pipeline(device, &shader, &image_bind_group_layout, "envelope_shader"),
+            peak: compute_pipeline(device, &shader, &image_bind_group_layout, "peak_shader"),
+            log_compress: compute_pipeline(device, &shader, &image_bind_group_layout, "log_compress_shader"),
+            log_compress_u8: compute_pipeline(device, &shader, &image_bind_group_layout, "log_compress_u8_shader"),
+        };
+
+        let layouts = Layouts {
+            beamform: bind_group_layout,
+            channel: channel_bind_group_layout,
+            time_gain: time_gain_bind_group_layout,
+            image: image_bind_group_layout,
+        };
+        let buffers = Buffers::new(device, &layouts, &config);
+        buffers.write(queue, &config, &tables);
+
+        pop_error_scopes(device)?;
+
+        Ok(Self {
+            context: context.clone(),
+            pipeline,
+            mvdr_pipeline,
+            channel_pipelines,
+            image_pipelines,
+            layouts,
+            buffers,
+            config,
+            tables,
+        })
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        self.context.adapter_info()
+    }
+
+    pub fn context(&self) -> &GpuContext {
+        &self.context
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    /// Replaces the config, keeping the current custom apodization weights,
+    /// transmit events, filter and demodulation taps, TGC curve and pixel
+    /// positions.
+    pub fn set_config(&mut self, config: BeamformingConfig) -> Result<()> {
+        self.update(config, self.tables.clone())
+    }
+
+    pub fn set_apodization(&mut self, apodization: Apodization) -> Result<()> {
+        let mut config = self.config;
+        let weights = apodization.apply(&mut config);
+        self.update(config, Tables { apodization: weights, ..self.tables.clone() })
+    }
+
+    /// Sets the transmit events of a frame and `config.num_transmits` to match.
+    pub fn set_transmits(&mut self, transmits: &[TransmitEvent]) -> Result<()> {
+        let config = BeamformingConfig { num_transmits: transmits.len() as u32, ..self.config };
+        self.update(config, Tables { transmits: transmits.to_vec(), ..self.tables.clone() })
+    }
+
+    /// Sets the FIR filter applied along the samples of every trace before
+    /// beamforming, and `config.num_filter_taps` to match. An empty slice
+    /// disables it.
+    pub fn set_filter(&mut self, taps: &[f32]) -> Result<()> {
+        let config = BeamformingConfig { num_filter_taps: taps.len() as u32, ..self.config };
+        self.update(config, Tables { filter: taps.to_vec(), ..self.tables.clone() })
+    }
+
+    /// Demodulates RF input to IQ on the device before beamforming: mixes it
+    /// down by `config.demodulation_frequency`, low-pass filters it with
+    /// `taps` and keeps every `decimation`-th sample. A decimation of 0 and
+    /// no taps disables it.
+    pub fn set_demodulation(&mut self, decimation: u32, taps: &[f32]) -> Result<()> {
+        let config = BeamformingConfig { decimation, num_demodulation_taps: taps.len() as u32, ..self.config };
+        self.update(config, Tables { demodulation: taps.to_vec(), ..self.tables.clone() })
+    }
+
+    /// Sets the time-gain compensation of the channel data, applied on the
+    /// device after filtering and demodulation.
+    pub fn set_tgc(&mut self, tgc: Tgc) -> Result<()> {
+        let mut config = self.config;
+        let points = tgc.apply(&mut config);
+        self.update(config, Tables { tgc: points, ..self.tables.clone() })
+    }
+
+    /// Beamforms onto arbitrary pixel positions `(x, z)`, laid out `width`
+    /// per row, instead of a computed grid, and sets `config.grid_geometry`,
+    /// `grid_width` and `grid_depth` to match.
+    pub fn set_pixels(&mut self, width: u32, positions: &[(f32, f32)]) -> Result<()> {
+        let depth = (positions.len() as u32).checked_div(width).unwrap_or(0);
+        let config =
+            BeamformingConfig { grid_geometry: grid_geometry::POINTS, grid_width: width, grid_depth: depth, ..self.config };
+        let pixels = positions.iter().map(|&(x, z)| Vec2::new(x, z)).collect();
+        self.update(config, Tables { pixels, ..self.tables.clone() })
+    }
+
+    /// Uploads a new config and its tables. Buffers are only reallocated if
+    /// the channel, transmit, tap or TGC point count, the decimation, the TGC
+    /// mode, the grid geometry, the minimum-variance subarray length or the
+    /// input or output sizes changed.
+    fn update(&mut self, config: BeamformingConfig, tables: Tables) -> Result<()> {
+        self.context.check_lost()?;
+        config::validate(&config, &tables)?;
+        let (device, queue) = (self.context.device(), self.context.queue());
+        config::check_limits(&config, &device.limits())?;
+        let resized = config.num_channels != self.config.num_channels
+            || config.num_transmits != self.config.num_transmits
+            || config.num_filter_taps != self.config.num_filter_taps
+            || config.num_demodulation_taps != self.config.num_demodulation_taps
+            || config.decimation != self.config.decimation
+            || config.tgc != self.config.tgc
+            || config.num_tgc_points != self.config.num_tgc_points
+            || config.grid_geometry != self.config.grid_geometry
+            || config.mvdr_subarray != self.config.mvdr_subarray
+            || config::input_len(&config) != config::input_len(&self.config)
+            || config::output_len(&config) != config::output_len(&self.config);
+        push_error_scopes(device);
+        let buffers = resized.then(|| Buffers::new(device, &self.layouts, &config));
+        buffers.as_ref().unwrap_or(&self.buffers).write(queue, &config, &tables);
+        pop_error_scopes(device)?;
+
+        // Only commit the new state once the device accepted it
+        if let Some(buffers) = buffers {
+            self.buffers = buffers;
+        }
+        self.config = config;
+        self.tables = tables;
+        Ok(())
+    }
+
+    /// Beamforms one frame of RF data laid out as `[transmit][channel][sample]`.
+    pub fn process(&mut self, rf: &[f32]) -> Result<Frame> {
+        config::check_input(&self.config, rf, false)?;
+        let data = self.run(rf, Readback::Beamformed)?;
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// Beamforms one frame of IQ data in the layout given by
+    /// `config.input_format`, or of RF data demodulated with
+    /// [`set_demodulation`](Self::set_demodulation).
+    pub fn process_iq(&mut self, iq: &[f32]) -> Result<IqFrame> {
+        config::check_input(&self.config, iq, true)?;
+        let data = self.run(iq, Readback::Beamformed)?;
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// Beamforms one frame of RF or IQ data and turns it into a B-mode image:
+    /// envelope, log compressed with `config.dynamic_range` and `config.gain`,
+    /// normalized to `[0, 1]`.
+    pub fn process_bmode(&mut self, input: &[f32]) -> Result<Frame> {
+        config::check_input(&self.config, input, config::is_complex(&self.config))?;
+        let data = self.run(input, Readback::Bmode)?;
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// [`process_bmode`](Self::process_bmode) with 8-bit grey levels.
+    pub fn process_bmode_u8(&mut self, input: &[f32]) -> Result<Frame<u8>> {
+        config::check_input(&self.config, input, config::is_complex(&self.config))?;
+        let mut data: Vec<u8> = self.run(input, Readback::BmodeU8)?;
+        // Pixels are packed four to a word, so the last word may be partial
+        data.truncate(shader::pixel_count(&self.config));
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// Writes one frame of input, laid out as for the `process` methods, for
+    /// the stages recorded next with [`encode_channels`](Self::encode_channels).
+    /// It reaches the device at the next submission on the queue, so encode
+    /// one frame per submission.
+    pub fn upload(&self, input: &[f32]) -> Result<()> {
+        config::check_input(&self.config, input, config::is_complex(&self.config))?;
+        self.context.upload(&self.buffers.input, input)
+    }
+
+    /// Records the filter, demodulation and TGC stages that are enabled, which
+    /// leave the channel data `main_shader` reads.
+    pub fn encode_channels(&self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        // One thread per input value, one per IQ sample, then one per value
+        // the beamformer reads
+        let config = &self.config;
+        let max_groups = self.context.device().limits().max_compute_workgroups_per_dimension;
+        let pipelines = &self.channel_pipelines;
+        if let Some(filter_bind_group) = &self.buffers.filter_bind_group {
+            let steps = [(&pipelines.filter, workgroups(config::input_len(config), max_groups))];
+            self.context.dispatch(encoder, filter_bind_group, &steps)?;
+        }
+        if let Some(demodulate_bind_group) = &self.buffers.demodulate_bind_group {
+            let threads = shader::demodulate::demodulate_threads(config);
+            self.context.dispatch(encoder, demodulate_bind_group, &[(&pipelines.demodulate, workgroups(threads, max_groups))])?;
+        }
+        if let Some(time_gain_bind_group) = &self.buffers.time_gain_bind_group {
+            let threads = shader::filter::filter_threads(&shader::demodulate::demodulated_config(config));
+            self.context.dispatch(encoder, time_gain_bind_group, &[(&pipelines.time_gain, workgroups(threads, max_groups))])?;
+        }
+        Ok(())
+    }
+
+    /// Records the transform, in place, of the channel data `main_shader`
+    /// reads: one trace of `num_samples` IQ samples per transmit and channel,
+    /// after demodulation if it is enabled. `fft` must be planned for that
+    /// shape on the same [`GpuContext`], and the data be interleaved IQ.
+    pub fn encode_fft(&self, encoder: &mut wgpu::CommandEncoder, fft: &GpuFft, direction: FftDirection) -> Result<()> {
+        let config = shader::demodulate::demodulated_config(&self.config);
+        if config.input_format != input_format::IQ_INTERLEAVED {
+            return Err(BeamformError::InvalidConfig(format!(
+                "FFT of the channel data needs interleaved IQ, got input format {}",
+                config.input_format,
+            )));
+        }
+        let traces = config.num_transmits as usize * config.num_channels as usize;
+        let plan = fft.plan();
+        if (plan.batch(), plan.transform_len()) != (traces, config.num_samples as usize) {
+            return Err(BeamformError::InvalidConfig(format!(
+                "FFT of {} x {} samples does not match {traces} traces of {} samples",
+                plan.batch(),
+                plan.transform_len(),
+                config.num_samples,
+            )));
+        }
+        fft.encode(encoder, self.buffers.channels(), 0, direction)
+    }
+
+    /// Records `main_shader`, or `mvdr_shader`, beamforming the channel data
+    /// into [`output_buffer`](Self::output_buffer).
+    pub fn encode_beamform(&self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        let pixels = shader::pixel_count(&self.config);
+        let max_groups = self.context.device().limits().max_compute_workgroups_per_dimension;
+        let step = if self.config.mvdr_subarray > 0 {
+            // One thread per scratch slot, each beamforming every slots-th pixel
+            (&self.mvdr_pipeline, ((self.buffers.mvdr_slots / shader::WORKGROUP_SIZE) as u32, 1))
+        } else {
+            // One workgroup per pixel, channels strided across its threads
+            let x = (pixels as u32).clamp(1, max_groups);
+            (&self.pipeline, (x, (pixels as u32).div_ceil(x)))
+        };
+        self.context.dispatch(encoder, &self.buffers.bind_group, &[step])
+    }
+
+    /// Records the image stage, turning the beamformed frame into a B-mode
+    /// image in [`image_buffer`](Self::image_buffer).
+    pub fn encode_bmode(&self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        self.encode_image(encoder, false)
+    }
+
+    /// The beamformed frame, laid out like those of [`process`](Self::process)
+    /// (one `f32` per pixel) and [`process_iq`](Self::process_iq) (interleaved
+    /// `(re, im)`). It can be copied from, e.g. into an ensemble of
+    /// [`GpuDoppler`](crate::GpuDoppler).
+    pub fn output_buffer(&self) -> &wgpu::Buffer {
+        &self.buffers.output
+    }
+
+    /// The B-mode image of [`encode_bmode`](Self::encode_bmode), one `f32` in
+    /// `[0, 1]` per pixel, e.g. for [`GpuScanConverter`](crate::GpuScanConverter).
+    pub fn image_buffer(&self) -> &wgpu::Buffer {
+        &self.buffers.image
+    }
+
+    /// Submits `encoder` and reads back the frame of RF data its recorded
+    /// stages beamformed.
+    pub fn process_encoded(&mut self, encoder: wgpu::CommandEncoder) -> Result<Frame> {
+        config::check_format(&self.config, false)?;
+        let data = self.read_back(encoder, Readback::Beamformed)?;
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// [`process_encoded`](Self::process_encoded) for IQ or demodulated data.
+    pub fn process_iq_encoded(&mut self, encoder: wgpu::CommandEncoder) -> Result<IqFrame> {
+        config::check_format(&self.config, true)?;
+        let data = self.read_back(encoder, Readback::Beamformed)?;
+        Ok(Frame::new(self.config.grid_width as usize, self.config.grid_depth as usize, data))
+    }
+
+    /// Image stage: one thread per pixel (or per four packed pixels), with a
+    /// single-workgroup peak reduction in between.
+    fn encode_image(&self, encoder: &mut wgpu::CommandEncoder, packed: bool) -> Result<()> {
+        let pipelines = &self.image_pipelines;
+        let pixels = shader::pixel_count(&self.config);
+        let max_groups = self.context.device().limits().max_compute_workgroups_per_dimension;
+        let groups = workgroups(pixels, max_groups);
+        let log_compress = if packed {
+            (&pipelines.log_compress_u8, workgroups(pixels.div_ceil(4), max_groups))
+        } else {
+            (&pipelines.log_compress, groups)
+        };
+        let steps = [(&pipelines.envelope, groups), (&pipelines.peak, (1, 1)), log_compress];
+        self.context.dispatch(encoder, &self.buffers.image_bind_group, &steps)
+    }
+
+    /// Uploads `input`, dispatches `main_shader` or `mvdr_shader` (preceded by the filter,
+    /// demodulation and TGC stages if enabled, and followed by the image stage unless
+    /// the beamformed frame is wanted) and reads the result back as
+    /// `T`: `f32` for RF, `Complex` for IQ, `f32` or `u8` for B-mode images.
+    fn run<T: bytemuck::Pod>(&mut self, input: &[f32], readback: Readback) -> Result<Vec<T>> {
+        if shader::pixel_count(&self.config) == 0 {
+            return Ok(Vec::new());
+        }
+        self.context.upload(&self.buffers.input, input)?;
+        let mut encoder = self.context.encoder();
+        self.encode_channels(&mut encoder)?;
+        self.encode_beamform(&mut encoder)?;
+        if readback != Readback::Beamformed {
+            self.encode_image(&mut encoder, readback == Readback::BmodeU8)?;
+        }
+        self.read_back(encoder, readback)
+    }
+
+    /// Submits `encoder` and reads back the beamformed frame or the image.
+    fn read_back<T: bytemuck::Pod>(&self, encoder: wgpu::CommandEncoder, readback: Readback) -> Result<Vec<T>> {
+        let pixels = shader::pixel_count(&self.config);
+        let (source, size) = match readback {
+            Readback::Beamformed => (&self.buffers.output, self.buffers.output.size()),
+            Readback::Bmode => (&self.buffers.image, pixels as u64 * 4),
+            Readback::BmodeU8 => (&self.buffers.image, pixels.div_ceil(4) as u64 * 4),
+        };
+        self.context.read_back(encoder, source, &self.buffers.staging, size)
+    }
+}
+
+/// A device and its queue, shared by the GPU stages built on it with their
+/// `with_context` constructors, so that they can hand buffers to one another
+/// without a round trip through the host. Cloning it is cheap.
+#[derive(Clone)]
+pub struct GpuContext {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    adapter_info: wgpu::AdapterInfo,
+    lost: DeviceLost,
+}
+
+impl GpuContext {
+    /// Requests a GPU adapter and a device on it. The `WGPU_BACKEND` and
+    /// `WGPU_POWER_PREF` environment variables narrow the choice.
+    pub async fn new() -> Result<Self> {
+        let (device, queue, adapter_info) = request_device().await?;
+        Ok(Self::from_device(device, queue, adapter_info))
+    }
+
+    /// Wraps an existing device, e.g. one shared with a renderer. This sets
+    /// the device-lost callback of the device.
+    pub fn from_device(device: wgpu::Device, queue: wgpu::Queue, adapter_info: wgpu::AdapterInfo) -> Self {
+        let lost = DeviceLost::watch(&device);
+        Self { device: Arc::new(device), queue: Arc::new(queue), adapter_info, lost }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    /// A command encoder for the `encode` methods of the stages.
+    pub fn encoder(&self) -> wgpu::CommandEncoder {
+        self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None })
+    }
+
+    pub(crate) fn check_lost(&self) -> Result<()> {
+        self.lost.check()
+    }
+
+    /// Rejects a storage buffer of `size` bytes larger than the device binds.
+    pub(crate) fn check_binding(&self, name: &str, size: u64) -> Result<()> {
+        let max_binding = config::max_binding(&self.device.limits());
+        if size > max_binding {
+            return Err(BeamformError::InvalidConfig(format!(
+                "{name} of {size} bytes exceeds the device limit of {max_binding} bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn buffer(&self, size: u64, usage: wgpu::BufferUsages) -> wgpu::Buffer {
+        self.device.create_buffer(&wgpu::BufferDescriptor { label: None, size, usage, mapped_at_creation: false })
+    }
+
+    /// A uniform buffer holding `value`.
+    pub(crate) fn uniform<T: bytemuck::Pod>(&self, value: &T) -> wgpu::Buffer {
+        let buffer = self.buffer(
+            std::mem::size_of::<T>() as u64,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        self.queue.write_buffer(&buffer, 0, bytemuck::bytes_of(value));
+        buffer
+    }
+
+    /// Runs `record`, which issues commands to the device, returning the
+    /// validation and out-of-memory errors they raise.
+    pub(crate) fn scoped<R>(&self, record: impl FnOnce() -> R) -> Result<R> {
+        self.lost.check()?;
+        push_error_scopes(&self.device);
+        let result = record();
+        pop_error_scopes(&self.device)?;
+        Ok(result)
+    }
+
+    /// Writes `data` to the start of `buffer` at the next submission.
+    pub(crate) fn upload<T: bytemuck::Pod>(&self, buffer: &wgpu::Buffer, data: &[T]) -> Result<()> {
+        self.scoped(|| self.queue.write_buffer(buffer, 0, bytemuck::cast_slice(data)))
+    }
+
+    /// Records one compute pass running each `(pipeline, workgroups)` of
+    /// `steps` in turn, with `bind_group` bound.
+    pub(crate) fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        steps: &[(&wgpu::ComputePipeline, (u32, u32))],
+    ) -> Result<()> {
+        self.scoped(|| {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            for &(pipeline, (x, y)) in steps {
+                compute_pass.set_pipeline(pipeline);
+                compute_pass.dispatch_workgroups(x, y, 1);
+            }
+        })
+    }
+
+    /// Records a copy of the first `len` values of type `T` of `source`, a
+    /// buffer of another stage on this device, to value `offset` of
+    /// `destination`.
+    pub(crate) fn copy_values<T>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+        destination: &wgpu::Buffer,
+        offset: usize,
+        len: usize,
+    ) -> Result<()> {
+        if !source.usage().contains(wgpu::BufferUsages::COPY_SRC) {
+            return Err(BeamformError::InvalidConfig(format!(
+                "source buffer needs COPY_SRC usage, got {:?}",
+                source.usage(),
+            )));
+        }
+        let value_size = std::mem::size_of::<T>() as u64;
+        if source.size() < len as u64 * value_size {
+            return Err(BeamformError::InvalidInput { expected: len, actual: (source.size() / value_size) as usize });
+        }
+        self.scoped(|| {
+            encoder.copy_buffer_to_buffer(source, 0, destination, offset as u64 * value_size, len as u64 * value_size)
+        })
+    }
+
+    /// Submits `encoder`, followed by a copy of the first `size` bytes of
+    /// `source` into `staging`, and reads them back as `T`.
+    pub(crate) fn read_back<T: bytemuck::Pod>(
+        &self,
+        mut encoder: wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+        staging: &wgpu::Buffer,
+        size: u64,
+    ) -> Result<Vec<T>> {
+        self.scoped(|| {
+            encoder.copy_buffer_to_buffer(source, 0, staging, 0, size);
+            self.queue.submit(Some(encoder.finish()));
+        })?;
+        read_back(&self.device, staging, size, &self.lost)
+    }
+}
+
+/// Requests a GPU adapter and a device on it.
+async fn request_device() -> Result<(wgpu::Device, wgpu::Queue, wgpu::AdapterInfo)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::util::backend_bits_from_env().unwrap_or_default(),
+        ..Default::default()
+    });
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::util::power_preference_from_env().unwrap_or_default(),
+            ..Default::default()
+        })
+        .await
+        .ok_or(BeamformError::NoAdapter)?;
+
+    // Ask for everything the adapter offers: synthetic-aperture frames
+    // need storage buffers far larger than the portable defaults
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor { required_limits: adapter.limits(), ..Default::default() },
+            None,
+        )
+        .await?;
+
+    Ok((device, queue, adapter.get_info()))
+}
+
+/// Loads the SPIR-V module holding every kernel of the shader crate.
+pub(crate) fn shader_module(device: &wgpu::Device) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: None,
+        source: wgpu::util::make_spirv(include_bytes!(env!("SHADER_PATH"))),
+    })
+}
+
+/// Set by the device-lost callback; checked before every dispatch.
+#[derive(Clone)]
+struct DeviceLost(Arc<Mutex<Option<String>>>);
+
+impl DeviceLost {
+    fn watch(device: &wgpu::Device) -> Self {
+        let lost = Arc::new(Mutex::new(None));
+        let lost_slot = Arc::clone(&lost);
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device (or replacing this callback) is not a failure
+            if matches!(reason, wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::Destroyed) {
+                *lost_slot.lock().unwrap() = Some(message);
+            }
+        });
+        Self(lost)
+    }
+
+    fn check(&self) -> Result<()> {
+        match self.0.lock().unwrap().clone() {
+            Some(message) => Err(BeamformError::DeviceLost(message)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Maps the first `size` bytes of `staging` once the submitted work is done
+/// and copies them out as `T`.
+fn read_back<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    staging: &wgpu::Buffer,
+    size: u64,
+    lost: &DeviceLost,
+) -> Result<Vec<T>> {
+    let buffer_slice = staging.slice(..size);
+    let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
+    buffer_slice.map_async(wgpu::MapMode::Read, move |res| {
+        let _ = tx.send(res);
+    });
+
+    device.poll(wgpu::Maintain::Wait);
+    match pollster::block_on(rx.receive()) {
+        Some(res) => res?,
+        // The callback was dropped without running, which only happens on device loss
+        None => {
+            lost.check()?;
+            return Err(BeamformError::DeviceLost("readback was cancelled".into()));
+        }
+    }
+
+    let data = buffer_slice.get_mapped_range();
+    let result: Vec<T> = bytemuck::cast_slice(&data).to_vec();
+    drop(data);
+    staging.unmap();
+
+    Ok(result)
+}
+
+/// Captures validation and out-of-memory errors raised by the commands issued
+/// until the matching [`pop_error_scopes`], instead of wgpu's default panic.
+pub(crate) fn push_error_scopes(device: &wgpu::Device) {
+    device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+}
+
+pub(crate) fn pop_error_scopes(device: &wgpu::Device) -> Result<()> {
+    let validation = pollster::block_on(device.pop_error_scope());
+    let out_of_memory = pollster::block_on(device.pop_error_scope());
+    match out_of_memory.or(validation) {
+        Some(err) => Err(err.into()),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only }, has_dynamic_offset: false, min_binding_size: None },
+        count: None,
+    }
+}
+
+pub(crate) fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+        count: None,
+    }
+}
+
+/// Creates a pipeline for `entry_point` of the shader module with a single
+/// bind group of the given layout.
+pub(crate) fn compute_pipeline(
+    device: &wgpu::Device,
+    module: &wgpu::ShaderModule,
+    layout: &wgpu::BindGroupLayout,
+    entry_point: &str,
+) -> wgpu::ComputePipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(&pipeline_layout),
+        module,
+        entry_point,
+    })
+}
+
+/// Workgroups `(x, y)` of a 1D dispatch of `threads` threads, spilling into
+/// `y` beyond `max_groups`. The kernels flatten it again with
+/// `shader::thread_index`.
+pub(crate) fn workgroups(threads: usize, max_groups: u32) -> (u32, u32) {
+    let groups = threads.div_ceil(shader::WORKGROUP_SIZE) as u32;
+    let x = groups.clamp(1, max_groups);
+    (x, groups.div_ceil(x))
+}
+
+impl Buffers {
+    fn new(device: &wgpu::Device, layouts: &Layouts, config: &BeamformingConfig) -> Self {
+        let input_size = config::input_len(config).max(1) as u64 * 4;
+        let demodulated_size = config::demodulated_len(config).max(1) as u64 * 4;
+        let output_size = config::output_len(config).max(1) as u64 * 4;
+        let image_size = shader::pixel_count(config).max(1) as u64 * 4;
+        let apodization_size = config.num_channels.max(1) as u64 * 4;
+        let transmits_size = (config.num_transmits.max(1) as usize * std::mem::size_of::<TransmitEvent>()) as u64;
+        let filter_size = config.num_filter_taps.max(1) as u64 * 4;
+        let demodulation_size = config.num_demodulation_taps.max(1) as u64 * 4;
+        let tgc_size = config.num_tgc_points.max(1) as u64 * 8;
+        let pixels_size = config::pixel_positions_len(config).max(1) as u64 * 4;
+        let config_size = std::mem::size_of::<BeamformingConfig>() as u64;
+        let mvdr_slots = config::mvdr_slots(config, &device.limits());
+        let scratch_size = (mvdr_slots * shader::mvdr::mvdr_scratch_len(config)).max(1) as u64 * 8;
+        let (filter_enabled, demodulate_enabled) = (config.num_filter_taps > 0, config.decimation > 0);
+        let time_gain_enabled = config.tgc != tgc::OFF;
+
+        // Copied both ways, like the other channel data buffers, by GpuFft::encode
+        let channel_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        let input = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: input_size,
+            usage: channel_usage,
+            mapped_at_creation: false,
+        });
+
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let config = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: config_size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let beamform_config = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: config_size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let apodization = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: apodization_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let transmits = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: transmits_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let filter = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: filter_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let demodulation = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: demodulation_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let tgc = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: tgc_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pixels = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: pixels_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Outputs of the channel data stages, only allocated when they run
+        let filtered = filter_enabled.then(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: input_size,
+                usage: channel_usage,
+                mapped_at_creation: false,
+            })
+        });
+
+        let demodulated = demodulate_enabled.then(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size: demodulated_size,
+                usage: channel_usage,
+                mapped_at_creation: false,
+            })
+        });
+
+        // Envelope of every pixel, then its maximum, only used by the image stage
+        let envelope = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: image_size,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let peak = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let image = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: image_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        // Only written and read by mvdr_shader, and a single value otherwise
+        let scratch = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: scratch_size,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // Shared by every readback, so large enough for the biggest
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: output_size.max(image_size),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Each stage reads the output of the previous one that ran
+        let filter_source = &input;
+        let demodulate_source = filtered.as_ref().unwrap_or(filter_source);
+        let beamform_source = demodulated.as_ref().unwrap_or(demodulate_source);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layouts.beamform,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: beamform_source.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: output.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: beamform_config.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: apodization.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: transmits.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: pixels.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 6, resource: scratch.as_entire_binding() },
+            ],
+        });
+
+        let channel_bind_group = |source: &wgpu::Buffer, taps: &wgpu::Buffer, destination: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layouts.channel,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: source.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: config.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: taps.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: destination.as_entire_binding() },
+                ],
+            })
+        };
+        let filter_bind_group = filtered.as_ref().map(|filtered| channel_bind_group(filter_source, &filter, filtered));
+        let demodulate_bind_group =
+            demodulated.as_ref().map(|demodulated| channel_bind_group(demodulate_source, &demodulation, demodulated));
+        let time_gain_bind_group = time_gain_enabled.then(|| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &layouts.time_gain,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 1, resource: beamform_config.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: tgc.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 3, resource: beamform_source.as_entire_binding() },
+                ],
+            })
+        });
+
+        let image_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layouts.image,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: output.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: beamform_config.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: envelope.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: peak.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: image.as_entire_binding() },
+            ],
+        });
+
+        Self {
+            input,
+            filtered,
+            demodulated,
+            output,
+            config,
+            beamform_config,
+            apodization,
+            transmits,
+            filter,
+            demodulation,
+            tgc,
+            pixels,
+            image,
+            mvdr_slots,
+            staging,
+            bind_group,
+            filter_bind_group,
+            demodulate_bind_group,
+            time_gain_bind_group,
+            image_bind_group,
+        }
+    }
+
+    /// The channel data `main_shader` reads: the output of the last channel
+    /// data stage that runs.
+    fn channels(&self) -> &wgpu::Buffer {
+        self.demodulated.as_ref().or(self.filtered.as_ref()).unwrap_or(&self.input)
+    }
+
+    fn write(&self, queue: &wgpu::Queue, config: &BeamformingConfig, tables: &Tables) {
+        queue.write_buffer(&self.config, 0, bytemuck::bytes_of(config));
+        let beamform_config = shader::demodulate::demodulated_config(config);
+        queue.write_buffer(&self.beamform_config, 0, bytemuck::bytes_of(&beamform_config));
+        if !tables.apodization.is_empty() {
+            queue.write_buffer(&self.apodization, 0, bytemuck::cast_slice(&tables.apodization));
+        }
+        queue.write_buffer(&self.transmits, 0, bytemuck::cast_slice(&tables.transmits));
+        for (buffer, taps) in [(&self.filter, &tables.filter), (&self.demodulation, &tables.demodulation)] {
+            if !taps.is_empty() {
+                queue.write_buffer(buffer, 0, bytemuck::cast_slice(taps));
+            }
+        }
+        for (buffer, points) in [(&self.tgc, &tables.tgc), (&self.pixels, &tables.pixels)] {
+            if !points.is_empty() {
+                let values: Vec<f32> = points.iter().flat_map(|point| [point.x, point.y]).collect();
+                queue.write_buffer(buffer, 0, bytemuck::cast_slice(&values));
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! GPU ultrasound beamforming with Rust-GPU kernels and wgpu.
+
+mod apodization;
+mod beamformer;
+mod config;
+mod cpu;
+mod doppler;
+mod error;
+mod fft;
+mod frame;
+mod gpu;
+mod scan_conversion;
+mod spectral_doppler;
+mod svd_filter;
+mod time_gain;
+pub mod filters;
+pub mod simulate;
+pub mod transmits;
+
+pub use apodization::Apodization;
+pub use beamformer::{Backend, Beamformer};
+pub use cpu::CpuBeamformer;
+pub use doppler::{nyquist_velocity, CpuDoppler, DopplerMaps, GpuDoppler};
+pub use error::{BeamformError, Result};
+pub use fft::{CpuFft, FftDirection, FftPlan, GpuFft};
+pub use frame::{Complex, Frame, IqFrame};
+pub use gpu::{GpuBeamformer, GpuContext};
+pub use scan_conversion::{fit_raster, CpuScanConverter, GpuScanConverter};
+pub use spectral_doppler::{SampleVolume, SpectralWindow, Spectrogram};
+pub use svd_filter::{CpuSvdFilter, GpuSvdFilter, SvdCutoff, SvdFiltered};
+pub use time_gain::Tgc;
+pub use shared::{
+    grid_geometry, input_format, interpolation, tgc, transmit_model, window, BeamformingConfig, Raster, TransmitEvent,
+};
@@ -1,165 +1,67 @@
-use bytemuck::{Pod, Zeroable};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
-const NUM_CHANNELS: usize = 64;
-const NUM_SAMPLES: usize = 16;
-
-#[repr(C)]
-#[derive(Clone, Copy, Pod, Zeroable)]
-struct BeamformingConfig {
-    speed_of_sound: f32,
-}
+use rust_gpu_app::{interpolation, simulate, Backend, Beamformer, BeamformingConfig, Frame};
 
 fn main() {
-    pollster::block_on(run());
-}
-
-async fn run() {
-    let instance = wgpu::Instance::default();
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions::default())
-        .await
-        .expect("Failed to find GPU adapter");
-
-    let (device, queue) = adapter
-        .request_device(
-            &wgpu::DeviceDescriptor::default(),
-            None,
-        )
-        .await
-        .expect("Failed to create device");
-
-    println!("Using GPU: {:?}", adapter.get_info().name);
-
-    let config = BeamformingConfig { speed_of_sound: 1540.0 };
-    let mut input_data = vec![0.0f32; NUM_CHANNELS * NUM_SAMPLES];
-    for s in 0..NUM_SAMPLES {
-        for c in 0..NUM_CHANNELS {
-            if s == 8 { input_data[s * NUM_CHANNELS + c] = 1.0; }
-        }
-    }
-
-    let results = execute_gpu_compute(&device, &queue, &input_data, config).await;
-
-    println!("\nBeamformed Output (Sample 8 pulse):");
-    for (i, result) in results.iter().enumerate() {
-        if *result > 0.0 {
-            println!("  Point [{:2}]: sum = {:8.1}", i, result);
-        }
+    if let Err(err) = pollster::block_on(run()) {
+        eprintln!("error: {err}");
+        std::process::exit(1);
     }
 }
 
-async fn execute_gpu_compute(
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    input_data: &[f32],
-    config: BeamformingConfig,
-) -> Vec<f32> {
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::util::make_spirv(include_bytes!(env!("SHADER_PATH"))),
-    });
-
-    let input_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: (input_data.len() * 4) as u64,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-
-    let output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: (NUM_SAMPLES * 4) as u64,
-        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    });
-
-    let config_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: 4,
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-
-    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: None,
-        size: (NUM_SAMPLES * 4) as u64,
-        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-
-    queue.write_buffer(&input_buffer, 0, bytemuck::cast_slice(input_data));
-    queue.write_buffer(&config_buffer, 0, bytemuck::bytes_of(&config));
-
-    let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-        label: None,
-        entries: &[
-            wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: true }, has_dynamic_offset: false, min_binding_size: None },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 1,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only: false }, has_dynamic_offset: false, min_binding_size: None },
-                count: None,
-            },
-            wgpu::BindGroupLayoutEntry {
-                binding: 2,
-                visibility: wgpu::ShaderStages::COMPUTE,
-                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
-                count: None,
-            },
-        ],
-    });
-
-    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: None,
-        layout: &bgl,
-        entries: &[
-            wgpu::BindGroupEntry { binding: 0, resource: input_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 1, resource: output_buffer.as_entire_binding() },
-            wgpu::BindGroupEntry { binding: 2, resource: config_buffer.as_entire_binding() },
-        ],
-    });
-
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[&bgl],
-        push_constant_ranges: &[],
-    });
-
-    let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        module: &shader,
-        entry_point: "main_shader",
-    });
-
-    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-    {
-        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
-        compute_pass.set_pipeline(&compute_pipeline);
-        compute_pass.set_bind_group(0, &bind_group, &[]);
-        compute_pass.dispatch_workgroups(NUM_SAMPLES as u32, 1, 1);
-    }
-
-    encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging_buffer, 0, (NUM_SAMPLES * 4) as u64);
-    queue.submit(Some(encoder.finish()));
-
-    let buffer_slice = staging_buffer.slice(..);
-    let (tx, rx) = futures_intrusive::channel::shared::oneshot_channel();
-    buffer_slice.map_async(wgpu::MapMode::Read, move |res| tx.send(res).unwrap());
-
-    device.poll(wgpu::Maintain::Wait);
-    rx.receive().await.unwrap().unwrap();
-
-    let data = buffer_slice.get_mapped_range();
-    let result: Vec<f32> = bytemuck::cast_slice(&data).to_vec();
-    drop(data);
-    staging_buffer.unmap();
-
-    result
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let num_channels = 128;
+    let num_samples = 2048;
+    let element_pitch = 0.3e-3;
+    // 0.3 mm x 0.05 mm pixels covering x = -19.2..19.2 mm, z = 10..30 mm; the
+    // fine axial spacing samples the 5 MHz echoes for envelope detection
+    let config = BeamformingConfig {
+        speed_of_sound: 1540.0,
+        sampling_frequency: 40.0e6,
+        element_pitch,
+        start_time: 0.0,
+        grid_origin_x: -19.2e-3,
+        grid_origin_z: 10.0e-3,
+        grid_spacing_x: 0.3e-3,
+        grid_spacing_z: 0.05e-3,
+        grid_width: 129,
+        grid_depth: 401,
+        num_channels,
+        num_samples,
+        interpolation: interpolation::CUBIC,
+        ..Default::default()
+    };
+
+    // Simulate the echo of a single point scatterer
+    let (target_x, target_z) = (0.0f32, 20.0e-3f32);
+    let input_data = simulate::pulse_echoes(&config, &[(target_x, target_z)], 5.0e6, 0.6);
+
+    // `--cpu` forces the CPU reference; otherwise fall back to it only without a GPU
+    let backend = if std::env::args().any(|arg| arg == "--cpu") { Backend::Cpu } else { Backend::Auto };
+    let mut beamformer = Beamformer::new(config, backend).await?;
+    println!("Using: {:?}", beamformer.device_name());
+
+    let frame = beamformer.process(&input_data)?;
+
+    let (peak_col, peak_row, peak) = frame.peak();
+    let peak_x = config.grid_origin_x + peak_col as f32 * config.grid_spacing_x;
+    let peak_z = config.grid_origin_z + peak_row as f32 * config.grid_spacing_z;
+
+    println!("\nBeamformed Output (point target at x = {:.1} mm, z = {:.1} mm):", target_x * 1e3, target_z * 1e3);
+    println!("  Peak at x = {:5.1} mm, z = {:5.1} mm: sum = {:6.1}", peak_x * 1e3, peak_z * 1e3, peak);
+
+    let image = beamformer.process_bmode_u8(&input_data)?;
+    write_pgm("bmode.pgm", &image)?;
+    println!("\nB-mode image ({} dB dynamic range) written to bmode.pgm", config.dynamic_range);
+
+    Ok(())
 }
 
+/// Writes an 8-bit image as a binary PGM file.
+fn write_pgm(path: &str, image: &Frame<u8>) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write!(file, "P5\n{} {}\n255\n", image.width, image.depth)?;
+    file.write_all(&image.data)?;
+    file.flush()
+}
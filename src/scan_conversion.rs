@@ -0,0 +1,183 @@
+//! Scan conversion of beamformed images onto a Cartesian raster, on the GPU
+//! with a CPU reference running the same kernel code.
+//!
+//! Phased and convex probes are beamformed on a polar grid
+//! ([`grid_geometry::POLAR`](shared::grid_geometry::POLAR)), whose columns
+//! fan out from an apex; the raster resamples that sector onto square pixels
+//! for display.
+
+use crate::config;
+use crate::error::{BeamformError, Result};
+use crate::frame::Frame;
+use crate::gpu::{self, GpuContext};
+use shader::glam::Vec2;
+use shared::{BeamformingConfig, Raster};
+
+/// Raster of `width` columns of square pixels covering the grid of `config`:
+/// the bounding box of its edge pixels, so a whole sector fits.
+pub fn fit_raster(config: &BeamformingConfig, width: u32) -> Raster {
+    let (cols, rows) = (config.grid_width as usize, config.grid_depth as usize);
+    let edges = (0..cols)
+        .flat_map(|col| [(col, 0), (col, rows.saturating_sub(1))])
+        .chain((0..rows).flat_map(|row| [(0, row), (cols.saturating_sub(1), row)]));
+    let (mut min, mut max) = (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY));
+    for (col, row) in edges {
+        let position = shader::pixel_position(config, col, row);
+        min = min.min(position);
+        max = max.max(position);
+    }
+    let spacing = (max.x - min.x) / width.saturating_sub(1).max(1) as f32;
+    let depth = ((max.y - min.y) / spacing).ceil() as u32 + 1;
+    Raster {
+        origin_x: min.x,
+        origin_z: min.y,
+        spacing_x: spacing,
+        spacing_z: spacing,
+        width,
+        depth,
+        ..Default::default()
+    }
+}
+
+/// Checks that `image` holds one value per pixel of the grid of `config`.
+fn check_image(config: &BeamformingConfig, image: &[f32]) -> Result<()> {
+    let expected = config.grid_width as usize * config.grid_depth as usize;
+    if image.len() != expected {
+        return Err(BeamformError::InvalidInput { expected, actual: image.len() });
+    }
+    Ok(())
+}
+
+/// CPU reference scan converter, running the kernel function of the `shader`
+/// crate one raster pixel at a time.
+pub struct CpuScanConverter {
+    config: BeamformingConfig,
+    raster: Raster,
+}
+
+impl CpuScanConverter {
+    /// Converts images on the grid of `config` onto `raster`.
+    pub fn new(config: BeamformingConfig, raster: Raster) -> Result<Self> {
+        config::validate_raster(&config, &raster)?;
+        Ok(Self { config, raster })
+    }
+
+    pub fn raster(&self) -> &Raster {
+        &self.raster
+    }
+
+    /// Resamples a real image on the grid of the config, laid out
+    /// `[depth][width]` like a B-mode frame, onto the raster.
+    pub fn process(&self, image: &[f32]) -> Result<Frame> {
+        check_image(&self.config, image)?;
+        let data = (0..shader::scan_convert::scan_convert_threads(&self.raster))
+            .map(|i| shader::scan_convert::scan_convert_pixel(image, &self.config, &self.raster, i))
+            .collect();
+        Ok(Frame::new(self.raster.width as usize, self.raster.depth as usize, data))
+    }
+}
+
+/// GPU scan converter. The pipeline, buffers and bind group are created once;
+/// each call uploads the image, dispatches one thread per raster pixel and
+/// reads the raster back. [`encode`](GpuScanConverter::encode) converts an
+/// image already on the device instead, e.g. that of a
+/// [`GpuBeamformer`](crate::GpuBeamformer) on the same [`GpuContext`].
+pub struct GpuScanConverter {
+    context: GpuContext,
+    raster: Raster,
+    pipeline: wgpu::ComputePipeline,
+    input: wgpu::Buffer,
+    output: wgpu::Buffer,
+    staging: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    workgroups: (u32, u32),
+    config: BeamformingConfig,
+}
+
+impl GpuScanConverter {
+    /// Requests a GPU adapter and prepares the conversion of images on the
+    /// grid of `config` onto `raster`.
+    pub async fn new(config: BeamformingConfig, raster: Raster) -> Result<Self> {
+        Self::with_context(&GpuContext::new().await?, config, raster)
+    }
+
+    pub fn with_context(context: &GpuContext, config: BeamformingConfig, raster: Raster) -> Result<Self> {
+        config::validate_raster(&config, &raster)?;
+        let input_size = config.grid_width as u64 * config.grid_depth as u64 * 4;
+        let output_size = shader::scan_convert::scan_convert_threads(&raster) as u64 * 4;
+        context.check_binding("raster", output_size)?;
+        let device = context.device();
+
+        gpu::push_error_scopes(device);
+
+        let shader = gpu::shader_module(device);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                gpu::storage_entry(0, true),
+                gpu::uniform_entry(1),
+                gpu::uniform_entry(2),
+                gpu::storage_entry(3, false),
+            ],
+        });
+        let pipeline = gpu::compute_pipeline(device, &shader, &layout, "scan_convert_shader");
+
+        let input = context.buffer(input_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let output = context.buffer(output_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let staging = context.buffer(output_size, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+        let config_buffer = context.uniform(&config);
+        let raster_buffer = context.uniform(&raster);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: input.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: config_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: raster_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: output.as_entire_binding() },
+            ],
+        });
+        let threads = shader::scan_convert::scan_convert_threads(&raster);
+        let workgroups = gpu::workgroups(threads, device.limits().max_compute_workgroups_per_dimension);
+
+        gpu::pop_error_scopes(device)?;
+
+        Ok(Self { context: context.clone(), raster, pipeline, input, output, staging, bind_group, workgroups, config })
+    }
+
+    pub fn raster(&self) -> &Raster {
+        &self.raster
+    }
+
+    /// Resamples a real image on the grid of the config, laid out
+    /// `[depth][width]` like a B-mode frame, onto the raster.
+    pub fn process(&mut self, image: &[f32]) -> Result<Frame> {
+        check_image(&self.config, image)?;
+        self.context.upload(&self.input, image)?;
+        let mut encoder = self.context.encoder();
+        self.dispatch(&mut encoder)?;
+        self.process_encoded(encoder)
+    }
+
+    /// Records the conversion of an image already on the device, laid out
+    /// like those of [`process`](Self::process), e.g. the
+    /// [`image_buffer`](crate::GpuBeamformer::image_buffer) of a beamformer on
+    /// the same context after its `encode_bmode`.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, image: &wgpu::Buffer) -> Result<()> {
+        let pixels = self.config.grid_width as usize * self.config.grid_depth as usize;
+        self.context.copy_values::<f32>(encoder, image, &self.input, 0, pixels)?;
+        self.dispatch(encoder)
+    }
+
+    /// Submits `encoder` and reads back the raster its recorded conversion
+    /// produced.
+    pub fn process_encoded(&mut self, encoder: wgpu::CommandEncoder) -> Result<Frame> {
+        let data = self.context.read_back(encoder, &self.output, &self.staging, self.staging.size())?;
+        Ok(Frame::new(self.raster.width as usize, self.raster.depth as usize, data))
+    }
+
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        self.context.dispatch(encoder, &self.bind_group, &[(&self.pipeline, self.workgroups)])
+    }
+}
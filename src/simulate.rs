@@ -0,0 +1,165 @@
+//! Synthetic RF data for demos and tests.
+
+use std::f32::consts::PI;
+
+use shared::{input_format, BeamformingConfig, TransmitEvent};
+
+/// Echoes of point scatterers at `(x, z)` positions after a single 0° plane
+/// wave, recorded as `[channel][sample]`. Each echo is a unit impulse at the
+/// nearest sample.
+pub fn point_targets(config: &BeamformingConfig, targets: &[(f32, f32)]) -> Vec<f32> {
+    let num_channels = config.num_channels as usize;
+    let num_samples = config.num_samples as usize;
+    let transmit = TransmitEvent::default();
+    let mut rf = vec![0.0f32; num_channels * num_samples];
+    for &(x, z) in targets {
+        for c in 0..num_channels {
+            let tof = shader::time_of_flight(config, &transmit, x, z, shader::element_position(config, c));
+            let s = ((tof - config.start_time) * config.sampling_frequency).round();
+            if s >= 0.0 && (s as usize) < num_samples {
+                rf[c * num_samples + s as usize] += 1.0;
+            }
+        }
+    }
+    rf
+}
+
+/// Echoes of point scatterers at `(x, z)` positions after a single 0° plane
+/// wave, recorded as `[channel][sample]`, using a Gaussian-modulated sinusoid
+/// with the given centre frequency (Hz) and -6 dB fractional bandwidth. The
+/// pulse is evaluated at the exact time of flight, so echoes are not quantized
+/// to the sample grid.
+pub fn pulse_echoes(
+    config: &BeamformingConfig,
+    targets: &[(f32, f32)],
+    center_frequency: f32,
+    fractional_bandwidth: f32,
+) -> Vec<f32> {
+    transmit_echoes(config, &[TransmitEvent::default()], targets, center_frequency, fractional_bandwidth)
+}
+
+/// The same echoes as [`pulse_echoes`], demodulated to complex baseband at
+/// `config.demodulation_frequency` and laid out as `config.input_format`
+/// describes (interleaved or planar IQ).
+pub fn pulse_echoes_iq(
+    config: &BeamformingConfig,
+    targets: &[(f32, f32)],
+    center_frequency: f32,
+    fractional_bandwidth: f32,
+) -> Vec<f32> {
+    transmit_echoes_iq(config, &[TransmitEvent::default()], targets, center_frequency, fractional_bandwidth)
+}
+
+/// Echoes of point scatterers at `(x, z)` positions after a single 0° plane
+/// wave carrying an arbitrary excitation, such as [`filters::chirp`],
+/// recorded as `[channel][sample]`. `pulse` is sampled at the RF rate and
+/// centred on the time of flight, linearly interpolated between its samples.
+///
+/// [`filters::chirp`]: crate::filters::chirp
+pub fn coded_echoes(config: &BeamformingConfig, targets: &[(f32, f32)], pulse: &[f32]) -> Vec<f32> {
+    let num_channels = config.num_channels as usize;
+    let num_samples = config.num_samples as usize;
+    let transmit = TransmitEvent::default();
+    let half_length = (pulse.len() as f32 - 1.0) * 0.5;
+    let pulse_at = |i: isize| if i >= 0 && (i as usize) < pulse.len() { pulse[i as usize] } else { 0.0 };
+    let mut rf = vec![0.0f32; num_channels * num_samples];
+    for &(x, z) in targets {
+        for c in 0..num_channels {
+            let tof = shader::time_of_flight(config, &transmit, x, z, shader::element_position(config, c));
+            let start = (tof - config.start_time) * config.sampling_frequency - half_length;
+            let first = start.floor() as isize;
+            for s in first..=first + pulse.len() as isize {
+                if s < 0 || s as usize >= num_samples {
+                    continue;
+                }
+                let position = s as f32 - start;
+                let (i, frac) = (position.floor() as isize, position - position.floor());
+                rf[c * num_samples + s as usize] += pulse_at(i) + (pulse_at(i + 1) - pulse_at(i)) * frac;
+            }
+        }
+    }
+    rf
+}
+
+/// Pulse echoes as in [`pulse_echoes`] for each of `transmits`, recorded as
+/// `[transmit][channel][sample]`.
+pub fn transmit_echoes(
+    config: &BeamformingConfig,
+    transmits: &[TransmitEvent],
+    targets: &[(f32, f32)],
+    center_frequency: f32,
+    fractional_bandwidth: f32,
+) -> Vec<f32> {
+    let num_samples = config.num_samples as usize;
+    let mut rf = vec![0.0f32; transmits.len() * config.num_channels as usize * num_samples];
+    for_each_pulse_sample(config, transmits, targets, center_frequency, fractional_bandwidth, |trace, s, t, envelope| {
+        rf[trace * num_samples + s] += envelope * (2.0 * PI * center_frequency * t).cos();
+    });
+    rf
+}
+
+/// The same echoes as [`transmit_echoes`], demodulated to complex baseband as
+/// in [`pulse_echoes_iq`].
+pub fn transmit_echoes_iq(
+    config: &BeamformingConfig,
+    transmits: &[TransmitEvent],
+    targets: &[(f32, f32)],
+    center_frequency: f32,
+    fractional_bandwidth: f32,
+) -> Vec<f32> {
+    let num_samples = config.num_samples as usize;
+    let plane = transmits.len() * config.num_channels as usize * num_samples;
+    let planar = config.input_format == input_format::IQ_PLANAR;
+    let mut iq = vec![0.0f32; 2 * plane];
+    for_each_pulse_sample(config, transmits, targets, center_frequency, fractional_bandwidth, |trace, s, t, envelope| {
+        // Analytic pulse times the demodulation carrier at the absolute sample time
+        let sample_time = config.start_time + s as f32 / config.sampling_frequency;
+        let phase = 2.0 * PI * (center_frequency * t - config.demodulation_frequency * sample_time);
+        let (i, q) = (envelope * phase.cos(), envelope * phase.sin());
+        let index = trace * num_samples + s;
+        if planar {
+            iq[index] += i;
+            iq[plane + index] += q;
+        } else {
+            iq[2 * index] += i;
+            iq[2 * index + 1] += q;
+        }
+    });
+    iq
+}
+
+/// Calls `emit(trace, sample, t, envelope)` for every sample within four
+/// standard deviations of each echo, where `trace` is
+/// `transmit * num_channels + channel` and `t` is the time relative to the
+/// echo's arrival.
+fn for_each_pulse_sample(
+    config: &BeamformingConfig,
+    transmits: &[TransmitEvent],
+    targets: &[(f32, f32)],
+    center_frequency: f32,
+    fractional_bandwidth: f32,
+    mut emit: impl FnMut(usize, usize, f32, f32),
+) {
+    let num_channels = config.num_channels as usize;
+    let num_samples = config.num_samples as usize;
+    let sigma_f = fractional_bandwidth * center_frequency / (2.0 * (2.0 * 2.0f32.ln()).sqrt());
+    let sigma_t = 1.0 / (2.0 * PI * sigma_f);
+    let half_length = (4.0 * sigma_t * config.sampling_frequency).ceil() as isize;
+
+    for (index, transmit) in transmits.iter().enumerate() {
+        for &(x, z) in targets {
+            for c in 0..num_channels {
+                let tof = shader::time_of_flight(config, transmit, x, z, shader::element_position(config, c));
+                let center = (tof - config.start_time) * config.sampling_frequency;
+                let first = center.round() as isize - half_length;
+                for s in first..=first + 2 * half_length {
+                    if s < 0 || s as usize >= num_samples {
+                        continue;
+                    }
+                    let t = (s as f32 - center) / config.sampling_frequency;
+                    emit(index * num_channels + c, s as usize, t, (-t * t / (2.0 * sigma_t * sigma_t)).exp());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Spectral (pulsed-wave) Doppler: the beamformer reconstructs a single sample
+//! volume per acquisition, at a high pulse repetition frequency, and the host
+//! turns that slow-time signal into a spectrogram for display.
+//!
+//! The gate is beamformed by `main_shader` like any list of pixels, see
+//! [`Beamformer::set_sample_volume`](crate::Beamformer::set_sample_volume).
+
+use std::f32::consts::PI;
+
+use crate::config;
+use crate::error::{BeamformError, Result};
+use crate::fft::{CpuFft, FftDirection};
+use crate::frame::{Complex, Frame, IqFrame};
+use shared::{window, BeamformingConfig};
+
+/// Gate of pulsed-wave Doppler: `length` metres along z centred on `(x, z)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleVolume {
+    pub x: f32,
+    pub z: f32,
+    pub length: f32,
+}
+
+impl SampleVolume {
+    /// Points of the gate, a quarter wavelength at `config.center_frequency`
+    /// apart and centred on the sample volume. A gate shorter than that, or a
+    /// config without a centre frequency, gives the centre alone.
+    pub fn positions(&self, config: &BeamformingConfig) -> Vec<(f32, f32)> {
+        let spacing = config.speed_of_sound / (4.0 * config.center_frequency);
+        if !(spacing > 0.0 && spacing.is_finite()) {
+            return vec![(self.x, self.z)];
+        }
+        let half = ((0.5 * self.length / spacing) as i32).max(0);
+        (-half..=half).map(|i| (self.x, self.z + i as f32 * spacing)).collect()
+    }
+
+    /// Slow-time sample of one acquisition beamformed on the
+    /// [`positions`](Self::positions) of the gate. Beamformed IQ keeps the
+    /// carrier phase, which turns by `4 pi f0 dz / c` along depth, so the
+    /// points are brought to baseband relative to the centre before they are
+    /// averaged coherently. The frame must hold one pixel per point.
+    pub fn slow_time_sample(&self, config: &BeamformingConfig, frame: &IqFrame) -> Result<Complex> {
+        let positions = self.positions(config);
+        if frame.data.len() != positions.len() {
+            return Err(BeamformError::InvalidInput { expected: positions.len(), actual: frame.data.len() });
+        }
+        let wavenumber = 4.0 * PI * config.center_frequency / config.speed_of_sound;
+        let (mut re, mut im) = (0.0, 0.0);
+        for (&(_, z), sample) in positions.iter().zip(&frame.data) {
+            let (sin, cos) = (-wavenumber * (z - self.z)).sin_cos();
+            re += sample.re * cos - sample.im * sin;
+            im += sample.re * sin + sample.im * cos;
+        }
+        let count = positions.len() as f32;
+        Ok(Complex::new(re / count, im / count))
+    }
+}
+
+/// Taper of the slow-time windows of a [`Spectrogram`], trading the width of
+/// a spectral line for leakage into neighbouring velocities.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SpectralWindow {
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    /// Flat top with cosine tapers; `alpha` is the tapered fraction of the
+    /// window, from 0 (rectangular) to 1 (Hann).
+    Tukey { alpha: f32 },
+    /// One weight per sample of the window.
+    Custom(Vec<f32>),
+}
+
+impl SpectralWindow {
+    /// Weights at `len` evenly spaced samples from one edge of the window to
+    /// the other. Custom weights are returned as they are.
+    fn weights(&self, len: usize) -> Vec<f32> {
+        let (kind, alpha) = match self {
+            Self::Rectangular => (window::RECTANGULAR, 0.0),
+            Self::Hann => (window::HANN, 0.0),
+            Self::Hamming => (window::HAMMING, 0.0),
+            Self::Tukey { alpha } => (window::TUKEY, *alpha),
+            Self::Custom(weights) => return weights.clone(),
+        };
+        (0..len).map(|i| shader::window_weight(kind, alpha, i as f32 / len.saturating_sub(1).max(1) as f32)).collect()
+    }
+}
+
+/// Sliding-window FFT of the slow-time signal of a sample volume, log
+/// compressed for display like a B-mode image.
+pub struct Spectrogram {
+    config: BeamformingConfig,
+    window: Vec<f32>,
+    overlap: usize,
+}
+
+impl Spectrogram {
+    /// Spectrogram of windows of `len` slow-time samples acquired at
+    /// `config.pulse_repetition_frequency`, tapered by `window` and
+    /// overlapping by `overlap` samples. Custom windows hold one weight per
+    /// sample.
+    pub fn new(config: BeamformingConfig, len: usize, overlap: usize, window: SpectralWindow) -> Result<Self> {
+        config::validate_velocity_scale(&config)?;
+        if len < 2 || overlap >= len {
+            return Err(BeamformError::InvalidConfig(format!(
+                "spectrogram windows need at least 2 samples and less overlap than their length, \
+                 got {len} and {overlap}"
+            )));
+        }
+        let window = window.weights(len);
+        if window.len() != len {
+            return Err(BeamformError::InvalidConfig(format!(
+                "custom spectrogram window has {} weights for {len} samples",
+                window.len(),
+            )));
+        }
+        Ok(Self { config, window, overlap })
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    /// Samples per window, and rows of the spectrogram.
+    pub fn window_len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Samples between the starts of consecutive windows.
+    pub fn hop(&self) -> usize {
+        self.window.len() - self.overlap
+    }
+
+    /// Columns of the spectrogram of `samples` slow-time samples.
+    pub fn columns(&self, samples: usize) -> usize {
+        match samples.checked_sub(self.window_len()) {
+            Some(rest) => rest / self.hop() + 1,
+            None => 0,
+        }
+    }
+
+    /// Axial velocity (m/s, positive towards the probe) of row `row`: row 0
+    /// holds the fastest flow towards the probe, the last row the fastest
+    /// away from it, at minus the Nyquist velocity.
+    pub fn velocity(&self, row: usize) -> f32 {
+        let len = self.window_len();
+        let bin = (len - 1 - row) as f32 - (len / 2) as f32;
+        let frequency = bin * self.config.pulse_repetition_frequency / len as f32;
+        self.config.speed_of_sound * frequency / (2.0 * self.config.center_frequency)
+    }
+
+    /// Time (s) of the centre of the window of column `col`, from the first
+    /// sample.
+    pub fn time(&self, col: usize) -> f32 {
+        (col * self.hop()) as f32 / self.config.pulse_repetition_frequency
+            + (self.window_len() - 1) as f32 / (2.0 * self.config.pulse_repetition_frequency)
+    }
+
+    /// Spectrogram of a slow-time signal: one column per window and one row
+    /// per [`velocity`](Self::velocity), the magnitude of every bin log
+    /// compressed onto `[0, 1]` with `config.dynamic_range` and `config.gain`
+    /// below the peak of the whole spectrogram.
+    pub fn process(&self, samples: &[Complex]) -> Result<Frame> {
+        let len = self.window_len();
+        let columns = self.columns(samples.len());
+        if columns == 0 {
+            return Err(BeamformError::InvalidInput { expected: len, actual: samples.len() });
+        }
+        let windowed: Vec<Complex> = (0..columns)
+            .flat_map(|col| samples[col * self.hop()..][..len].iter().zip(&self.window))
+            .map(|(sample, &weight)| Complex::new(sample.re * weight, sample.im * weight))
+            .collect();
+        let spectra = CpuFft::new(len, columns)?.process(&windowed, FftDirection::Forward)?;
+
+        let peak = spectra.iter().fold(0.0f32, |m, c| m.max(c.norm()));
+        let data = (0..len * columns)
+            .map(|i| {
+                let (row, col) = (i / columns, i % columns);
+                let bin = (2 * len - 1 - row - len / 2) % len;
+                shader::image::log_compress(&self.config, spectra[col * len + bin].norm(), peak)
+            })
+            .collect();
+        Ok(Frame::new(columns, len, data))
+    }
+}
@@ -0,0 +1,384 @@
+//! Spatiotemporal (SVD) clutter filtering of Doppler ensembles, on the GPU
+//! with a CPU reference running the same kernel code.
+//!
+//! Tissue moves slowly and coherently over large regions, so it fills the
+//! strongest singular components of the Casorati matrix of pixels by frames,
+//! where a high-pass filter along slow time cannot separate it from slow
+//! flow. The time covariance is computed by the kernels, decomposed on the
+//! host, and the ensemble projected onto the kept components by the kernels
+//! again. The filtered ensemble feeds [`CpuDoppler`](crate::CpuDoppler) or
+//! [`GpuDoppler`](crate::GpuDoppler).
+
+use std::ops::Range;
+
+use crate::config;
+use crate::doppler::{copy_frame, flatten};
+use crate::error::{BeamformError, Result};
+use crate::frame::{Complex, IqFrame};
+use crate::gpu::{self, GpuContext};
+use shader::glam::Vec2;
+use shared::BeamformingConfig;
+
+/// Singular components an SVD clutter filter keeps, sorted by decreasing
+/// singular value. The strongest hold tissue, the weakest noise.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SvdCutoff {
+    /// Keeps the components of the range, removing tissue before it and
+    /// noise after it.
+    Keep(Range<usize>),
+    /// Removes the components before the knee of the singular values in dB,
+    /// the point furthest below the line joining the first and the last
+    /// resolved one, and keeps the rest.
+    #[default]
+    Automatic,
+}
+
+impl SvdCutoff {
+    fn validate(&self, config: &BeamformingConfig) -> Result<()> {
+        match self {
+            Self::Keep(range) if range.is_empty() || range.end > config.ensemble_length as usize => {
+                Err(BeamformError::InvalidConfig(format!(
+                    "SVD filter must keep a non-empty range of the {} components, got {range:?}",
+                    config.ensemble_length,
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Components kept for the given singular values, in decreasing order.
+    fn kept(&self, singular_values: &[f32]) -> Range<usize> {
+        match self {
+            Self::Keep(range) => range.clone(),
+            Self::Automatic => knee(singular_values)..singular_values.len(),
+        }
+    }
+}
+
+/// Singular values more than this far below the largest are lost in the
+/// rounding of the single-precision covariance, 60 dB.
+const RESOLUTION: f32 = 1e-3;
+
+/// Index of the knee of the singular values in dB, or 0 when the curve never
+/// dips below the line joining its ends. The curve ends at the last value
+/// above the [`RESOLUTION`] of the covariance.
+fn knee(singular_values: &[f32]) -> usize {
+    let last = singular_values.iter().rposition(|&s| s > RESOLUTION * singular_values[0]).unwrap_or(0);
+    let peak = singular_values[0] as f64;
+    let db: Vec<f64> = singular_values[..=last].iter().map(|&s| 20.0 * (s as f64 / peak).log10()).collect();
+    let chord = |k: usize| db[0] + (db[last] - db[0]) * k as f64 / last as f64;
+    let dip = |k: usize| chord(k) - db[k];
+    (1..last).filter(|&k| dip(k) > 0.0).max_by(|&i, &j| dip(i).total_cmp(&dip(j))).unwrap_or(0)
+}
+
+/// An ensemble after SVD clutter filtering.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvdFiltered {
+    /// The filtered frames, on the grid of the input.
+    pub ensemble: Vec<IqFrame>,
+    /// Singular values of the ensemble, in decreasing order.
+    pub singular_values: Vec<f32>,
+    /// Components kept by the filter.
+    pub kept: Range<usize>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct C64 {
+    re: f64,
+    im: f64,
+}
+
+impl C64 {
+    fn conj(self) -> Self {
+        Self { re: self.re, im: -self.im }
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self { re: self.re * other.re - self.im * other.im, im: self.re * other.im + self.im * other.re }
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self { re: self.re + other.re, im: self.im + other.im }
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        Self { re: self.re * factor, im: self.im * factor }
+    }
+
+    fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+}
+
+/// Eigenvalues of the Hermitian `n x n` matrix `matrix`, stored row by row,
+/// in decreasing order, with the matching unit eigenvectors as the columns of
+/// the second matrix. Cyclic complex Jacobi rotations in double precision,
+/// which stay accurate for the small, ill-conditioned covariances of
+/// ensembles dominated by tissue.
+fn hermitian_eigen(matrix: &[Complex], n: usize) -> (Vec<f64>, Vec<C64>) {
+    let mut a: Vec<C64> = matrix.iter().map(|c| C64 { re: c.re as f64, im: c.im as f64 }).collect();
+    let mut v = vec![C64::default(); n * n];
+    for i in 0..n {
+        v[i * n + i].re = 1.0;
+    }
+    let total: f64 = a.iter().map(|c| c.norm() * c.norm()).sum();
+
+    for _ in 0..64 {
+        let off_diagonal: f64 = (0..n * n).filter(|i| i / n != i % n).map(|i| a[i].norm().powi(2)).sum();
+        if off_diagonal <= 1e-28 * total {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let magnitude = a[p * n + q].norm();
+                if magnitude <= 1e-300 {
+                    continue;
+                }
+                // Rotation J, with J_pp = J_qq = c, J_pq = s e^(i phi) and
+                // J_qp = -s e^(-i phi), so that J^H A J zeroes entry (p, q)
+                let phase = a[p * n + q].scale(1.0 / magnitude);
+                let theta = (a[q * n + q].re - a[p * n + p].re) / (2.0 * magnitude);
+                let t = theta.signum() / (theta.abs() + theta.hypot(1.0));
+                let c = 1.0 / t.hypot(1.0);
+                let s = t * c;
+                let (jpq, jqp) = (phase.scale(s), phase.conj().scale(-s));
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = akp.scale(c).add(akq.mul(jqp));
+                    a[k * n + q] = akp.mul(jpq).add(akq.scale(c));
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = vkp.scale(c).add(vkq.mul(jqp));
+                    v[k * n + q] = vkp.mul(jpq).add(vkq.scale(c));
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = apk.scale(c).add(aqk.mul(jqp.conj()));
+                    a[q * n + k] = apk.mul(jpq.conj()).add(aqk.scale(c));
+                }
+            }
+        }
+    }
+
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| a[j * n + j].re.total_cmp(&a[i * n + i].re));
+    let values = order.iter().map(|&i| a[i * n + i].re).collect();
+    let vectors = (0..n * n).map(|i| v[i / n * n + order[i % n]]).collect();
+    (values, vectors)
+}
+
+/// Decomposes the time covariance of an ensemble of `n` frames and returns
+/// its singular values and the projection `I - sum v_k v_k^H` over the
+/// components `cutoff` removes, stored row by row for the kernels.
+fn decompose(covariance: &[Complex], n: usize, cutoff: &SvdCutoff) -> (Vec<f32>, Range<usize>, Vec<Complex>) {
+    let (values, vectors) = hermitian_eigen(covariance, n);
+    let singular_values: Vec<f32> = values.iter().map(|&value| value.max(0.0).sqrt() as f32).collect();
+    let kept = cutoff.kept(&singular_values);
+    let mut projection = vec![C64::default(); n * n];
+    for m in 0..n {
+        projection[m * n + m].re = 1.0;
+    }
+    for k in (0..n).filter(|k| !kept.contains(k)) {
+        for m in 0..n {
+            for col in 0..n {
+                let term = vectors[m * n + k].mul(vectors[col * n + k].conj());
+                projection[m * n + col] = projection[m * n + col].add(term.scale(-1.0));
+            }
+        }
+    }
+    let projection = projection.iter().map(|c| Complex::new(c.re as f32, c.im as f32)).collect();
+    (singular_values, kept, projection)
+}
+
+/// Splits a flat `[frame][pixel]` ensemble into frames of the grid.
+fn unflatten(config: &BeamformingConfig, data: &[Complex]) -> Vec<IqFrame> {
+    let (width, depth) = (config.grid_width as usize, config.grid_depth as usize);
+    data.chunks(width * depth).map(|frame| IqFrame::new(width, depth, frame.to_vec())).collect()
+}
+
+/// CPU reference SVD clutter filter, running the kernel functions of the
+/// `shader` crate one covariance entry and one sample at a time.
+pub struct CpuSvdFilter {
+    config: BeamformingConfig,
+    cutoff: SvdCutoff,
+}
+
+impl CpuSvdFilter {
+    /// Filters ensembles of `config.ensemble_length` frames on the grid of
+    /// `config`, keeping the components `cutoff` selects.
+    pub fn new(config: BeamformingConfig, cutoff: SvdCutoff) -> Result<Self> {
+        config::validate_ensemble(&config)?;
+        cutoff.validate(&config)?;
+        Ok(Self { config, cutoff })
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    pub fn cutoff(&self) -> &SvdCutoff {
+        &self.cutoff
+    }
+
+    /// Removes the clutter components from an ensemble of IQ frames.
+    pub fn process(&self, ensemble: &[IqFrame]) -> Result<SvdFiltered> {
+        let ensemble: Vec<Vec2> = flatten(&self.config, ensemble)?.iter().map(|c| Vec2::new(c.re, c.im)).collect();
+        let n = self.config.ensemble_length as usize;
+        let covariance: Vec<Complex> = (0..n * n)
+            .map(|i| shader::svd_filter::covariance_sum(&ensemble, &self.config, i / n, i % n, 0, 1))
+            .map(|c| Complex::new(c.x, c.y))
+            .collect();
+        let (singular_values, kept, projection) = decompose(&covariance, n, &self.cutoff);
+        let projection: Vec<Vec2> = projection.iter().map(|c| Vec2::new(c.re, c.im)).collect();
+        let filtered: Vec<Complex> = (0..shader::svd_filter::svd_filter_threads(&self.config))
+            .map(|i| shader::svd_filter::project(&ensemble, &projection, &self.config, i))
+            .map(|c| Complex::new(c.x, c.y))
+            .collect();
+        Ok(SvdFiltered { ensemble: unflatten(&self.config, &filtered), singular_values, kept })
+    }
+}
+
+/// GPU SVD clutter filter. The pipelines, buffers and bind group are created
+/// once; each call uploads the ensemble, reads its time covariance back for
+/// the decomposition, then uploads the projection and reads the filtered
+/// ensemble back. [`encode_frame`](GpuSvdFilter::encode_frame) gathers the
+/// ensemble from frames already on the device instead, e.g. those of a
+/// [`GpuBeamformer`](crate::GpuBeamformer) on the same [`GpuContext`].
+pub struct GpuSvdFilter {
+    context: GpuContext,
+    config: BeamformingConfig,
+    cutoff: SvdCutoff,
+    covariance_pipeline: wgpu::ComputePipeline,
+    filter_pipeline: wgpu::ComputePipeline,
+    input: wgpu::Buffer,
+    covariance: wgpu::Buffer,
+    projection: wgpu::Buffer,
+    output: wgpu::Buffer,
+    staging: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    workgroups: (u32, u32),
+}
+
+impl GpuSvdFilter {
+    /// Requests a GPU adapter and prepares the filtering of ensembles of
+    /// `config.ensemble_length` frames on the grid of `config`.
+    pub async fn new(config: BeamformingConfig, cutoff: SvdCutoff) -> Result<Self> {
+        Self::with_context(&GpuContext::new().await?, config, cutoff)
+    }
+
+    pub fn with_context(context: &GpuContext, config: BeamformingConfig, cutoff: SvdCutoff) -> Result<Self> {
+        config::validate_ensemble(&config)?;
+        cutoff.validate(&config)?;
+        let n = config.ensemble_length as usize;
+        let ensemble_size = (shader::svd_filter::svd_filter_threads(&config) * 8) as u64;
+        let matrix_size = (n * n * 8) as u64;
+        context.check_binding("Doppler ensemble", ensemble_size)?;
+        let device = context.device();
+        let max_groups = device.limits().max_compute_workgroups_per_dimension;
+        if n > max_groups as usize {
+            return Err(BeamformError::InvalidConfig(format!(
+                "ensemble of {n} frames exceeds the device limit for the covariance stage"
+            )));
+        }
+
+        gpu::push_error_scopes(device);
+
+        let shader = gpu::shader_module(device);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                gpu::storage_entry(0, true),
+                gpu::uniform_entry(1),
+                gpu::storage_entry(2, false),
+                gpu::storage_entry(3, true),
+                gpu::storage_entry(4, false),
+            ],
+        });
+        let covariance_pipeline = gpu::compute_pipeline(device, &shader, &layout, "covariance_shader");
+        let filter_pipeline = gpu::compute_pipeline(device, &shader, &layout, "svd_filter_shader");
+
+        let input = context.buffer(ensemble_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let covariance = context.buffer(matrix_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let projection = context.buffer(matrix_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST);
+        let output = context.buffer(ensemble_size, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let staging_size = ensemble_size.max(matrix_size);
+        let staging = context.buffer(staging_size, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+        let config_buffer = context.uniform(&config);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: input.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: config_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: covariance.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: projection.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: output.as_entire_binding() },
+            ],
+        });
+        let workgroups = gpu::workgroups(shader::svd_filter::svd_filter_threads(&config), max_groups);
+
+        gpu::pop_error_scopes(device)?;
+
+        Ok(Self {
+            context: context.clone(),
+            config,
+            cutoff,
+            covariance_pipeline,
+            filter_pipeline,
+            input,
+            covariance,
+            projection,
+            output,
+            staging,
+            bind_group,
+            workgroups,
+        })
+    }
+
+    pub fn config(&self) -> &BeamformingConfig {
+        &self.config
+    }
+
+    pub fn cutoff(&self) -> &SvdCutoff {
+        &self.cutoff
+    }
+
+    /// Removes the clutter components from an ensemble of IQ frames.
+    pub fn process(&mut self, ensemble: &[IqFrame]) -> Result<SvdFiltered> {
+        self.context.upload(&self.input, &flatten(&self.config, ensemble)?)?;
+        self.process_encoded(self.context.encoder())
+    }
+
+    /// Records the copy of frame `index` of the ensemble from a buffer on the
+    /// device, see [`GpuDoppler::encode_frame`](crate::GpuDoppler::encode_frame).
+    pub fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, index: usize, frame: &wgpu::Buffer) -> Result<()> {
+        copy_frame(&self.context, encoder, &self.config, &self.input, index, frame)
+    }
+
+    /// Submits `encoder` with the covariance stage, for an ensemble gathered
+    /// with [`encode_frame`](Self::encode_frame), then filters the ensemble
+    /// and reads it back.
+    pub fn process_encoded(&mut self, encoder: wgpu::CommandEncoder) -> Result<SvdFiltered> {
+        // One workgroup per covariance entry
+        let n = self.config.ensemble_length;
+        let covariance = self.dispatch(encoder, &self.covariance_pipeline, (n, n), &self.covariance)?;
+        let (singular_values, kept, projection) = decompose(&covariance, n as usize, &self.cutoff);
+
+        self.context.upload(&self.projection, &projection)?;
+        let filtered = self.dispatch(self.context.encoder(), &self.filter_pipeline, self.workgroups, &self.output)?;
+        Ok(SvdFiltered { ensemble: unflatten(&self.config, &filtered), singular_values, kept })
+    }
+
+    /// Runs `pipeline` over `workgroups` after the work in `encoder` and reads
+    /// `source` back.
+    fn dispatch(
+        &self,
+        mut encoder: wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        workgroups: (u32, u32),
+        source: &wgpu::Buffer,
+    ) -> Result<Vec<Complex>> {
+        self.context.dispatch(&mut encoder, &self.bind_group, &[(pipeline, workgroups)])?;
+        self.context.read_back(encoder, source, &self.staging, source.size())
+    }
+}
@@ -0,0 +1,35 @@
+use shader::glam::Vec2;
+use shared::{tgc, BeamformingConfig};
+
+/// Time-gain compensation applied to the channel data before beamforming.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Tgc {
+    #[default]
+    Off,
+    /// Cancels the round-trip attenuation of `coefficient` dB/cm/MHz at
+    /// `frequency` (Hz), usually the centre frequency of the pulse.
+    Attenuation { coefficient: f32, frequency: f32 },
+    /// Gain (dB) at each depth (m), as `(depth, gain)` points sorted by depth.
+    /// It is interpolated linearly between them and held constant beyond the
+    /// first and last.
+    Curve(Vec<(f32, f32)>),
+}
+
+impl Tgc {
+    /// Writes the TGC selection into `config` and returns the points to
+    /// upload to the TGC buffer (empty unless a curve).
+    pub(crate) fn apply(self, config: &mut BeamformingConfig) -> Vec<Vec2> {
+        let (mode, points) = match self {
+            Self::Off => (tgc::OFF, Vec::new()),
+            Self::Attenuation { coefficient, frequency } => {
+                config.tgc_attenuation = coefficient;
+                config.tgc_frequency = frequency;
+                (tgc::ATTENUATION, Vec::new())
+            }
+            Self::Curve(points) => (tgc::CURVE, points.into_iter().map(|(depth, gain)| Vec2::new(depth, gain)).collect()),
+        };
+        config.tgc = mode;
+        config.num_tgc_points = points.len() as u32;
+        points
+    }
+}
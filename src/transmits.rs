@@ -0,0 +1,13 @@
+//! Ready-made transmit sequences for [`set_transmits`](crate::Beamformer::set_transmits).
+
+use shared::{BeamformingConfig, TransmitEvent};
+
+/// One [`TransmitEvent::element`] per element of the array, in channel order.
+/// The frame is then the full `[tx_element][rx_channel][sample]` data set of
+/// a synthetic transmit aperture acquisition, focused in both transmit and
+/// receive at every pixel.
+pub fn synthetic_aperture(config: &BeamformingConfig) -> Vec<TransmitEvent> {
+    (0..config.num_channels as usize)
+        .map(|element| TransmitEvent::element(shader::element_position(config, element)))
+        .collect()
+}
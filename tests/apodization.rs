@@ -0,0 +1,87 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, peak_sidelobe_db, test_config};
+use rust_gpu_app::{simulate, Apodization, BeamformError, BeamformingConfig, CpuBeamformer};
+
+/// A lateral cut through a point target at (0, 20 mm): 0.1 mm columns over
+/// +/-10 mm and 0.05 mm rows over +/-1 mm in depth.
+fn lateral_config() -> BeamformingConfig {
+    BeamformingConfig {
+        grid_origin_x: -10.0e-3,
+        grid_origin_z: 19.0e-3,
+        grid_spacing_x: 0.1e-3,
+        grid_spacing_z: 0.05e-3,
+        grid_width: 201,
+        grid_depth: 41,
+        ..test_config()
+    }
+}
+
+fn point_target_psl(apodization: Apodization) -> f32 {
+    let config = lateral_config();
+    let rf = simulate::pulse_echoes(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_apodization(apodization).unwrap();
+    let frame = cpu.process(&rf).unwrap();
+    peak_sidelobe_db(&frame, &config)
+}
+
+#[test]
+fn windows_reduce_sidelobes() {
+    let rectangular = point_target_psl(Apodization::Rectangular);
+    let hann = point_target_psl(Apodization::Hann);
+    let hamming = point_target_psl(Apodization::Hamming);
+    let tukey = point_target_psl(Apodization::Tukey { alpha: 0.5 });
+
+    assert!(hann < rectangular - 4.0, "Hann {hann:.1} dB vs rectangular {rectangular:.1} dB");
+    assert!(hamming < rectangular - 4.0, "Hamming {hamming:.1} dB vs rectangular {rectangular:.1} dB");
+    assert!(tukey < rectangular - 3.0, "Tukey {tukey:.1} dB vs rectangular {rectangular:.1} dB");
+}
+
+#[test]
+fn custom_weights_match_builtin_window() {
+    let config = test_config();
+    let n = config.num_channels as usize;
+    let hann: Vec<f32> = (0..n)
+        .map(|c| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * c as f32 / (n - 1) as f32).cos())
+        .collect();
+    let rf = simulate::pulse_echoes(&config, &[(1.0e-3, 19.0e-3)], 5.0e6, 0.6);
+
+    let mut builtin = CpuBeamformer::new(config).unwrap();
+    builtin.set_apodization(Apodization::Hann).unwrap();
+    let mut custom = CpuBeamformer::new(config).unwrap();
+    custom.set_apodization(Apodization::Custom(hann)).unwrap();
+
+    assert_frames_close(&custom.process(&rf).unwrap(), &builtin.process(&rf).unwrap(), 1e-5);
+}
+
+#[test]
+fn rejects_invalid_apodization() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+
+    let err = cpu.set_apodization(Apodization::Custom(vec![1.0; 3])).unwrap_err();
+    assert!(matches!(err, BeamformError::InvalidConfig(_)));
+
+    // Tukey tapers need 0 <= alpha <= 1
+    for alpha in [-0.1, 1.5, f32::NAN, f32::INFINITY] {
+        let err = cpu.set_apodization(Apodization::Tukey { alpha }).unwrap_err();
+        assert!(matches!(err, BeamformError::InvalidConfig(_)), "alpha {alpha}");
+    }
+    cpu.set_apodization(Apodization::Tukey { alpha: 1.0 }).unwrap();
+}
+
+#[test]
+fn gpu_matches_cpu_with_apodization() {
+    let config = test_config();
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let rf = noise((config.num_channels * config.num_samples) as usize, 3);
+    let weights = noise(config.num_channels as usize, 5);
+
+    for apodization in [Apodization::Tukey { alpha: 0.3 }, Apodization::Custom(weights)] {
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        cpu.set_apodization(apodization.clone()).unwrap();
+        gpu.set_apodization(apodization).unwrap();
+
+        assert_frames_close(&gpu.process(&rf).unwrap(), &cpu.process(&rf).unwrap(), 1e-5);
+    }
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use std::f32::consts::PI;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{input_format, interpolation, simulate, BeamformError, BeamformingConfig, CpuBeamformer};
+
+/// 20 um rows around the target at (0, 20 mm), fine enough to sample the
+/// beamformed 5 MHz RF axially.
+fn bmode_config() -> BeamformingConfig {
+    BeamformingConfig {
+        grid_origin_z: 19.0e-3,
+        grid_spacing_z: 0.02e-3,
+        grid_depth: 100,
+        interpolation: interpolation::CUBIC,
+        ..test_config()
+    }
+}
+
+#[test]
+fn hilbert_envelope_of_cosine_is_flat() {
+    let depth = 200;
+    let config = BeamformingConfig { grid_width: 1, grid_depth: depth, ..test_config() };
+    let column: Vec<f32> = (0..depth).map(|row| 3.0 * (2.0 * PI * 0.1 * row as f32 + 0.4).cos()).collect();
+
+    // Away from the ends, where the filter runs off the column
+    for row in 20..depth as usize - 20 {
+        let envelope = shader::image::envelope(&column, &config, 0, row);
+        assert!((envelope - 3.0).abs() < 0.06, "row {row}: {envelope}");
+    }
+}
+
+#[test]
+fn log_compression_spans_dynamic_range() {
+    let config = BeamformingConfig { dynamic_range: 40.0, ..test_config() };
+    let compress = |envelope: f32, config: &BeamformingConfig| shader::image::log_compress(config, envelope, 2.0);
+
+    assert_eq!(compress(2.0, &config), 1.0);
+    assert!((compress(0.2, &config) - 0.5).abs() < 1e-5);
+    assert_eq!(compress(0.01, &config), 0.0);
+    assert_eq!(compress(0.0, &config), 0.0);
+    // 20 dB of gain lifts the -20 dB level to the top
+    let brighter = BeamformingConfig { gain: 20.0, ..config };
+    assert_eq!(compress(0.2, &brighter), 1.0);
+    assert!((compress(0.02, &brighter) - 0.5).abs() < 1e-5);
+}
+
+#[test]
+fn point_target_is_brightest_pixel() {
+    let config = bmode_config();
+    let rf = simulate::pulse_echoes(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    let image = cpu.process_bmode(&rf).unwrap();
+
+    let (col, row, value) = image.peak();
+    assert_eq!(value, 1.0);
+    assert_eq!(col, 24);
+    assert!((row as i32 - 50).abs() <= 2, "peak at row {row}");
+    assert!(image.data.iter().all(|v| (0.0..=1.0).contains(v)));
+    // Unlike the raw RF sum, the envelope has no zero crossings at the target
+    assert!(image.get(24, 49) > 0.9 && image.get(24, 51) > 0.9);
+
+    let image_u8 = cpu.process_bmode_u8(&rf).unwrap();
+    let expected: Vec<u8> = image.data.iter().map(|&v| (v * 255.0).round() as u8).collect();
+    assert_eq!(image_u8.data, expected);
+}
+
+#[test]
+fn iq_envelope_is_magnitude() {
+    let config = BeamformingConfig {
+        input_format: input_format::IQ_INTERLEAVED,
+        demodulation_frequency: 5.0e6,
+        ..bmode_config()
+    };
+    let iq = simulate::pulse_echoes_iq(&config, &[(0.0, 20.0e-3), (2.0e-3, 19.5e-3)], 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    let image = cpu.process_bmode(&iq).unwrap();
+
+    let magnitude = cpu.process_iq(&iq).unwrap().magnitude();
+    let peak = magnitude.peak().2;
+    for (value, envelope) in image.data.iter().zip(&magnitude.data) {
+        let expected = shader::image::log_compress(&config, *envelope, peak);
+        assert!((value - expected).abs() < 1e-5, "{value} vs {expected}");
+    }
+}
+
+#[test]
+fn non_positive_dynamic_range_is_rejected() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+
+    for dynamic_range in [0.0, -10.0, f32::NAN] {
+        let err = cpu.set_config(BeamformingConfig { dynamic_range, ..test_config() }).unwrap_err();
+        assert!(matches!(err, BeamformError::InvalidConfig(_)), "{dynamic_range}");
+    }
+}
+
+#[test]
+fn gpu_matches_cpu_bmode() {
+    let Some(mut gpu) = gpu_beamformer(bmode_config()) else { return };
+    let iq_config = BeamformingConfig {
+        input_format: input_format::IQ_PLANAR,
+        demodulation_frequency: 5.0e6,
+        ..bmode_config()
+    };
+
+    for config in [bmode_config(), iq_config] {
+        gpu.set_config(config).unwrap();
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        let mut input = if config.input_format == input_format::RF {
+            simulate::pulse_echoes(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6)
+        } else {
+            simulate::pulse_echoes_iq(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6)
+        };
+        let background = noise(input.len(), 37);
+        for (value, n) in input.iter_mut().zip(background) {
+            *value += 0.01 * n;
+        }
+
+        assert_frames_close(&gpu.process_bmode(&input).unwrap(), &cpu.process_bmode(&input).unwrap(), 1e-3);
+        let gpu_u8 = gpu.process_bmode_u8(&input).unwrap();
+        let cpu_u8 = cpu.process_bmode_u8(&input).unwrap();
+        assert!(gpu_u8.data.iter().zip(&cpu_u8.data).all(|(a, b)| a.abs_diff(*b) <= 1));
+    }
+}
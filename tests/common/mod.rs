@@ -0,0 +1,108 @@
+//! Helpers shared by the integration tests.
+
+#![allow(dead_code)]
+
+use rust_gpu_app::{BeamformError, BeamformingConfig, Frame, GpuBeamformer, GpuContext, IqFrame};
+
+/// A 96-element probe (deliberately not a multiple of the workgroup size)
+/// imaging a 12 mm x 8 mm region with 0.25 mm pixels.
+pub fn test_config() -> BeamformingConfig {
+    BeamformingConfig {
+        speed_of_sound: 1540.0,
+        sampling_frequency: 40.0e6,
+        element_pitch: 0.3e-3,
+        start_time: 0.0,
+        grid_origin_x: -6.0e-3,
+        grid_origin_z: 15.0e-3,
+        grid_spacing_x: 0.25e-3,
+        grid_spacing_z: 0.25e-3,
+        grid_width: 48,
+        grid_depth: 32,
+        num_channels: 96,
+        num_samples: 1536,
+        ..Default::default()
+    }
+}
+
+/// Deterministic noise in `[-1, 1)` from a xorshift generator.
+pub fn noise(len: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed.max(1);
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 * 2.0 - 1.0
+        })
+        .collect()
+}
+
+/// Creates a GPU beamformer, or returns `None` when the machine has no
+/// adapter so GPU comparisons are skipped rather than failed. Set
+/// `WGPU_BACKEND=vulkan` with lavapipe installed to run them headless.
+pub fn gpu_beamformer(config: BeamformingConfig) -> Option<GpuBeamformer> {
+    match pollster::block_on(GpuBeamformer::new(config)) {
+        Ok(gpu) => Some(gpu),
+        Err(BeamformError::NoAdapter) => {
+            eprintln!("no GPU adapter available, skipping GPU comparison");
+            None
+        }
+        Err(err) => panic!("failed to create GPU beamformer: {err}"),
+    }
+}
+
+/// Requests a device for stages that share it, or returns `None` when the
+/// machine has no adapter, like [`gpu_beamformer`].
+pub fn gpu_context() -> Option<GpuContext> {
+    match pollster::block_on(GpuContext::new()) {
+        Ok(context) => Some(context),
+        Err(BeamformError::NoAdapter) => {
+            eprintln!("no GPU adapter available, skipping GPU comparison");
+            None
+        }
+        Err(err) => panic!("failed to create GPU context: {err}"),
+    }
+}
+
+/// Asserts that two frames agree to within `tolerance` of the largest
+/// magnitude in `expected`. The GPU sums channels in a different order, so
+/// bit-exact equality is not expected.
+pub fn assert_frames_close(actual: &Frame, expected: &Frame, tolerance: f32) {
+    assert_eq!((actual.width, actual.depth), (expected.width, expected.depth));
+    let scale = expected.data.iter().fold(0.0f32, |m, v| m.max(v.abs())).max(f32::MIN_POSITIVE);
+    for (i, (a, e)) in actual.data.iter().zip(&expected.data).enumerate() {
+        assert!(
+            (a - e).abs() <= tolerance * scale,
+            "pixel {} (col {}, row {}): {a} vs {e}",
+            i,
+            i % expected.width,
+            i / expected.width,
+        );
+    }
+}
+
+/// [`assert_frames_close`] for complex frames, comparing the magnitude of
+/// the difference against the largest magnitude in `expected`.
+pub fn assert_iq_frames_close(actual: &IqFrame, expected: &IqFrame, tolerance: f32) {
+    assert_eq!((actual.width, actual.depth), (expected.width, expected.depth));
+    let scale = expected.data.iter().fold(0.0f32, |m, c| m.max(c.norm())).max(f32::MIN_POSITIVE);
+    for (i, (a, e)) in actual.data.iter().zip(&expected.data).enumerate() {
+        let error = (a.re - e.re).hypot(a.im - e.im);
+        assert!(error <= tolerance * scale, "pixel {i}: {a:?} vs {e:?}");
+    }
+}
+
+/// Peak sidelobe level (dB relative to the main lobe) more than 1 mm from
+/// x = 0, taking each column's maximum magnitude over depth.
+pub fn peak_sidelobe_db(frame: &Frame, config: &BeamformingConfig) -> f32 {
+    let profile: Vec<f32> = (0..frame.width)
+        .map(|col| (0..frame.depth).fold(0.0f32, |m, row| m.max(frame.get(col, row).abs())))
+        .collect();
+    let main_lobe = profile.iter().fold(0.0f32, |m, &v| m.max(v));
+    let sidelobe = profile
+        .iter()
+        .enumerate()
+        .filter(|(col, _)| (config.grid_origin_x + *col as f32 * config.grid_spacing_x).abs() > 1.0e-3)
+        .fold(0.0f32, |m, (_, &v)| m.max(v));
+    20.0 * (sidelobe / main_lobe).log10()
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, peak_sidelobe_db, test_config};
+use rust_gpu_app::{interpolation, simulate, BeamformError, BeamformingConfig, CpuBeamformer, TransmitEvent};
+
+/// `count` plane waves evenly spread over `[-max_angle, max_angle]` (rad).
+fn angle_sweep(count: usize, max_angle: f32) -> Vec<TransmitEvent> {
+    (0..count)
+        .map(|i| TransmitEvent::plane_wave(-max_angle + 2.0 * max_angle * i as f32 / (count - 1) as f32))
+        .collect()
+}
+
+#[test]
+fn steered_transmits_focus_coherently() {
+    let config = BeamformingConfig { interpolation: interpolation::SINC, ..test_config() };
+    let transmits = angle_sweep(11, 10.0f32.to_radians());
+    let rf = simulate::transmit_echoes(&config, &transmits, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_transmits(&transmits).unwrap();
+    let frame = cpu.process(&rf).unwrap();
+
+    // Every transmit and channel adds the pulse peak in phase at the target
+    let (col, row, value) = frame.peak();
+    let expected = (transmits.len() * config.num_channels as usize) as f32;
+    assert_eq!((col, row), (24, 20));
+    assert!((value - expected).abs() < 0.01 * expected, "peak {value} vs {expected}");
+    assert_eq!(cpu.config().num_transmits, 11);
+}
+
+#[test]
+fn compounding_lowers_sidelobes() {
+    let config = BeamformingConfig {
+        grid_origin_x: -10.0e-3,
+        grid_origin_z: 19.0e-3,
+        grid_spacing_x: 0.1e-3,
+        grid_spacing_z: 0.05e-3,
+        grid_width: 201,
+        grid_depth: 41,
+        ..test_config()
+    };
+    let psl = |transmits: &[TransmitEvent]| {
+        let rf = simulate::transmit_echoes(&config, transmits, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        cpu.set_transmits(transmits).unwrap();
+        peak_sidelobe_db(&cpu.process(&rf).unwrap(), &config)
+    };
+
+    let single = psl(&[TransmitEvent::plane_wave(0.0)]);
+    let compounded = psl(&angle_sweep(15, 15.0f32.to_radians()));
+
+    assert!(compounded < single - 10.0, "compounded {compounded:.1} dB vs single {single:.1} dB");
+}
+
+#[test]
+fn input_must_hold_every_transmit() {
+    let config = test_config();
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_transmits(&angle_sweep(3, 0.1)).unwrap();
+
+    let err = cpu.process(&simulate::point_targets(&config, &[(0.0, 20.0e-3)])).unwrap_err();
+
+    let per_transmit = (config.num_channels * config.num_samples) as usize;
+    assert!(matches!(err, BeamformError::InvalidInput { expected, .. } if expected == 3 * per_transmit));
+}
+
+#[test]
+fn invalid_transmits_are_rejected() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+
+    for transmits in [vec![], vec![TransmitEvent::plane_wave(std::f32::consts::FRAC_PI_2)]] {
+        let err = cpu.set_transmits(&transmits).unwrap_err();
+        assert!(matches!(err, BeamformError::InvalidConfig(_)), "{transmits:?}");
+    }
+    assert_eq!(cpu.config().num_transmits, 1);
+}
+
+#[test]
+fn gpu_matches_cpu_with_compounding() {
+    let config = BeamformingConfig { interpolation: interpolation::LINEAR, ..test_config() };
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let mut transmits = angle_sweep(5, 12.0f32.to_radians());
+    transmits[1].origin_x = -3.0e-3;
+    transmits[3].origin_z = 1.0e-3;
+    let rf = noise(transmits.len() * (config.num_channels * config.num_samples) as usize, 23);
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_transmits(&transmits).unwrap();
+    gpu.set_transmits(&transmits).unwrap();
+
+    assert_frames_close(&gpu.process(&rf).unwrap(), &cpu.process(&rf).unwrap(), 1e-5);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{simulate, Backend, BeamformError, Beamformer, BeamformingConfig, CpuBeamformer, GpuBeamformer};
+
+#[test]
+fn cpu_focuses_point_target() {
+    let config = test_config();
+    let rf = simulate::point_targets(&config, &[(0.0, 20.0e-3)]);
+
+    let frame = CpuBeamformer::new(config).unwrap().process(&rf).unwrap();
+
+    let (col, row, value) = frame.peak();
+    assert_eq!((col, row), (24, 20));
+    assert_eq!(value, config.num_channels as f32);
+}
+
+#[test]
+fn cpu_rejects_wrong_input_length() {
+    let config = test_config();
+    let rf = vec![0.0; 10];
+
+    let err = CpuBeamformer::new(config).unwrap().process(&rf).unwrap_err();
+
+    assert!(matches!(err, BeamformError::InvalidInput { expected: 147456, actual: 10 }));
+}
+
+#[test]
+fn rejects_empty_channel_data_and_invalid_rates() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+    for config in [
+        BeamformingConfig::default(),
+        BeamformingConfig { num_channels: 0, ..test_config() },
+        BeamformingConfig { speed_of_sound: 0.0, ..test_config() },
+        BeamformingConfig { sampling_frequency: f32::INFINITY, ..test_config() },
+    ] {
+        assert!(matches!(cpu.set_config(config), Err(BeamformError::InvalidConfig(_))));
+        assert!(matches!(CpuBeamformer::new(config), Err(BeamformError::InvalidConfig(_))));
+        let result = pollster::block_on(Beamformer::new(config, Backend::Cpu));
+        assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+    }
+
+    match pollster::block_on(GpuBeamformer::new(BeamformingConfig::default())) {
+        Err(BeamformError::InvalidConfig(_) | BeamformError::NoAdapter) => {}
+        Err(err) => panic!("expected an invalid config, got {err}"),
+        Ok(_) => panic!("expected an invalid config"),
+    }
+}
+
+#[test]
+fn rejects_grids_with_too_many_pixels() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+    let config = BeamformingConfig { grid_width: 1 << 16, grid_depth: 1 << 16, ..test_config() };
+
+    assert!(matches!(cpu.set_config(config), Err(BeamformError::InvalidConfig(_))));
+}
+
+#[test]
+fn gpu_matches_cpu_on_point_targets_and_noise() {
+    let config = test_config();
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let mut rf = simulate::point_targets(&config, &[(0.0, 20.0e-3), (-3.0e-3, 17.0e-3), (4.0e-3, 21.5e-3)]);
+    let noise = noise(rf.len(), 7);
+    for (sample, n) in rf.iter_mut().zip(noise) {
+        *sample += 0.1 * n;
+    }
+
+    let expected = CpuBeamformer::new(config).unwrap().process(&rf).unwrap();
+    let actual = gpu.process(&rf).unwrap();
+
+    assert_frames_close(&actual, &expected, 1e-5);
+}
+
+#[test]
+fn gpu_matches_cpu_after_resize() {
+    let Some(mut gpu) = gpu_beamformer(test_config()) else { return };
+    let config = rust_gpu_app::BeamformingConfig { num_channels: 192, grid_width: 40, ..test_config() };
+    gpu.set_config(config).unwrap();
+    let rf = noise((config.num_channels * config.num_samples) as usize, 11);
+
+    let expected = CpuBeamformer::new(config).unwrap().process(&rf).unwrap();
+    let actual = gpu.process(&rf).unwrap();
+
+    assert_frames_close(&actual, &expected, 1e-5);
+}
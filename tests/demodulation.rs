@@ -0,0 +1,106 @@
+mod common;
+
+use common::{assert_frames_close, assert_iq_frames_close, gpu_beamformer, test_config};
+use rust_gpu_app::{filters, input_format, interpolation, simulate, BeamformError, BeamformingConfig, CpuBeamformer};
+
+const TARGETS: [(f32, f32); 2] = [(0.0, 20.0e-3), (-2.0e-3, 17.0e-3)];
+
+/// RF at 40 MHz, demodulated at the 5 MHz centre frequency of the echoes.
+fn rf_config() -> BeamformingConfig {
+    BeamformingConfig { demodulation_frequency: 5.0e6, interpolation: interpolation::CUBIC, ..test_config() }
+}
+
+/// Low-pass keeping the baseband of the 60 % bandwidth echoes and rejecting
+/// the image at twice the carrier.
+fn low_pass(config: &BeamformingConfig) -> Vec<f32> {
+    filters::low_pass(config, 4.0e6, 63)
+}
+
+#[test]
+fn demodulated_rf_matches_iq_input() {
+    let config = rf_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_demodulation(4, &low_pass(&config)).unwrap();
+    let demodulated = cpu.process_iq(&rf).unwrap();
+
+    let iq_config = BeamformingConfig { input_format: input_format::IQ_INTERLEAVED, ..config };
+    let iq = simulate::pulse_echoes_iq(&iq_config, &TARGETS, 5.0e6, 0.6);
+    let expected = CpuBeamformer::new(iq_config).unwrap().process_iq(&iq).unwrap();
+
+    assert_iq_frames_close(&demodulated, &expected, 0.05);
+    let target = demodulated.get(24, 20);
+    assert!(target.arg().abs() < 0.1, "{target:?}");
+    assert_eq!(demodulated.magnitude().peak().0, 24);
+    assert_eq!(demodulated.magnitude().peak().1, 20);
+}
+
+#[test]
+fn filter_runs_before_demodulation() {
+    let config = rf_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let samples = config.num_samples as usize;
+    let delayed: Vec<f32> = (0..rf.len()).map(|i| if i.is_multiple_of(samples) { 0.0 } else { rf[i - 1] }).collect();
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_demodulation(4, &low_pass(&config)).unwrap();
+    let expected = cpu.process_iq(&delayed).unwrap();
+
+    // Delays the RF by one sample, which demodulation must then see
+    cpu.set_filter(&[0.0, 0.0, 1.0]).unwrap();
+    assert_eq!(cpu.process_iq(&rf).unwrap(), expected);
+}
+
+#[test]
+fn demodulated_frames_come_from_process_iq() {
+    let config = rf_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_demodulation(4, &low_pass(&config)).unwrap();
+    assert_eq!((cpu.config().decimation, cpu.config().num_demodulation_taps), (4, 63));
+
+    assert!(matches!(cpu.process(&rf), Err(BeamformError::InvalidConfig(_))));
+    let result = cpu.process_iq(&rf[1..]);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { actual, .. }) if actual == rf.len() - 1));
+    let (col, row, _) = cpu.process_bmode(&rf).unwrap().peak();
+    assert_eq!((col, row), (24, 20));
+
+    cpu.set_demodulation(0, &[]).unwrap();
+    assert!(cpu.process(&rf).is_ok());
+}
+
+#[test]
+fn rejects_invalid_demodulation() {
+    let config = rf_config();
+    let taps = low_pass(&config);
+    let invalid = |config: BeamformingConfig, decimation: u32, taps: &[f32]| {
+        let result = CpuBeamformer::new(config).unwrap().set_demodulation(decimation, taps);
+        matches!(result, Err(BeamformError::InvalidConfig(_)))
+    };
+
+    assert!(invalid(BeamformingConfig { input_format: input_format::IQ_INTERLEAVED, ..config }, 4, &taps));
+    assert!(invalid(BeamformingConfig { demodulation_frequency: 0.0, ..config }, 4, &taps));
+    assert!(invalid(config, 4, &[]));
+    assert!(invalid(config, 0, &taps));
+    assert!(invalid(config, 4, &[f32::INFINITY]));
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let result = cpu.set_config(BeamformingConfig { decimation: 2, num_demodulation_taps: 5, ..config });
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+}
+
+#[test]
+fn gpu_matches_cpu_with_demodulation() {
+    let config = rf_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    for taps in [Vec::new(), filters::bandpass(&config, 2.0e6, 8.0e6, 31)] {
+        gpu.set_filter(&taps).unwrap();
+        cpu.set_filter(&taps).unwrap();
+        gpu.set_demodulation(4, &low_pass(&config)).unwrap();
+        cpu.set_demodulation(4, &low_pass(&config)).unwrap();
+
+        assert_iq_frames_close(&gpu.process_iq(&rf).unwrap(), &cpu.process_iq(&rf).unwrap(), 1e-3);
+        assert_frames_close(&gpu.process_bmode(&rf).unwrap(), &cpu.process_bmode(&rf).unwrap(), 1e-3);
+    }
+}
@@ -0,0 +1,226 @@
+mod common;
+
+use common::{assert_frames_close, gpu_context, noise, test_config};
+use rust_gpu_app::{
+    input_format, nyquist_velocity, simulate, BeamformError, BeamformingConfig, Complex, CpuBeamformer, CpuDoppler,
+    Frame, GpuBeamformer, GpuDoppler, IqFrame,
+};
+
+/// 0.1 mm pixels around a target at (0, 20 mm), which sits in col 8, row 8,
+/// imaged at 5 kHz with 8 frames per ensemble.
+fn doppler_config() -> BeamformingConfig {
+    BeamformingConfig {
+        input_format: input_format::IQ_INTERLEAVED,
+        demodulation_frequency: 5.0e6,
+        grid_origin_x: -0.8e-3,
+        grid_origin_z: 19.2e-3,
+        grid_spacing_x: 0.1e-3,
+        grid_spacing_z: 0.1e-3,
+        grid_width: 16,
+        grid_depth: 16,
+        ensemble_length: 8,
+        pulse_repetition_frequency: 5.0e3,
+        center_frequency: 5.0e6,
+        ..test_config()
+    }
+}
+
+/// Beamformed frames of a target starting at `(x, 20 mm)` and moving towards
+/// the probe at `velocity`.
+fn moving_target(config: &BeamformingConfig, x: f32, velocity: f32) -> Vec<IqFrame> {
+    let mut cpu = CpuBeamformer::new(*config).unwrap();
+    (0..config.ensemble_length)
+        .map(|frame| {
+            let depth = 20.0e-3 - velocity * frame as f32 / config.pulse_repetition_frequency;
+            let iq = simulate::pulse_echoes_iq(config, &[(x, depth)], 5.0e6, 0.6);
+            cpu.process_iq(&iq).unwrap()
+        })
+        .collect()
+}
+
+/// Slow flow at (2 mm, 20 mm) under a static reflector 20 dB brighter at
+/// (-2 mm, 20 mm), on 0.2 mm pixels where they sit in cols 26 and 6 of row 8.
+fn flow_under_clutter(clutter_filter_order: u32) -> (BeamformingConfig, Vec<IqFrame>) {
+    let config = BeamformingConfig {
+        grid_origin_x: -3.2e-3,
+        grid_origin_z: 18.4e-3,
+        grid_spacing_x: 0.2e-3,
+        grid_spacing_z: 0.2e-3,
+        grid_width: 32,
+        clutter_filter_order,
+        ..doppler_config()
+    };
+    let clutter = moving_target(&config, -2.0e-3, 0.0);
+    let ensemble = moving_target(&config, 2.0e-3, 0.05)
+        .into_iter()
+        .zip(clutter)
+        .map(|(flow, clutter)| {
+            let data = flow.data.iter().zip(&clutter.data);
+            let data = data.map(|(f, c)| Complex::new(f.re + 10.0 * c.re, f.im + 10.0 * c.im)).collect();
+            IqFrame::new(flow.width, flow.depth, data)
+        })
+        .collect();
+    (config, ensemble)
+}
+
+#[test]
+fn kasai_estimates_axial_velocity() {
+    let config = doppler_config();
+    let doppler = CpuDoppler::new(config).unwrap();
+    for velocity in [0.2, -0.1, 0.02] {
+        let maps = doppler.process(&moving_target(&config, 0.0, velocity)).unwrap();
+        let estimate = maps.velocity.get(8, 8);
+        assert!((estimate - velocity).abs() < 0.05 * velocity.abs(), "{estimate} m/s for {velocity} m/s");
+        // The target moves up to 0.28 mm over the ensemble, which decorrelates it a little
+        assert!(maps.variance.get(8, 8) < 0.2, "variance {}", maps.variance.get(8, 8));
+    }
+}
+
+#[test]
+fn velocity_aliases_beyond_nyquist() {
+    let config = doppler_config();
+    let nyquist = nyquist_velocity(&config);
+    assert!((nyquist - 0.385).abs() < 1e-6);
+
+    let maps = CpuDoppler::new(config).unwrap().process(&moving_target(&config, 0.0, 1.5 * nyquist)).unwrap();
+    let estimate = maps.velocity.get(8, 8);
+    assert!((estimate + 0.5 * nyquist).abs() < 0.1 * nyquist, "{estimate} m/s");
+}
+
+#[test]
+fn static_target_keeps_its_power() {
+    let config = doppler_config();
+    let ensemble = moving_target(&config, 0.0, 0.0);
+    let maps = CpuDoppler::new(config).unwrap().process(&ensemble).unwrap();
+
+    assert!(maps.velocity.get(8, 8).abs() < 1e-4);
+    assert!(maps.variance.get(8, 8) < 1e-4);
+    let power = ensemble[0].data.iter().map(|c| c.norm() * c.norm()).collect();
+    assert_frames_close(&maps.power, &Frame::new(16, 16, power), 1e-4);
+}
+
+#[test]
+fn noise_has_high_variance() {
+    let config = doppler_config();
+    let pixels = (config.grid_width * config.grid_depth) as usize;
+    let ensemble: Vec<IqFrame> = (0..config.ensemble_length)
+        .map(|frame| {
+            let values = noise(2 * pixels, frame + 1);
+            let data = values.chunks(2).map(|c| Complex::new(c[0], c[1])).collect();
+            IqFrame::new(16, 16, data)
+        })
+        .collect();
+    let maps = CpuDoppler::new(config).unwrap().process(&ensemble).unwrap();
+    let mean = maps.variance.data.iter().sum::<f32>() / pixels as f32;
+    assert!(mean > 0.5, "mean variance {mean}");
+}
+
+#[test]
+fn wall_filter_reveals_flow_under_clutter() {
+    let (config, ensemble) = flow_under_clutter(0);
+    let unfiltered = CpuDoppler::new(config).unwrap().process_power(&ensemble).unwrap();
+    assert_eq!(unfiltered.get(6, 8), 1.0);
+    assert!(unfiltered.get(26, 8) < 0.75, "flow at {}", unfiltered.get(26, 8));
+
+    let (config, ensemble) = flow_under_clutter(1);
+    let doppler = CpuDoppler::new(config).unwrap();
+    let power = doppler.process_power(&ensemble).unwrap();
+    let (col, row, peak) = power.peak();
+    assert!(col.abs_diff(26) <= 1 && row.abs_diff(8) <= 1 && peak == 1.0, "peak at ({col}, {row})");
+    assert!(power.get(6, 8) < 0.5, "clutter at {}", power.get(6, 8));
+    let velocity = doppler.process(&ensemble).unwrap().velocity.get(26, 8);
+    assert!(velocity > 0.0, "{velocity} m/s");
+}
+
+#[test]
+fn second_order_wall_filter_removes_linear_drift() {
+    let config = BeamformingConfig { grid_width: 1, grid_depth: 1, ..doppler_config() };
+    let ensemble: Vec<IqFrame> = (0..config.ensemble_length)
+        .map(|frame| IqFrame::new(1, 1, vec![Complex::new(1.0 + 0.1 * frame as f32, -0.5 + 0.2 * frame as f32)]))
+        .collect();
+    let power = |clutter_filter_order: u32| {
+        let config = BeamformingConfig { clutter_filter_order, ..config };
+        CpuDoppler::new(config).unwrap().process(&ensemble).unwrap().power.get(0, 0)
+    };
+    // Mean removal leaves the ramp, 0.05 (n - 3.5)^2 on average
+    assert!((power(1) - 0.2625).abs() < 1e-5, "{}", power(1));
+    assert!(power(2) < 1e-10, "{}", power(2));
+}
+
+#[test]
+fn rejects_invalid_ensembles() {
+    let config = doppler_config();
+    let invalid = |config: BeamformingConfig| matches!(CpuDoppler::new(config), Err(BeamformError::InvalidConfig(_)));
+    assert!(invalid(BeamformingConfig { ensemble_length: 1, ..config }));
+    assert!(invalid(BeamformingConfig { pulse_repetition_frequency: 0.0, ..config }));
+    assert!(invalid(BeamformingConfig { center_frequency: f32::NAN, ..config }));
+    assert!(invalid(BeamformingConfig { grid_width: 0, ..config }));
+    assert!(invalid(BeamformingConfig { clutter_filter_order: 3, ..config }));
+    assert!(invalid(BeamformingConfig { ensemble_length: 2, clutter_filter_order: 2, ..config }));
+
+    let doppler = CpuDoppler::new(config).unwrap();
+    let ensemble = moving_target(&config, 0.0, 0.1);
+    let result = doppler.process(&ensemble[1..]);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected: 2048, actual: 1792 })));
+    let mut reshaped = ensemble.clone();
+    reshaped[3] = IqFrame::new(8, 32, reshaped[3].data.clone());
+    assert!(matches!(doppler.process(&reshaped), Err(BeamformError::InvalidInput { .. })));
+}
+
+#[test]
+fn gpu_matches_cpu_doppler() {
+    let config = doppler_config();
+    let mut gpu = match pollster::block_on(GpuDoppler::new(config)) {
+        Ok(gpu) => gpu,
+        Err(BeamformError::NoAdapter) => {
+            eprintln!("no GPU adapter available, skipping GPU comparison");
+            return;
+        }
+        Err(err) => panic!("failed to create GPU Doppler processor: {err}"),
+    };
+    let ensemble = moving_target(&config, 0.0, 0.2);
+    let actual = gpu.process(&ensemble).unwrap();
+    let expected = CpuDoppler::new(config).unwrap().process(&ensemble).unwrap();
+    assert_frames_close(&actual.velocity, &expected.velocity, 1e-3);
+    assert_frames_close(&actual.variance, &expected.variance, 1e-3);
+    assert_frames_close(&actual.power, &expected.power, 1e-3);
+
+    let (config, ensemble) = flow_under_clutter(1);
+    let mut gpu = pollster::block_on(GpuDoppler::new(config)).unwrap();
+    let expected = CpuDoppler::new(config).unwrap().process_power(&ensemble).unwrap();
+    assert_frames_close(&gpu.process_power(&ensemble).unwrap(), &expected, 1e-3);
+}
+
+#[test]
+fn gpu_gathers_beamformed_ensemble_on_device() {
+    let Some(context) = gpu_context() else { return };
+    let config = doppler_config();
+    let mut beamformer = GpuBeamformer::with_context(&context, config).unwrap();
+    let mut doppler = GpuDoppler::with_context(&context, config).unwrap();
+    let acquisitions: Vec<Vec<f32>> = (0..config.ensemble_length)
+        .map(|frame| {
+            let depth = 20.0e-3 - 0.2 * frame as f32 / config.pulse_repetition_frequency;
+            simulate::pulse_echoes_iq(&config, &[(0.0, depth)], 5.0e6, 0.6)
+        })
+        .collect();
+
+    // Each frame is beamformed and copied into the ensemble in its own submission
+    for (index, iq) in acquisitions.iter().enumerate() {
+        beamformer.upload(iq).unwrap();
+        let mut encoder = context.encoder();
+        beamformer.encode_channels(&mut encoder).unwrap();
+        beamformer.encode_beamform(&mut encoder).unwrap();
+        doppler.encode_frame(&mut encoder, index, beamformer.output_buffer()).unwrap();
+        context.queue().submit(Some(encoder.finish()));
+    }
+    let actual = doppler.process_encoded(context.encoder()).unwrap();
+
+    let ensemble: Vec<IqFrame> = acquisitions.iter().map(|iq| beamformer.process_iq(iq).unwrap()).collect();
+    let expected = CpuDoppler::new(config).unwrap().process(&ensemble).unwrap();
+    assert_frames_close(&actual.velocity, &expected.velocity, 1e-3);
+    assert_frames_close(&actual.variance, &expected.variance, 1e-3);
+    assert_frames_close(&actual.power, &expected.power, 1e-3);
+
+    let result = doppler.encode_frame(&mut context.encoder(), 8, beamformer.output_buffer());
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+}
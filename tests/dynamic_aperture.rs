@@ -0,0 +1,61 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{simulate, Apodization, BeamformError, BeamformingConfig, CpuBeamformer};
+
+/// Number of elements within `z / (2 * f_number)` of the pixel at `(x, z)`.
+fn active_elements(config: &BeamformingConfig, x: f32, z: f32) -> usize {
+    let half_aperture = 0.5 * z / config.f_number;
+    (0..config.num_channels as usize)
+        .map(|c| (c as f32 - (config.num_channels - 1) as f32 * 0.5) * config.element_pitch)
+        .filter(|element_x| (element_x - x).abs() <= half_aperture)
+        .count()
+}
+
+#[test]
+fn aperture_grows_with_depth() {
+    let config = BeamformingConfig { f_number: 2.0, ..test_config() };
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    for (x, z) in [(0.0, 16.0e-3), (0.0, 20.0e-3), (-2.0e-3, 22.0e-3)] {
+        let rf = simulate::point_targets(&config, &[(x, z)]);
+        let frame = cpu.process(&rf).unwrap();
+
+        let (_, _, value) = frame.peak();
+        assert_eq!(value, active_elements(&config, x, z) as f32, "target at ({x}, {z})");
+    }
+}
+
+#[test]
+fn small_f_number_uses_full_array() {
+    let config = BeamformingConfig { f_number: 0.5, ..test_config() };
+    let rf = simulate::point_targets(&config, &[(0.0, 20.0e-3)]);
+
+    let frame = CpuBeamformer::new(config).unwrap().process(&rf).unwrap();
+
+    assert_eq!(frame.peak().2, config.num_channels as f32);
+}
+
+#[test]
+fn negative_f_number_is_rejected() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+
+    let err = cpu.set_config(BeamformingConfig { f_number: -1.0, ..test_config() }).unwrap_err();
+
+    assert!(matches!(err, BeamformError::InvalidConfig(_)));
+}
+
+#[test]
+fn gpu_matches_cpu_with_dynamic_aperture() {
+    let config = BeamformingConfig { f_number: 1.5, ..test_config() };
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let rf = noise((config.num_channels * config.num_samples) as usize, 13);
+
+    for apodization in [Apodization::Hann, Apodization::Custom(noise(config.num_channels as usize, 17))] {
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        cpu.set_apodization(apodization.clone()).unwrap();
+        gpu.set_apodization(apodization).unwrap();
+
+        assert_frames_close(&gpu.process(&rf).unwrap(), &cpu.process(&rf).unwrap(), 1e-5);
+    }
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use std::f64::consts::PI;
+
+use common::{assert_iq_frames_close, gpu_context, noise, test_config};
+use rust_gpu_app::{
+    input_format, BeamformError, BeamformingConfig, Complex, CpuBeamformer, CpuFft, FftDirection, FftPlan,
+    GpuBeamformer, GpuFft,
+};
+
+/// Direct DFT in f64, scaled by `1 / len` when inverse.
+fn naive_dft(signal: &[Complex], inverse: bool) -> Vec<Complex> {
+    let n = signal.len();
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let scale = if inverse { 1.0 / n as f64 } else { 1.0 };
+    (0..n)
+        .map(|k| {
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for (j, x) in signal.iter().enumerate() {
+                let angle = sign * 2.0 * PI * ((j * k) % n) as f64 / n as f64;
+                re += x.re as f64 * angle.cos() - x.im as f64 * angle.sin();
+                im += x.re as f64 * angle.sin() + x.im as f64 * angle.cos();
+            }
+            Complex::new((re * scale) as f32, (im * scale) as f32)
+        })
+        .collect()
+}
+
+fn complex_noise(len: usize, seed: u32) -> Vec<Complex> {
+    let re = noise(len, seed);
+    let im = noise(len, seed + 1);
+    re.into_iter().zip(im).map(|(re, im)| Complex::new(re, im)).collect()
+}
+
+/// Asserts that two spectra agree to within `tolerance` of the largest
+/// magnitude in `expected`.
+fn assert_spectra_close(actual: &[Complex], expected: &[Complex], tolerance: f32) {
+    assert_eq!(actual.len(), expected.len());
+    let scale = expected.iter().fold(0.0f32, |m, v| m.max(v.norm())).max(f32::MIN_POSITIVE);
+    for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+        let error = Complex::new(a.re - e.re, a.im - e.im).norm();
+        assert!(error <= tolerance * scale, "bin {i}: {a:?} vs {e:?}");
+    }
+}
+
+#[test]
+fn plans_radix_passes_and_bluestein() {
+    let plan = FftPlan::new(64, 3).unwrap();
+    assert_eq!(plan.radices(), &[4, 4, 4]);
+    assert!(!plan.is_bluestein());
+
+    let plan = FftPlan::new(128, 1).unwrap();
+    assert_eq!(plan.radices(), &[4, 4, 4, 2]);
+
+    // 1536 samples: convolution of 4096 = 4^6
+    let plan = FftPlan::new(1536, 2).unwrap();
+    assert!(plan.is_bluestein());
+    assert_eq!(plan.radices(), &[4; 6]);
+    assert_eq!((plan.transform_len(), plan.batch()), (1536, 2));
+}
+
+#[test]
+fn cpu_matches_naive_dft() {
+    for len in [1, 2, 4, 8, 12, 64, 100, 128, 1536] {
+        let batch = 3;
+        let signal = complex_noise(len * batch, len as u32);
+        let fft = CpuFft::new(len, batch).unwrap();
+
+        for (direction, inverse) in [(FftDirection::Forward, false), (FftDirection::Inverse, true)] {
+            let spectrum = fft.process(&signal, direction).unwrap();
+            let expected: Vec<Complex> = signal.chunks(len).flat_map(|trace| naive_dft(trace, inverse)).collect();
+            assert_spectra_close(&spectrum, &expected, 1e-4);
+        }
+    }
+}
+
+#[test]
+fn inverse_undoes_forward() {
+    for len in [256, 1000] {
+        let signal = complex_noise(len * 2, 7);
+        let fft = CpuFft::new(len, 2).unwrap();
+        let spectrum = fft.process(&signal, FftDirection::Forward).unwrap();
+        let round_trip = fft.process(&spectrum, FftDirection::Inverse).unwrap();
+        assert_spectra_close(&round_trip, &signal, 1e-5);
+    }
+}
+
+#[test]
+fn transforms_rf_along_sample_axis() {
+    // Each trace is a tone in its own bin
+    let config = test_config();
+    let (traces, samples) = (4, config.num_samples as usize);
+    let rf: Vec<f32> = (0..traces * samples)
+        .map(|i| {
+            let (trace, sample) = (i / samples, i % samples);
+            let bin = 10 * (trace + 1);
+            (2.0 * PI * (bin * sample) as f64 / samples as f64).cos() as f32
+        })
+        .collect();
+
+    let spectrum = CpuFft::new(samples, traces).unwrap().process_real(&rf, FftDirection::Forward).unwrap();
+    for (trace, bins) in spectrum.chunks(samples).enumerate() {
+        let peak = (0..samples / 2).max_by(|&a, &b| bins[a].norm().total_cmp(&bins[b].norm())).unwrap();
+        assert_eq!(peak, 10 * (trace + 1));
+        assert!((bins[peak].norm() - samples as f32 / 2.0).abs() < 0.01 * samples as f32);
+    }
+}
+
+#[test]
+fn rejects_empty_plans_and_wrong_input_length() {
+    assert!(matches!(FftPlan::new(0, 1), Err(BeamformError::InvalidConfig(_))));
+    assert!(matches!(FftPlan::new(16, 0), Err(BeamformError::InvalidConfig(_))));
+
+    let fft = CpuFft::new(16, 2).unwrap();
+    let result = fft.process(&[Complex::default(); 16], FftDirection::Forward);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected: 32, actual: 16 })));
+}
+
+#[test]
+fn gpu_matches_cpu() {
+    for len in [128, 1536] {
+        let batch = 5;
+        let gpu = pollster::block_on(GpuFft::new(len, batch));
+        let mut gpu = match gpu {
+            Ok(gpu) => gpu,
+            Err(BeamformError::NoAdapter) => {
+                eprintln!("no GPU adapter available, skipping GPU comparison");
+                return;
+            }
+            Err(err) => panic!("failed to create GPU FFT: {err}"),
+        };
+        let cpu = CpuFft::new(len, batch).unwrap();
+        let signal = complex_noise(len * batch, 3);
+
+        for direction in [FftDirection::Forward, FftDirection::Inverse] {
+            let expected = cpu.process(&signal, direction).unwrap();
+            assert_spectra_close(&gpu.process(&signal, direction).unwrap(), &expected, 1e-5);
+        }
+    }
+}
+
+#[test]
+fn gpu_transforms_device_buffer_in_place() {
+    let (len, batch) = (1000, 3);
+    let fft = match pollster::block_on(GpuFft::new(len, batch)) {
+        Ok(fft) => fft,
+        Err(BeamformError::NoAdapter) => {
+            eprintln!("no GPU adapter available, skipping GPU comparison");
+            return;
+        }
+        Err(err) => panic!("failed to create GPU FFT: {err}"),
+    };
+    let (device, queue) = (fft.context().device(), fft.context().queue());
+    // The signals sit after 16 other values, which must stay untouched
+    let signal = complex_noise(len * batch, 5);
+    let mut data = vec![Complex::new(7.0, -7.0); 16];
+    data.extend(&signal);
+    let size = (data.len() * 8) as u64;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    queue.write_buffer(&buffer, 0, bytemuck::cast_slice(&data));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    fft.encode(&mut encoder, &buffer, 16 * 8, FftDirection::Forward).unwrap();
+    encoder.copy_buffer_to_buffer(&buffer, 0, &readback, 0, size);
+    queue.submit(Some(encoder.finish()));
+    readback.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::Maintain::Wait);
+    let actual: Vec<Complex> = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+
+    assert!(actual[..16].iter().all(|&v| v == Complex::new(7.0, -7.0)));
+    let expected = CpuFft::new(len, batch).unwrap().process(&signal, FftDirection::Forward).unwrap();
+    assert_spectra_close(&actual[16..], &expected, 1e-5);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    let result = fft.encode(&mut encoder, &buffer, 24 * 8, FftDirection::Forward);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected: 3000, actual: 2992 })));
+    assert!(matches!(fft.encode(&mut encoder, &buffer, 2, FftDirection::Forward), Err(BeamformError::InvalidConfig(_))));
+}
+
+#[test]
+fn gpu_beamformer_transforms_channel_data_on_device() {
+    let Some(context) = gpu_context() else { return };
+    let config = BeamformingConfig { input_format: input_format::IQ_INTERLEAVED, ..test_config() };
+    let mut gpu = GpuBeamformer::with_context(&context, config).unwrap();
+    // 96 traces of 1536 samples, a Bluestein length
+    let (len, batch) = (config.num_samples as usize, config.num_channels as usize);
+    let fft = GpuFft::with_context(&context, len, batch).unwrap();
+    let iq = noise(2 * len * batch, 13);
+
+    // The spectrum of every trace, beamformed as if it were samples
+    let traces: Vec<Complex> = iq.chunks(2).map(|v| Complex::new(v[0], v[1])).collect();
+    let spectrum = CpuFft::new(len, batch).unwrap().process(&traces, FftDirection::Forward).unwrap();
+    let spectrum: Vec<f32> = spectrum.iter().flat_map(|c| [c.re, c.im]).collect();
+    let expected = CpuBeamformer::new(config).unwrap().process_iq(&spectrum).unwrap();
+
+    gpu.upload(&iq).unwrap();
+    let mut encoder = context.encoder();
+    gpu.encode_channels(&mut encoder).unwrap();
+    gpu.encode_fft(&mut encoder, &fft, FftDirection::Forward).unwrap();
+    gpu.encode_beamform(&mut encoder).unwrap();
+    assert_iq_frames_close(&gpu.process_iq_encoded(encoder).unwrap(), &expected, 1e-3);
+
+    // A round trip leaves the channel data as uploaded
+    gpu.upload(&iq).unwrap();
+    let mut encoder = context.encoder();
+    gpu.encode_channels(&mut encoder).unwrap();
+    gpu.encode_fft(&mut encoder, &fft, FftDirection::Forward).unwrap();
+    gpu.encode_fft(&mut encoder, &fft, FftDirection::Inverse).unwrap();
+    gpu.encode_beamform(&mut encoder).unwrap();
+    let round_trip = gpu.process_iq_encoded(encoder).unwrap();
+    assert_iq_frames_close(&round_trip, &gpu.process_iq(&iq).unwrap(), 1e-4);
+
+    let mismatched = GpuFft::with_context(&context, len, batch - 1).unwrap();
+    let result = gpu.encode_fft(&mut context.encoder(), &mismatched, FftDirection::Forward);
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+}
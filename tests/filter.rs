@@ -0,0 +1,135 @@
+mod common;
+
+use std::f64::consts::PI;
+
+use common::{assert_frames_close, gpu_beamformer, test_config};
+use rust_gpu_app::{filters, input_format, interpolation, simulate, BeamformError, BeamformingConfig, CpuBeamformer, Frame};
+
+/// Magnitude of the frequency response of `taps` at `frequency` (Hz).
+fn gain(config: &BeamformingConfig, taps: &[f32], frequency: f32) -> f64 {
+    let omega = 2.0 * PI * frequency as f64 / config.sampling_frequency as f64;
+    let (re, im) = taps.iter().enumerate().fold((0.0, 0.0), |(re, im), (k, &tap)| {
+        (re + tap as f64 * (omega * k as f64).cos(), im - tap as f64 * (omega * k as f64).sin())
+    });
+    re.hypot(im)
+}
+
+/// Extent (m) of the part of column `col` within 6 dB of the image peak.
+fn axial_width(config: &BeamformingConfig, image: &Frame, col: usize) -> f32 {
+    let threshold = 1.0 - 6.0 / config.dynamic_range;
+    let rows = (0..image.depth).filter(|&row| image.get(col, row) >= threshold).count();
+    rows as f32 * config.grid_spacing_z
+}
+
+#[test]
+fn designed_filters_pass_their_band() {
+    let config = test_config();
+    let taps = filters::bandpass(&config, 2.5e6, 7.5e6, 63);
+    assert_eq!(taps.len(), 63);
+
+    assert!(gain(&config, &taps, 0.0) < 1e-6);
+    assert!((gain(&config, &taps, 5.0e6) - 1.0).abs() < 0.02);
+    assert!(gain(&config, &taps, 15.0e6) < 0.01);
+
+    let taps = filters::low_pass(&config, 2.5e6, 63);
+    assert!((gain(&config, &taps, 0.0) - 1.0).abs() < 1e-6);
+    assert!(gain(&config, &taps, 7.5e6) < 0.01);
+}
+
+#[test]
+fn single_unit_tap_leaves_frame_unchanged() {
+    let config = test_config();
+    let rf = simulate::pulse_echoes(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let unfiltered = cpu.process(&rf).unwrap();
+
+    cpu.set_filter(&[1.0]).unwrap();
+    assert_eq!(cpu.config().num_filter_taps, 1);
+    assert_eq!(cpu.process(&rf).unwrap(), unfiltered);
+
+    cpu.set_filter(&[]).unwrap();
+    assert_eq!(cpu.config().num_filter_taps, 0);
+    assert_eq!(cpu.process(&rf).unwrap(), unfiltered);
+}
+
+#[test]
+fn bandpass_removes_dc_offset_before_beamforming() {
+    let config = test_config();
+    let mut rf = simulate::pulse_echoes(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+    rf.iter_mut().for_each(|sample| *sample += 0.5);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    // Without the filter, the offset sums coherently into every pixel
+    let (.., peak) = cpu.process(&rf).unwrap().peak();
+    assert!(cpu.process(&rf).unwrap().get(0, 0) > 0.25 * peak);
+
+    cpu.set_filter(&filters::bandpass(&config, 2.5e6, 7.5e6, 63)).unwrap();
+    let frame = cpu.process(&rf).unwrap();
+    let (col, row, peak) = frame.peak();
+    assert_eq!(col, 24);
+    assert!(row.abs_diff(20) <= 1, "peak at row {row}");
+    assert!(frame.get(0, 0).abs() < 0.01 * peak);
+}
+
+#[test]
+fn matched_filter_compresses_chirp() {
+    // 20 um rows around the target at (0, 20 mm)
+    let config = BeamformingConfig {
+        grid_origin_z: 19.0e-3,
+        grid_spacing_z: 0.02e-3,
+        grid_depth: 100,
+        interpolation: interpolation::CUBIC,
+        ..test_config()
+    };
+    let pulse = filters::chirp(&config, 3.0e6, 7.0e6, 10.0e-6);
+    assert_eq!(pulse.len(), 401);
+    let rf = simulate::coded_echoes(&config, &[(0.0, 20.0e-3)], &pulse);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    // The uncompressed echo is 7.7 mm long and fills the column
+    let uncompressed = cpu.process_bmode(&rf).unwrap();
+    assert!(axial_width(&config, &uncompressed, 24) > 1.5e-3);
+
+    cpu.set_filter(&filters::matched(&pulse)).unwrap();
+    let compressed = cpu.process_bmode(&rf).unwrap();
+    let width = axial_width(&config, &compressed, 24);
+    assert!(width < 0.4e-3, "-6 dB width {width}");
+    let (col, row, _) = compressed.peak();
+    assert_eq!(col, 24);
+    assert!(row.abs_diff(50) <= 2, "peak at row {row}");
+}
+
+#[test]
+fn rejects_mismatched_and_non_finite_taps() {
+    let config = test_config();
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    let result = cpu.set_config(BeamformingConfig { num_filter_taps: 3, ..config });
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+    assert!(matches!(cpu.set_filter(&[0.5, f32::NAN]), Err(BeamformError::InvalidConfig(_))));
+    assert_eq!(cpu.config().num_filter_taps, 0);
+}
+
+#[test]
+fn gpu_matches_cpu_with_filter() {
+    for format in [input_format::RF, input_format::IQ_INTERLEAVED, input_format::IQ_PLANAR] {
+        let config = BeamformingConfig {
+            input_format: format,
+            demodulation_frequency: if format == input_format::RF { 0.0 } else { 5.0e6 },
+            ..test_config()
+        };
+        let targets = [(0.0, 20.0e-3), (2.0e-3, 17.0e-3)];
+        // IQ data sits around 0 Hz, so it is low-passed rather than bandpassed
+        let (input, taps) = if format == input_format::RF {
+            (simulate::pulse_echoes(&config, &targets, 5.0e6, 0.6), filters::bandpass(&config, 2.5e6, 7.5e6, 31))
+        } else {
+            (simulate::pulse_echoes_iq(&config, &targets, 5.0e6, 0.6), filters::low_pass(&config, 2.5e6, 31))
+        };
+        let Some(mut gpu) = gpu_beamformer(config) else { return };
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        gpu.set_filter(&taps).unwrap();
+        cpu.set_filter(&taps).unwrap();
+
+        assert_frames_close(&gpu.process_bmode(&input).unwrap(), &cpu.process_bmode(&input).unwrap(), 1e-3);
+    }
+}
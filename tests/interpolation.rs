@@ -0,0 +1,89 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{interpolation, simulate, BeamformingConfig, CpuBeamformer};
+
+const MODES: [u32; 4] = [interpolation::NEAREST, interpolation::LINEAR, interpolation::CUBIC, interpolation::SINC];
+
+/// A 5 MHz Gaussian pulse with 60 % bandwidth, sampled at 40 MHz.
+fn pulse(t: f32) -> f32 {
+    let sigma = 1.0 / (2.0 * std::f32::consts::PI * (0.6 * 5.0e6 / (2.0 * (2.0 * 2.0f32.ln()).sqrt())));
+    (-t * t / (2.0 * sigma * sigma)).exp() * (2.0 * std::f32::consts::PI * 5.0e6 * t).cos()
+}
+
+/// RMS error of [`shader::sample_at`] against the analytic pulse, evaluated
+/// at 0.1-sample steps around its centre.
+fn rms_error(mode: u32) -> f32 {
+    let config = BeamformingConfig { num_channels: 1, num_samples: 128, interpolation: mode, ..test_config() };
+    let center = 64.0;
+    let trace: Vec<f32> = (0..128).map(|s| pulse((s as f32 - center) / config.sampling_frequency)).collect();
+
+    let positions: Vec<f32> = (0..=400).map(|i| center - 20.0 + i as f32 * 0.1).collect();
+    let sum_sq: f32 = positions
+        .iter()
+        .map(|&p| {
+            let expected = pulse((p - center) / config.sampling_frequency);
+            (shader::sample_at(&trace, &config, 0, 0, p) - expected).powi(2)
+        })
+        .sum();
+    (sum_sq / positions.len() as f32).sqrt()
+}
+
+#[test]
+fn higher_order_modes_reduce_interpolation_error() {
+    let errors = MODES.map(rms_error);
+
+    assert!(errors[1] < 0.5 * errors[0], "linear {} vs nearest {}", errors[1], errors[0]);
+    assert!(errors[2] < 0.5 * errors[1], "cubic {} vs linear {}", errors[2], errors[1]);
+    assert!(errors[3] < 0.5 * errors[2], "sinc {} vs cubic {}", errors[3], errors[2]);
+    assert!(errors[3] < 5e-3, "sinc {}", errors[3]);
+}
+
+#[test]
+fn modes_are_exact_on_sample_points() {
+    let config = BeamformingConfig { num_channels: 1, num_samples: 32, ..test_config() };
+    let trace = noise(32, 23);
+
+    for mode in MODES {
+        let config = BeamformingConfig { interpolation: mode, ..config };
+        for s in 4..28 {
+            let value = shader::sample_at(&trace, &config, 0, 0, s as f32);
+            assert!((value - trace[s]).abs() < 1e-5, "mode {mode} at sample {s}");
+        }
+    }
+}
+
+#[test]
+fn interpolation_recovers_point_target_amplitude() {
+    // Every channel's echo peaks at exactly 1 at its time of flight, so ideal
+    // interpolation sums to the channel count at the target pixel
+    let base = test_config();
+    let rf = simulate::pulse_echoes(&base, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+    let ideal = base.num_channels as f32;
+
+    let errors = MODES.map(|mode| {
+        let config = BeamformingConfig { interpolation: mode, ..base };
+        let frame = CpuBeamformer::new(config).unwrap().process(&rf).unwrap();
+        (frame.get(24, 20) - ideal).abs() / ideal
+    });
+
+    // Linear interpolation cuts the crest of the pulse, so it is not
+    // necessarily closer than nearest at the peak itself
+    assert!(errors[2] < errors[0].min(errors[1]), "relative errors {errors:?}");
+    assert!(errors[3] < errors[2], "relative errors {errors:?}");
+    assert!(errors[3] < 5e-3, "relative errors {errors:?}");
+}
+
+#[test]
+fn gpu_matches_cpu_for_every_mode() {
+    let Some(mut gpu) = gpu_beamformer(test_config()) else { return };
+    let rf = simulate::pulse_echoes(&test_config(), &[(0.0, 20.0e-3), (2.0e-3, 17.0e-3)], 5.0e6, 0.6);
+
+    for mode in MODES {
+        let config = BeamformingConfig { interpolation: mode, ..test_config() };
+        gpu.set_config(config).unwrap();
+
+        let expected = CpuBeamformer::new(config).unwrap().process(&rf).unwrap();
+        assert_frames_close(&gpu.process(&rf).unwrap(), &expected, 1e-4);
+    }
+}
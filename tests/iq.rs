@@ -0,0 +1,84 @@
+mod common;
+
+use common::{assert_iq_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{input_format, interpolation, simulate, BeamformError, BeamformingConfig, CpuBeamformer};
+
+fn iq_config(format: u32) -> BeamformingConfig {
+    BeamformingConfig {
+        input_format: format,
+        demodulation_frequency: 5.0e6,
+        interpolation: interpolation::CUBIC,
+        ..test_config()
+    }
+}
+
+#[test]
+fn phase_rotation_focuses_iq_point_target() {
+    let config = iq_config(input_format::IQ_INTERLEAVED);
+    let iq = simulate::pulse_echoes_iq(&config, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+
+    let frame = CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap();
+
+    // Every channel contributes a unit phasor at zero phase once rotated
+    let target = frame.get(24, 20);
+    let ideal = config.num_channels as f32;
+    assert!((target.norm() - ideal).abs() < 0.02 * ideal, "{target:?}");
+    assert!(target.arg().abs() < 0.05, "{target:?}");
+    assert_eq!(frame.magnitude().peak().0, 24);
+    assert_eq!(frame.magnitude().peak().1, 20);
+}
+
+#[test]
+fn without_phase_rotation_channels_do_not_add_up() {
+    let config = BeamformingConfig { demodulation_frequency: 0.0, ..iq_config(input_format::IQ_INTERLEAVED) };
+    let demodulated = BeamformingConfig { demodulation_frequency: 5.0e6, ..config };
+    let iq = simulate::pulse_echoes_iq(&demodulated, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+
+    let frame = CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap();
+
+    assert!(frame.get(24, 20).norm() < 0.5 * config.num_channels as f32);
+}
+
+#[test]
+fn interleaved_and_planar_layouts_agree() {
+    let interleaved = iq_config(input_format::IQ_INTERLEAVED);
+    let planar = iq_config(input_format::IQ_PLANAR);
+    let targets = [(0.0, 20.0e-3), (-2.0e-3, 17.0e-3)];
+
+    let a = CpuBeamformer::new(interleaved).unwrap()
+        .process_iq(&simulate::pulse_echoes_iq(&interleaved, &targets, 5.0e6, 0.6))
+        .unwrap();
+    let b = CpuBeamformer::new(planar).unwrap()
+        .process_iq(&simulate::pulse_echoes_iq(&planar, &targets, 5.0e6, 0.6))
+        .unwrap();
+
+    assert_iq_frames_close(&a, &b, 1e-6);
+}
+
+#[test]
+fn rf_and_iq_entry_points_are_not_interchangeable() {
+    let iq_config = iq_config(input_format::IQ_PLANAR);
+    let iq = vec![0.0; 2 * (iq_config.num_channels * iq_config.num_samples) as usize];
+    let rf = vec![0.0; (iq_config.num_channels * iq_config.num_samples) as usize];
+
+    let err = CpuBeamformer::new(iq_config).unwrap().process(&iq).unwrap_err();
+    assert!(matches!(err, BeamformError::InvalidConfig(_)));
+    let err = CpuBeamformer::new(test_config()).unwrap().process_iq(&rf).unwrap_err();
+    assert!(matches!(err, BeamformError::InvalidConfig(_)));
+    let err = CpuBeamformer::new(iq_config).unwrap().process_iq(&rf).unwrap_err();
+    assert!(matches!(err, BeamformError::InvalidInput { .. }));
+}
+
+#[test]
+fn gpu_matches_cpu_for_iq_layouts() {
+    let Some(mut gpu) = gpu_beamformer(test_config()) else { return };
+
+    for format in [input_format::IQ_INTERLEAVED, input_format::IQ_PLANAR] {
+        let config = iq_config(format);
+        gpu.set_config(config).unwrap();
+        let iq = noise(2 * (config.num_channels * config.num_samples) as usize, format);
+
+        let expected = CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap();
+        assert_iq_frames_close(&gpu.process_iq(&iq).unwrap(), &expected, 1e-4);
+    }
+}
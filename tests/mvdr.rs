@@ -0,0 +1,95 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{input_format, simulate, BeamformError, BeamformingConfig, CpuBeamformer, Frame};
+
+/// IQ data of a 5 MHz pulse, beamformed on 0.05 mm columns from -3 mm to
+/// 3 mm and 0.1 mm rows from 19.5 mm to 20.5 mm.
+fn mvdr_config(mvdr_subarray: u32, mvdr_loading: f32) -> BeamformingConfig {
+    BeamformingConfig {
+        input_format: input_format::IQ_INTERLEAVED,
+        demodulation_frequency: 5.0e6,
+        grid_origin_x: -3.0e-3,
+        grid_origin_z: 19.5e-3,
+        grid_spacing_x: 0.05e-3,
+        grid_spacing_z: 0.1e-3,
+        grid_width: 121,
+        grid_depth: 11,
+        mvdr_subarray,
+        mvdr_loading,
+        ..test_config()
+    }
+}
+
+/// Magnitude of the beamformed point targets.
+fn image(config: BeamformingConfig, targets: &[(f32, f32)]) -> Frame {
+    let iq = simulate::pulse_echoes_iq(&config, targets, 5.0e6, 0.6);
+    CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap().magnitude()
+}
+
+/// Columns within 6 dB of the peak, along the row of the peak.
+fn lateral_width(frame: &Frame) -> usize {
+    let (_, row, peak) = frame.peak();
+    (0..frame.width).filter(|&col| frame.get(col, row) >= 0.5 * peak).count()
+}
+
+#[test]
+fn narrows_point_target_mainlobe() {
+    let target = [(0.0, 20.0e-3)];
+    let das = image(mvdr_config(0, 0.01), &target);
+    let mvdr = image(mvdr_config(48, 0.01), &target);
+
+    // Both peak on the target, at col 60 and row 5
+    assert_eq!((das.peak().0, das.peak().1), (60, 5));
+    assert_eq!((mvdr.peak().0, mvdr.peak().1), (60, 5));
+    let (das_width, mvdr_width) = (lateral_width(&das), lateral_width(&mvdr));
+    assert!(2 * mvdr_width <= das_width, "delay-and-sum {das_width} columns, MVDR {mvdr_width}");
+}
+
+#[test]
+fn resolves_targets_delay_and_sum_merges() {
+    // 0.3 mm apart, in cols 57 and 63: within the delay-and-sum mainlobe
+    let targets = [(-0.15e-3, 20.0e-3), (0.15e-3, 20.0e-3)];
+    let dip = |frame: &Frame| frame.get(60, 5) / frame.get(57, 5).min(frame.get(63, 5));
+    let das = image(mvdr_config(0, 0.01), &targets);
+    let mvdr = image(mvdr_config(48, 0.01), &targets);
+    assert!(dip(&das) > 0.9, "delay-and-sum dip {}", dip(&das));
+    assert!(dip(&mvdr) < 0.5, "MVDR dip {}", dip(&mvdr));
+}
+
+#[test]
+fn heavy_loading_approaches_delay_and_sum() {
+    // A single subarray over the whole array, weighted uniformly once the
+    // loading swamps the covariance
+    let config = mvdr_config(0, 0.01);
+    let iq = simulate::pulse_echoes_iq(&config, &[(0.5e-3, 20.0e-3)], 5.0e6, 0.6);
+    let das = CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap().magnitude();
+    let config = BeamformingConfig { mvdr_subarray: 96, mvdr_loading: 1.0e6, ..config };
+    let mvdr = CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap().magnitude();
+    assert_frames_close(&mvdr, &das, 1e-3);
+}
+
+#[test]
+fn rejects_invalid_subarrays_and_loading() {
+    let mut cpu = CpuBeamformer::new(mvdr_config(48, 0.01)).unwrap();
+    for (mvdr_subarray, mvdr_loading) in [(97, 0.01), (48, 0.0), (48, -1.0), (48, f32::NAN)] {
+        let err = cpu.set_config(mvdr_config(mvdr_subarray, mvdr_loading)).unwrap_err();
+        assert!(matches!(err, BeamformError::InvalidConfig(_)), "{mvdr_subarray} {mvdr_loading}");
+    }
+    // Loading is ignored by delay-and-sum
+    cpu.set_config(mvdr_config(0, 0.0)).unwrap();
+}
+
+#[test]
+fn gpu_matches_cpu_mvdr() {
+    let config = mvdr_config(32, 0.05);
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let targets = simulate::pulse_echoes_iq(&config, &[(-0.4e-3, 19.8e-3), (0.6e-3, 20.2e-3)], 5.0e6, 0.6);
+    let jitter = noise(targets.len(), 29);
+    let iq: Vec<f32> = targets.iter().zip(&jitter).map(|(t, n)| t + 0.05 * n).collect();
+
+    let actual = gpu.process_iq(&iq).unwrap().magnitude();
+    let expected = CpuBeamformer::new(config).unwrap().process_iq(&iq).unwrap().magnitude();
+    assert_frames_close(&actual, &expected, 1e-3);
+}
+
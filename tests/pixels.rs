@@ -0,0 +1,96 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, test_config};
+use rust_gpu_app::{
+    grid_geometry, input_format, simulate, BeamformError, BeamformingConfig, CpuBeamformer, CpuScanConverter, Raster,
+};
+
+const TARGETS: [(f32, f32); 2] = [(0.0, 20.0e-3), (2.0e-3, 17.0e-3)];
+
+/// Positions of every pixel of the grid of `config`, row by row.
+fn grid_positions(config: &BeamformingConfig) -> Vec<(f32, f32)> {
+    let width = config.grid_width as usize;
+    (0..width * config.grid_depth as usize)
+        .map(|i| {
+            let x = config.grid_origin_x + (i % width) as f32 * config.grid_spacing_x;
+            let z = config.grid_origin_z + (i / width) as f32 * config.grid_spacing_z;
+            (x, z)
+        })
+        .collect()
+}
+
+#[test]
+fn pixel_positions_match_computed_grid() {
+    let config = test_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let expected = cpu.process(&rf).unwrap();
+
+    cpu.set_pixels(config.grid_width, &grid_positions(&config)).unwrap();
+    assert_eq!(cpu.config().grid_geometry, grid_geometry::POINTS);
+    assert_eq!((cpu.config().grid_width, cpu.config().grid_depth), (48, 32));
+    assert_frames_close(&cpu.process(&rf).unwrap(), &expected, 1e-5);
+    let expected = CpuBeamformer::new(config).unwrap().process_bmode(&rf).unwrap();
+    assert_frames_close(&cpu.process_bmode(&rf).unwrap(), &expected, 1e-5);
+}
+
+#[test]
+fn point_list_probes_region_of_interest() {
+    let config =
+        BeamformingConfig { input_format: input_format::IQ_INTERLEAVED, demodulation_frequency: 5.0e6, ..test_config() };
+    let iq = simulate::pulse_echoes_iq(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let grid = cpu.process_iq(&iq).unwrap().magnitude();
+
+    // The two targets, then a point between them; one row of three
+    let points = [TARGETS[0], TARGETS[1], (1.0e-3, 18.5e-3)];
+    cpu.set_pixels(3, &points).unwrap();
+    let probed = cpu.process_iq(&iq).unwrap().magnitude();
+    assert_eq!((probed.width, probed.depth), (3, 1));
+    // Target pixels of the grid: (0, 20 mm) is col 24, row 20; (2, 17 mm) col 32, row 8
+    assert!((probed.get(0, 0) - grid.get(24, 20)).abs() < 1e-4 * grid.get(24, 20));
+    assert!((probed.get(1, 0) - grid.get(32, 8)).abs() < 1e-4 * grid.get(32, 8));
+    assert!(probed.get(2, 0) < 0.1 * probed.get(0, 0));
+}
+
+#[test]
+fn rejects_invalid_pixels() {
+    let config = test_config();
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let positions = grid_positions(&config);
+
+    // 1536 positions do not fill rows of 1000
+    assert!(matches!(cpu.set_pixels(1000, &positions), Err(BeamformError::InvalidConfig(_))));
+    assert!(matches!(cpu.set_pixels(0, &positions), Err(BeamformError::InvalidConfig(_))));
+    assert!(matches!(cpu.set_pixels(1, &[(0.0, f32::INFINITY)]), Err(BeamformError::InvalidConfig(_))));
+    assert_eq!(cpu.config().grid_geometry, grid_geometry::CARTESIAN);
+
+    cpu.set_pixels(48, &positions).unwrap();
+    let result = cpu.set_config(BeamformingConfig { grid_depth: 16, ..*cpu.config() });
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+    let raster = Raster { width: 8, depth: 8, spacing_x: 1.0e-3, spacing_z: 1.0e-3, ..Default::default() };
+    let result = CpuScanConverter::new(*cpu.config(), raster);
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+
+    // Back to a computed grid, the positions are ignored
+    cpu.set_config(BeamformingConfig { grid_geometry: grid_geometry::CARTESIAN, ..*cpu.config() }).unwrap();
+}
+
+#[test]
+fn gpu_matches_cpu_with_pixels() {
+    let config = test_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+
+    // A scattered list longer than one workgroup row of the grid
+    let points: Vec<(f32, f32)> =
+        (0..500).map(|i| (-5.0e-3 + 0.02e-3 * i as f32, 16.0e-3 + (i % 37) as f32 * 0.15e-3)).collect();
+    gpu.set_pixels(500, &points).unwrap();
+    cpu.set_pixels(500, &points).unwrap();
+    assert_frames_close(&gpu.process(&rf).unwrap(), &cpu.process(&rf).unwrap(), 1e-3);
+
+    gpu.set_pixels(config.grid_width, &grid_positions(&config)).unwrap();
+    cpu.set_pixels(config.grid_width, &grid_positions(&config)).unwrap();
+    assert_frames_close(&gpu.process_bmode(&rf).unwrap(), &cpu.process_bmode(&rf).unwrap(), 1e-3);
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use common::{assert_frames_close, gpu_context, test_config};
+use rust_gpu_app::{
+    fit_raster, grid_geometry, simulate, BeamformError, BeamformingConfig, CpuBeamformer, CpuScanConverter,
+    GpuBeamformer, GpuScanConverter, Raster,
+};
+
+/// A 60° sector of 1° beams from 15 mm to 28 mm, with the apex at the centre
+/// of the array.
+fn sector_config() -> BeamformingConfig {
+    BeamformingConfig {
+        grid_geometry: grid_geometry::POLAR,
+        grid_origin_x: (-30.0f32).to_radians(),
+        grid_spacing_x: 1.0f32.to_radians(),
+        grid_width: 61,
+        grid_origin_z: 15.0e-3,
+        grid_spacing_z: 0.25e-3,
+        grid_depth: 53,
+        ..test_config()
+    }
+}
+
+/// Image whose value is `col + 100 * row`, which bilinear interpolation
+/// reproduces exactly between pixels.
+fn ramp(config: &BeamformingConfig) -> Vec<f32> {
+    let width = config.grid_width as usize;
+    (0..width * config.grid_depth as usize).map(|i| (i % width) as f32 + 100.0 * (i / width) as f32).collect()
+}
+
+#[test]
+fn fitted_raster_covers_sector() {
+    let config = sector_config();
+    let raster = fit_raster(&config, 201);
+    let half_width = 28.0e-3 * 30.0f32.to_radians().sin();
+    assert_eq!(raster.width, 201);
+    assert!((raster.origin_x + half_width).abs() < 1e-6, "{raster:?}");
+    assert!((raster.origin_z - 15.0e-3 * 30.0f32.to_radians().cos()).abs() < 1e-6, "{raster:?}");
+    assert!((raster.spacing_x - 2.0 * half_width / 200.0).abs() < 1e-9);
+    assert_eq!(raster.spacing_z, raster.spacing_x);
+    // The centre beam reaches the deepest point
+    let bottom = raster.origin_z + (raster.depth - 1) as f32 * raster.spacing_z;
+    assert!(bottom >= 28.0e-3 - 1e-6 && bottom < 28.0e-3 + raster.spacing_z, "{raster:?}");
+}
+
+#[test]
+fn raster_interpolates_inside_sector_and_masks_outside() {
+    let config = sector_config();
+    let raster = fit_raster(&config, 201);
+    let converted = CpuScanConverter::new(config, raster).unwrap().process(&ramp(&config)).unwrap();
+    assert_eq!((converted.width, converted.depth), (raster.width as usize, raster.depth as usize));
+
+    let (mut inside, mut outside) = (0, 0);
+    for row in 0..converted.depth {
+        for col in 0..converted.width {
+            let x = (raster.origin_x + col as f32 * raster.spacing_x) as f64;
+            let z = (raster.origin_z + row as f32 * raster.spacing_z) as f64;
+            let beam = (x.atan2(z) - config.grid_origin_x as f64) / config.grid_spacing_x as f64;
+            let sample = (x.hypot(z) - config.grid_origin_z as f64) / config.grid_spacing_z as f64;
+            let value = converted.get(col, row) as f64;
+            if (0.0..=60.0).contains(&beam) && (0.0..=52.0).contains(&sample) {
+                assert!((value - (beam + 100.0 * sample)).abs() < 0.05, "({col}, {row}): {value}");
+                inside += 1;
+            } else if !(-1e-3..=60.001).contains(&beam) || !(-1e-3..=52.001).contains(&sample) {
+                assert_eq!(value, 0.0, "({col}, {row}) is outside the sector");
+                outside += 1;
+            }
+        }
+    }
+    assert!(inside > 1000 && outside > 1000, "{inside} inside, {outside} outside");
+}
+
+#[test]
+fn polar_grid_images_target_in_sector() {
+    let config = sector_config();
+    let target = (5.0e-3, 25.0e-3);
+    let rf = simulate::pulse_echoes(&config, &[target], 5.0e6, 0.6);
+    let bmode = CpuBeamformer::new(config).unwrap().process_bmode(&rf).unwrap();
+    let (col, row, _) = bmode.peak();
+    assert!(col.abs_diff(41) <= 1, "peak in beam {col}");
+    assert!(row.abs_diff(42) <= 1, "peak at radius {row}");
+
+    // 0.1 mm pixels from 0 mm to 12 mm laterally, 20 mm to 30 mm deep
+    let raster = Raster {
+        origin_x: 0.0,
+        origin_z: 20.0e-3,
+        spacing_x: 0.1e-3,
+        spacing_z: 0.1e-3,
+        width: 121,
+        depth: 101,
+        ..Default::default()
+    };
+    let (col, row, _) = CpuScanConverter::new(config, raster).unwrap().process(&bmode.data).unwrap().peak();
+    assert!(col.abs_diff(50) <= 3 && row.abs_diff(50) <= 3, "peak at ({col}, {row})");
+}
+
+#[test]
+fn rejects_invalid_rasters_and_grids() {
+    let config = sector_config();
+    let raster = fit_raster(&config, 101);
+    let invalid = |config: BeamformingConfig, raster: Raster| {
+        matches!(CpuScanConverter::new(config, raster), Err(BeamformError::InvalidConfig(_)))
+    };
+
+    assert!(invalid(config, Raster { width: 0, ..raster }));
+    assert!(invalid(config, Raster { spacing_z: -1.0e-4, ..raster }));
+    assert!(invalid(config, Raster { origin_x: f32::NAN, ..raster }));
+    assert!(invalid(BeamformingConfig { grid_spacing_x: 0.0, ..config }, raster));
+    assert!(invalid(BeamformingConfig { grid_geometry: 2, ..config }, raster));
+    assert!(invalid(BeamformingConfig { grid_spacing_x: 4.0f32.to_radians(), ..config }, raster));
+
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+    let result = cpu.set_config(BeamformingConfig { grid_origin_z: -1.0e-3, ..config });
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+
+    let converter = CpuScanConverter::new(config, raster).unwrap();
+    let result = converter.process(&[0.0; 10]);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected, actual: 10 }) if expected == 61 * 53));
+}
+
+#[test]
+fn gpu_matches_cpu_scan_conversion() {
+    let config = sector_config();
+    let raster = fit_raster(&config, 256);
+    let mut gpu = match pollster::block_on(GpuScanConverter::new(config, raster)) {
+        Ok(gpu) => gpu,
+        Err(BeamformError::NoAdapter) => {
+            eprintln!("no GPU adapter available, skipping GPU comparison");
+            return;
+        }
+        Err(err) => panic!("failed to create GPU scan converter: {err}"),
+    };
+    let image = ramp(&config);
+    let cpu = CpuScanConverter::new(config, raster).unwrap();
+    assert_frames_close(&gpu.process(&image).unwrap(), &cpu.process(&image).unwrap(), 1e-4);
+}
+
+#[test]
+fn gpu_converts_beamformed_image_on_device() {
+    let Some(context) = gpu_context() else { return };
+    let config = sector_config();
+    let raster = fit_raster(&config, 128);
+    let mut beamformer = GpuBeamformer::with_context(&context, config).unwrap();
+    let mut converter = GpuScanConverter::with_context(&context, config, raster).unwrap();
+    let rf = simulate::pulse_echoes(&config, &[(5.0e-3, 25.0e-3)], 5.0e6, 0.6);
+
+    beamformer.upload(&rf).unwrap();
+    let mut encoder = context.encoder();
+    beamformer.encode_channels(&mut encoder).unwrap();
+    beamformer.encode_beamform(&mut encoder).unwrap();
+    beamformer.encode_bmode(&mut encoder).unwrap();
+    converter.encode(&mut encoder, beamformer.image_buffer()).unwrap();
+    let actual = converter.process_encoded(encoder).unwrap();
+
+    let bmode = beamformer.process_bmode(&rf).unwrap();
+    let expected = CpuScanConverter::new(config, raster).unwrap().process(&bmode.data).unwrap();
+    assert_frames_close(&actual, &expected, 1e-5);
+
+    let small = context.device().create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: 16,
+        usage: wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let result = converter.encode(&mut context.encoder(), &small);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected, actual: 4 }) if expected == 61 * 53));
+}
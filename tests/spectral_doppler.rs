@@ -0,0 +1,157 @@
+mod common;
+
+use std::f32::consts::PI;
+
+use common::{gpu_beamformer, test_config};
+use rust_gpu_app::{
+    input_format, simulate, Backend, BeamformError, Beamformer, BeamformingConfig, Complex, Frame, IqFrame,
+    SampleVolume, SpectralWindow, Spectrogram,
+};
+
+/// IQ acquisitions at 10 kHz of a 5 MHz pulse: a Nyquist velocity of
+/// 0.77 m/s.
+fn pw_config() -> BeamformingConfig {
+    BeamformingConfig {
+        input_format: input_format::IQ_INTERLEAVED,
+        demodulation_frequency: 5.0e6,
+        pulse_repetition_frequency: 10.0e3,
+        center_frequency: 5.0e6,
+        ..test_config()
+    }
+}
+
+/// Slow-time signal of a scatterer moving towards the probe at `velocity`.
+fn tone(config: &BeamformingConfig, velocity: f32, samples: usize) -> Vec<Complex> {
+    let frequency = 2.0 * velocity * config.center_frequency / config.speed_of_sound;
+    (0..samples)
+        .map(|n| {
+            let phase = 2.0 * PI * frequency * n as f32 / config.pulse_repetition_frequency;
+            Complex::new(phase.cos(), phase.sin())
+        })
+        .collect()
+}
+
+/// Row of the brightest bin of column `col`.
+fn peak_row(spectrogram: &Frame, col: usize) -> usize {
+    (0..spectrogram.depth).max_by(|&a, &b| spectrogram.get(col, a).total_cmp(&spectrogram.get(col, b))).unwrap()
+}
+
+#[test]
+fn gate_spans_sample_volume() {
+    let config = pw_config();
+    let volume = SampleVolume { x: 1.0e-3, z: 20.0e-3, length: 1.0e-3 };
+    // A quarter wavelength is 77 um: 6 points on either side of the centre
+    let positions = volume.positions(&config);
+    assert_eq!(positions.len(), 13);
+    assert!(positions.iter().all(|&(x, _)| x == 1.0e-3));
+    assert!((positions[0].1 - (20.0e-3 - 6.0 * 77.0e-6)).abs() < 1e-8, "{positions:?}");
+    assert!((positions[6].1 - 20.0e-3).abs() < 1e-9);
+
+    assert_eq!(SampleVolume { length: 0.0, ..volume }.positions(&config), vec![(1.0e-3, 20.0e-3)]);
+    let config = BeamformingConfig { center_frequency: 0.0, ..config };
+    assert_eq!(volume.positions(&config), vec![(1.0e-3, 20.0e-3)]);
+}
+
+#[test]
+fn spectrogram_finds_tone_velocity() {
+    let config = pw_config();
+    let spectrogram = Spectrogram::new(config, 64, 48, SpectralWindow::Hann).unwrap();
+    assert_eq!((spectrogram.hop(), spectrogram.columns(1000)), (16, 59));
+    assert!((spectrogram.time(1) - (16.0 + 31.5) / 10.0e3).abs() < 1e-9);
+    // 64 rows from +31 to -32 bins of 156 Hz, 24 mm/s apart
+    let bin = 1540.0 * 10.0e3 / 64.0 / (2.0 * 5.0e6);
+    assert!((spectrogram.velocity(0) - 31.0 * bin).abs() < 1e-6);
+    assert!((spectrogram.velocity(63) + 32.0 * bin).abs() < 1e-6);
+
+    for velocity in [0.3, -0.5] {
+        let image = spectrogram.process(&tone(&config, velocity, 1000)).unwrap();
+        assert_eq!((image.width, image.depth), (59, 64));
+        for col in 0..image.width {
+            let row = peak_row(&image, col);
+            assert!((spectrogram.velocity(row) - velocity).abs() <= 0.5 * bin, "{velocity} m/s in row {row}");
+            assert!(image.get(col, row) > 0.999);
+        }
+    }
+}
+
+#[test]
+fn window_reduces_leakage() {
+    let config = pw_config();
+    // Halfway between two bins, where a rectangular window leaks the most
+    let bin = 1540.0 * 10.0e3 / 64.0 / (2.0 * 5.0e6);
+    let samples = tone(&config, 10.5 * bin, 256);
+    let leakage = |window: SpectralWindow| {
+        let spectrogram = Spectrogram::new(config, 64, 0, window).unwrap();
+        let image = spectrogram.process(&samples).unwrap();
+        // The tone sits between rows 20 and 21, row 37 is 16 bins slower
+        image.get(0, 21 + 16)
+    };
+    let (rectangular, hann) = (leakage(SpectralWindow::Rectangular), leakage(SpectralWindow::Hann));
+    assert!(rectangular > 0.3, "rectangular {rectangular}");
+    assert!(hann < rectangular - 0.2, "rectangular {rectangular} hann {hann}");
+}
+
+#[test]
+fn pulsed_wave_doppler_tracks_moving_scatterer() {
+    let config = pw_config();
+    // The scatterer crosses 1.28 mm of the 2 mm gate over 128 acquisitions
+    let volume = SampleVolume { x: 0.0, z: 19.36e-3, length: 2.0e-3 };
+    let mut beamformer = pollster::block_on(Beamformer::new(config, Backend::Cpu)).unwrap();
+    beamformer.set_sample_volume(&volume).unwrap();
+    assert_eq!(beamformer.config().grid_width, volume.positions(&config).len() as u32);
+
+    let samples: Vec<Complex> = (0..128)
+        .map(|n| {
+            let depth = 20.0e-3 - 0.1 * n as f32 / config.pulse_repetition_frequency;
+            let iq = simulate::pulse_echoes_iq(&config, &[(0.0, depth)], 5.0e6, 0.6);
+            volume.slow_time_sample(&config, &beamformer.process_iq(&iq).unwrap()).unwrap()
+        })
+        .collect();
+    let spectrogram = Spectrogram::new(config, 32, 16, SpectralWindow::Hann).unwrap();
+    let image = spectrogram.process(&samples).unwrap();
+    let bin = spectrogram.velocity(0) - spectrogram.velocity(1);
+    for col in 0..image.width {
+        let velocity = spectrogram.velocity(peak_row(&image, col));
+        assert!((velocity - 0.1).abs() <= bin, "{velocity} m/s at {} s", spectrogram.time(col));
+    }
+}
+
+#[test]
+fn rejects_invalid_spectrograms() {
+    let config = pw_config();
+    let invalid = |config: BeamformingConfig, len: usize, overlap: usize, window: SpectralWindow| {
+        matches!(Spectrogram::new(config, len, overlap, window), Err(BeamformError::InvalidConfig(_)))
+    };
+    assert!(invalid(config, 64, 64, SpectralWindow::Hann));
+    assert!(invalid(config, 1, 0, SpectralWindow::Hann));
+    assert!(invalid(config, 64, 0, SpectralWindow::Custom(vec![1.0; 32])));
+    assert!(invalid(BeamformingConfig { pulse_repetition_frequency: 0.0, ..config }, 64, 0, SpectralWindow::Hann));
+
+    let spectrogram = Spectrogram::new(config, 64, 32, SpectralWindow::Custom(vec![1.0; 64])).unwrap();
+    let result = spectrogram.process(&tone(&config, 0.1, 10));
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected: 64, actual: 10 })));
+
+    // A frame of the full grid rather than the gate
+    let volume = SampleVolume { x: 0.0, z: 20.0e-3, length: 1.0e-3 };
+    let frame = IqFrame::new(4, 2, vec![Complex::new(1.0, 0.0); 8]);
+    let expected = volume.positions(&config).len();
+    let result = volume.slow_time_sample(&config, &frame);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected: e, actual: 8 }) if e == expected));
+}
+
+#[test]
+fn gpu_matches_cpu_sample_volume() {
+    let config = pw_config();
+    let Some(gpu) = gpu_beamformer(config) else { return };
+    let volume = SampleVolume { x: 0.5e-3, z: 20.0e-3, length: 1.5e-3 };
+    let mut gpu = Beamformer::Gpu(Box::new(gpu));
+    let mut cpu = pollster::block_on(Beamformer::new(config, Backend::Cpu)).unwrap();
+    gpu.set_sample_volume(&volume).unwrap();
+    cpu.set_sample_volume(&volume).unwrap();
+
+    let iq = simulate::pulse_echoes_iq(&config, &[(0.3e-3, 20.2e-3)], 5.0e6, 0.6);
+    let actual = volume.slow_time_sample(&config, &gpu.process_iq(&iq).unwrap()).unwrap();
+    let expected = volume.slow_time_sample(&config, &cpu.process_iq(&iq).unwrap()).unwrap();
+    let scale = expected.norm();
+    assert!((actual.re - expected.re).abs() < 1e-3 * scale && (actual.im - expected.im).abs() < 1e-3 * scale);
+}
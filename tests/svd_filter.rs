@@ -0,0 +1,206 @@
+mod common;
+
+use std::f32::consts::PI;
+
+use common::{assert_frames_close, gpu_context, noise, test_config};
+use rust_gpu_app::{
+    input_format, simulate, BeamformError, BeamformingConfig, Complex, CpuBeamformer, CpuDoppler, CpuSvdFilter, Frame,
+    GpuBeamformer, GpuSvdFilter, IqFrame, SvdCutoff,
+};
+
+const SINGULAR_VALUES: [f32; 8] = [100.0, 50.0, 20.0, 10.0, 5.0, 3.0, 2.0, 1.0];
+
+/// 16 x 16 pixels, 8 frames per ensemble.
+fn ensemble_config() -> BeamformingConfig {
+    BeamformingConfig {
+        input_format: input_format::IQ_INTERLEAVED,
+        demodulation_frequency: 5.0e6,
+        grid_width: 16,
+        grid_depth: 16,
+        ensemble_length: 8,
+        pulse_repetition_frequency: 5.0e3,
+        center_frequency: 5.0e6,
+        ..test_config()
+    }
+}
+
+/// Ensemble whose component `k` has singular value `SINGULAR_VALUES[k]`,
+/// spread over the pixels `p % 8 == k` and oscillating at `k` cycles per
+/// ensemble: disjoint pixels and orthogonal frequencies.
+fn known_ensemble(config: &BeamformingConfig) -> Vec<IqFrame> {
+    let frames = config.ensemble_length as usize;
+    let pixels = (config.grid_width * config.grid_depth) as usize;
+    let scale = 1.0 / ((pixels / frames) as f32 * frames as f32).sqrt();
+    (0..frames)
+        .map(|frame| {
+            let data = (0..pixels)
+                .map(|pixel| {
+                    let k = pixel % frames;
+                    let phase = 2.0 * PI * (k * frame) as f32 / frames as f32;
+                    let amplitude = SINGULAR_VALUES[k] * scale;
+                    Complex::new(amplitude * phase.cos(), amplitude * phase.sin())
+                })
+                .collect();
+            IqFrame::new(16, 16, data)
+        })
+        .collect()
+}
+
+/// 0.2 mm pixels around (-2 mm, 20 mm) and (2 mm, 20 mm), in cols 6 and 26
+/// of row 8, with 16 frames per ensemble.
+fn moving_tissue_config() -> BeamformingConfig {
+    BeamformingConfig {
+        grid_origin_x: -3.2e-3,
+        grid_origin_z: 18.4e-3,
+        grid_spacing_x: 0.2e-3,
+        grid_spacing_z: 0.2e-3,
+        grid_width: 32,
+        ensemble_length: 16,
+        ..ensemble_config()
+    }
+}
+
+/// IQ acquisitions of flow at (2 mm, 20 mm) under tissue 40 dB brighter at
+/// (-2 mm, 20 mm) that drifts at 2 mm/s, with a little noise.
+fn moving_tissue_acquisitions(config: &BeamformingConfig) -> Vec<Vec<f32>> {
+    (0..config.ensemble_length)
+        .map(|frame| {
+            let time = frame as f32 / config.pulse_repetition_frequency;
+            let tissue = simulate::pulse_echoes_iq(config, &[(-2.0e-3, 20.0e-3 - 2.0e-3 * time)], 5.0e6, 0.6);
+            let flow = simulate::pulse_echoes_iq(config, &[(2.0e-3, 20.0e-3 - 0.05 * time)], 5.0e6, 0.6);
+            let jitter = noise(tissue.len(), frame + 1);
+            tissue.iter().zip(&flow).zip(&jitter).map(|((t, f), n)| 100.0 * t + f + 0.01 * n).collect()
+        })
+        .collect()
+}
+
+/// The beamformed ensemble of [`moving_tissue_acquisitions`].
+fn flow_under_moving_tissue() -> (BeamformingConfig, Vec<IqFrame>) {
+    let config = moving_tissue_config();
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let ensemble = moving_tissue_acquisitions(&config).iter().map(|iq| cpu.process_iq(iq).unwrap()).collect();
+    (config, ensemble)
+}
+
+fn magnitudes(frames: &[IqFrame]) -> Vec<Frame> {
+    frames.iter().map(|frame| frame.magnitude()).collect()
+}
+
+#[test]
+fn finds_singular_values_and_removes_components() {
+    let config = ensemble_config();
+    let ensemble = known_ensemble(&config);
+    let filter = CpuSvdFilter::new(config, SvdCutoff::Keep(2..6)).unwrap();
+    let filtered = filter.process(&ensemble).unwrap();
+
+    for (actual, expected) in filtered.singular_values.iter().zip(SINGULAR_VALUES) {
+        assert!((actual - expected).abs() < 1e-3 * expected, "{:?}", filtered.singular_values);
+    }
+    assert_eq!(filtered.kept, 2..6);
+    // Components 0, 1, 6 and 7 vanish from their pixels, the others stay
+    for (frame, original) in filtered.ensemble.iter().zip(&ensemble) {
+        for (pixel, (actual, expected)) in frame.data.iter().zip(&original.data).enumerate() {
+            let expected = if (2..6).contains(&(pixel % 8)) { *expected } else { Complex::default() };
+            assert!((actual.re - expected.re).abs() < 1e-3 && (actual.im - expected.im).abs() < 1e-3, "pixel {pixel}");
+        }
+    }
+}
+
+#[test]
+fn automatic_cutoff_rejects_moving_tissue() {
+    let (config, ensemble) = flow_under_moving_tissue();
+    let filtered = CpuSvdFilter::new(config, SvdCutoff::Automatic).unwrap().process(&ensemble).unwrap();
+    let (kept, singular_values) = (&filtered.kept, &filtered.singular_values);
+    assert!(kept.start >= 1 && kept.end == 16, "{kept:?} of {singular_values:?}");
+
+    // A mean-removing wall filter leaves the drifting tissue brighter than
+    // the flow; the SVD filter does not
+    let doppler = |clutter_filter_order| {
+        let config = BeamformingConfig { clutter_filter_order, ..config };
+        CpuDoppler::new(config).unwrap()
+    };
+    let power = doppler(1).process_power(&ensemble).unwrap();
+    assert!(power.get(6, 8) > power.get(26, 8), "tissue {} flow {}", power.get(6, 8), power.get(26, 8));
+    let power = doppler(0).process_power(&filtered.ensemble).unwrap();
+    let (col, row, _) = power.peak();
+    assert!(col.abs_diff(26) <= 1 && row.abs_diff(8) <= 1, "peak at ({col}, {row})");
+    // Log compressed over 60 dB: the tissue ends up over 10 dB below the flow
+    let (tissue, flow) = (power.get(6, 8), power.get(26, 8));
+    assert!((flow - tissue) * config.dynamic_range > 10.0, "tissue {tissue} flow {flow}");
+}
+
+#[test]
+fn keeping_everything_preserves_the_ensemble() {
+    let config = ensemble_config();
+    let ensemble = known_ensemble(&config);
+    let filtered = CpuSvdFilter::new(config, SvdCutoff::Keep(0..8)).unwrap().process(&ensemble).unwrap();
+    for (actual, expected) in magnitudes(&filtered.ensemble).iter().zip(magnitudes(&ensemble)) {
+        assert_frames_close(actual, &expected, 1e-5);
+    }
+}
+
+#[test]
+fn rejects_invalid_cutoffs_and_ensembles() {
+    let config = ensemble_config();
+    let invalid = |config: BeamformingConfig, cutoff: SvdCutoff| {
+        matches!(CpuSvdFilter::new(config, cutoff), Err(BeamformError::InvalidConfig(_)))
+    };
+    assert!(invalid(config, SvdCutoff::Keep(3..3)));
+    assert!(invalid(config, SvdCutoff::Keep(1..9)));
+    assert!(invalid(BeamformingConfig { ensemble_length: 1, ..config }, SvdCutoff::Automatic));
+    assert!(invalid(BeamformingConfig { grid_depth: 0, ..config }, SvdCutoff::Automatic));
+
+    let filter = CpuSvdFilter::new(config, SvdCutoff::Automatic).unwrap();
+    let ensemble = known_ensemble(&config);
+    let result = filter.process(&ensemble[..7]);
+    assert!(matches!(result, Err(BeamformError::InvalidInput { expected: 2048, actual: 1792 })));
+}
+
+#[test]
+fn gpu_matches_cpu_svd_filter() {
+    let (config, ensemble) = flow_under_moving_tissue();
+    let mut gpu = match pollster::block_on(GpuSvdFilter::new(config, SvdCutoff::Automatic)) {
+        Ok(gpu) => gpu,
+        Err(BeamformError::NoAdapter) => {
+            eprintln!("no GPU adapter available, skipping GPU comparison");
+            return;
+        }
+        Err(err) => panic!("failed to create GPU SVD filter: {err}"),
+    };
+    let actual = gpu.process(&ensemble).unwrap();
+    let expected = CpuSvdFilter::new(config, SvdCutoff::Automatic).unwrap().process(&ensemble).unwrap();
+    assert_eq!(actual.kept, expected.kept);
+    for (a, e) in actual.singular_values.iter().zip(&expected.singular_values) {
+        assert!((a - e).abs() < 1e-3 * expected.singular_values[0], "{a} vs {e}");
+    }
+    for (actual, expected) in magnitudes(&actual.ensemble).iter().zip(magnitudes(&expected.ensemble)) {
+        assert_frames_close(actual, &expected, 1e-3);
+    }
+}
+
+#[test]
+fn gpu_gathers_beamformed_ensemble_on_device() {
+    let Some(context) = gpu_context() else { return };
+    let config = moving_tissue_config();
+    let mut beamformer = GpuBeamformer::with_context(&context, config).unwrap();
+    let mut gpu = GpuSvdFilter::with_context(&context, config, SvdCutoff::Automatic).unwrap();
+    let acquisitions = moving_tissue_acquisitions(&config);
+
+    // Each frame is beamformed and copied into the ensemble in its own submission
+    for (index, iq) in acquisitions.iter().enumerate() {
+        beamformer.upload(iq).unwrap();
+        let mut encoder = context.encoder();
+        beamformer.encode_channels(&mut encoder).unwrap();
+        beamformer.encode_beamform(&mut encoder).unwrap();
+        gpu.encode_frame(&mut encoder, index, beamformer.output_buffer()).unwrap();
+        context.queue().submit(Some(encoder.finish()));
+    }
+    let actual = gpu.process_encoded(context.encoder()).unwrap();
+
+    let ensemble: Vec<IqFrame> = acquisitions.iter().map(|iq| beamformer.process_iq(iq).unwrap()).collect();
+    let expected = CpuSvdFilter::new(config, SvdCutoff::Automatic).unwrap().process(&ensemble).unwrap();
+    assert_eq!(actual.kept, expected.kept);
+    for (actual, expected) in magnitudes(&actual.ensemble).iter().zip(magnitudes(&expected.ensemble)) {
+        assert_frames_close(actual, &expected, 1e-3);
+    }
+}
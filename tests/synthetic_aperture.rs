@@ -0,0 +1,93 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{
+    interpolation, simulate, transmit_model, transmits, BeamformingConfig, CpuBeamformer, Frame, TransmitEvent,
+};
+
+/// A 32-element probe, so the `[tx][rx][sample]` cube stays small.
+fn sta_config() -> BeamformingConfig {
+    BeamformingConfig { num_channels: 32, interpolation: interpolation::SINC, ..test_config() }
+}
+
+/// Width (m) of the region around the brightest column where the lateral
+/// profile stays above half its peak.
+fn lateral_width(frame: &Frame, config: &BeamformingConfig) -> f32 {
+    let profile: Vec<f32> = (0..frame.width)
+        .map(|col| (0..frame.depth).fold(0.0f32, |m, row| m.max(frame.get(col, row).abs())))
+        .collect();
+    let (peak_col, peak) = profile
+        .iter()
+        .enumerate()
+        .fold((0, 0.0f32), |best, (col, &v)| if v > best.1 { (col, v) } else { best });
+    let left = (0..peak_col).rev().find(|&col| profile[col] < 0.5 * peak).map_or(0, |col| col + 1);
+    let right = (peak_col..profile.len()).find(|&col| profile[col] < 0.5 * peak).unwrap_or(profile.len());
+    (right - left) as f32 * config.grid_spacing_x
+}
+
+#[test]
+fn synthetic_aperture_fires_every_element() {
+    let config = sta_config();
+
+    let events = transmits::synthetic_aperture(&config);
+
+    assert_eq!(events.len(), config.num_channels as usize);
+    for (element, event) in events.iter().enumerate() {
+        assert_eq!(event.model, transmit_model::ELEMENT);
+        assert_eq!(event.source_x, shader::element_position(&config, element));
+        assert_eq!((event.source_x, event.source_z), (event.origin_x, event.origin_z));
+    }
+}
+
+#[test]
+fn two_way_focusing_sums_every_transmit_receive_pair() {
+    let config = sta_config();
+    let events = transmits::synthetic_aperture(&config);
+    let rf = simulate::transmit_echoes(&config, &events, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_transmits(&events).unwrap();
+    let (col, row, value) = cpu.process(&rf).unwrap().peak();
+
+    let expected = (config.num_channels * config.num_channels) as f32;
+    assert_eq!((col, row), (24, 20));
+    assert!((value - expected).abs() < 0.01 * expected, "peak {value} vs {expected}");
+}
+
+#[test]
+fn two_way_focusing_narrows_main_lobe() {
+    let config = BeamformingConfig {
+        grid_origin_x: -5.0e-3,
+        grid_origin_z: 19.0e-3,
+        grid_spacing_x: 0.05e-3,
+        grid_spacing_z: 0.05e-3,
+        grid_width: 201,
+        grid_depth: 41,
+        ..sta_config()
+    };
+    let width = |events: &[TransmitEvent]| {
+        let rf = simulate::transmit_echoes(&config, events, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        cpu.set_transmits(events).unwrap();
+        lateral_width(&cpu.process(&rf).unwrap(), &config)
+    };
+
+    let plane_wave = width(&[TransmitEvent::plane_wave(0.0)]);
+    let sta = width(&transmits::synthetic_aperture(&config));
+
+    assert!(sta < 0.85 * plane_wave, "STA {sta} m vs plane wave {plane_wave} m");
+}
+
+#[test]
+fn gpu_matches_cpu_in_synthetic_aperture_mode() {
+    let config = sta_config();
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let events = transmits::synthetic_aperture(&config);
+    let rf = noise(events.len() * (config.num_channels * config.num_samples) as usize, 31);
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_transmits(&events).unwrap();
+    gpu.set_transmits(&events).unwrap();
+
+    assert_frames_close(&gpu.process(&rf).unwrap(), &cpu.process(&rf).unwrap(), 1e-5);
+}
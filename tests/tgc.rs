@@ -0,0 +1,124 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, test_config};
+use rust_gpu_app::{filters, input_format, simulate, tgc, BeamformError, BeamformingConfig, CpuBeamformer, Tgc};
+
+/// One target near the top of the grid (row 4) and one near the bottom (row 28).
+const TARGETS: [(f32, f32); 2] = [(0.0, 16.0e-3), (0.0, 22.0e-3)];
+
+/// 0.5 dB/cm/MHz at the 5 MHz centre frequency of the echoes.
+const ATTENUATION: Tgc = Tgc::Attenuation { coefficient: 0.5, frequency: 5.0e6 };
+
+/// `rf` with each sample weakened by the round-trip attenuation of
+/// `ATTENUATION` at the depth it comes from.
+fn attenuate(config: &BeamformingConfig, rf: &[f32]) -> Vec<f32> {
+    let samples = config.num_samples as usize;
+    rf.iter()
+        .enumerate()
+        .map(|(i, &value)| {
+            let time = config.start_time as f64 + (i % samples) as f64 / config.sampling_frequency as f64;
+            let depth_cm = 0.5 * config.speed_of_sound as f64 * time * 100.0;
+            let loss_db = 2.0 * 0.5 * 5.0 * depth_cm;
+            value * 10f64.powf(-loss_db / 20.0) as f32
+        })
+        .collect()
+}
+
+#[test]
+fn attenuation_restores_deep_echoes() {
+    let config = test_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let expected = cpu.process(&rf).unwrap();
+
+    // 11 dB lost at 22 mm
+    let attenuated = attenuate(&config, &rf);
+    let uncompensated = cpu.process(&attenuated).unwrap();
+    assert!(uncompensated.get(24, 28).abs() < 0.3 * expected.get(24, 28).abs());
+
+    cpu.set_tgc(ATTENUATION).unwrap();
+    assert_eq!(cpu.config().tgc, tgc::ATTENUATION);
+    assert_frames_close(&cpu.process(&attenuated).unwrap(), &expected, 1e-3);
+}
+
+#[test]
+fn linear_curve_matches_attenuation() {
+    let config = test_config();
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_tgc(ATTENUATION).unwrap();
+    let expected = cpu.process(&rf).unwrap();
+
+    // 5 dB per cm of depth, up to 10 cm
+    cpu.set_tgc(Tgc::Curve(vec![(0.0, 0.0), (0.1, 50.0)])).unwrap();
+    assert_eq!((cpu.config().tgc, cpu.config().num_tgc_points), (tgc::CURVE, 2));
+    assert_frames_close(&cpu.process(&rf).unwrap(), &expected, 1e-3);
+}
+
+#[test]
+fn constant_curve_scales_demodulated_frames() {
+    let config = BeamformingConfig { demodulation_frequency: 5.0e6, ..test_config() };
+    let rf = simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6);
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_demodulation(4, &filters::low_pass(&config, 4.0e6, 63)).unwrap();
+    let expected = cpu.process_iq(&rf).unwrap();
+
+    // A single point holds its gain at every depth
+    cpu.set_tgc(Tgc::Curve(vec![(20.0e-3, 6.0)])).unwrap();
+    let gain = 10f32.powf(6.0 / 20.0);
+    let scale = expected.data.iter().fold(0.0f32, |m, c| m.max(c.norm()));
+    for (a, e) in cpu.process_iq(&rf).unwrap().data.iter().zip(&expected.data) {
+        assert!((a.re - gain * e.re).hypot(a.im - gain * e.im) <= 1e-4 * scale, "{a:?} vs {e:?}");
+    }
+
+    cpu.set_tgc(Tgc::Off).unwrap();
+    assert_eq!(cpu.config().num_tgc_points, 0);
+    assert_eq!(cpu.process_iq(&rf).unwrap(), expected);
+}
+
+#[test]
+fn rejects_invalid_tgc() {
+    let config = test_config();
+    let invalid = |tgc: Tgc| {
+        let result = CpuBeamformer::new(config).unwrap().set_tgc(tgc);
+        matches!(result, Err(BeamformError::InvalidConfig(_)))
+    };
+
+    assert!(invalid(Tgc::Attenuation { coefficient: -0.5, frequency: 5.0e6 }));
+    assert!(invalid(Tgc::Attenuation { coefficient: 0.5, frequency: 0.0 }));
+    assert!(invalid(Tgc::Curve(Vec::new())));
+    assert!(invalid(Tgc::Curve(vec![(0.02, 10.0), (0.01, 0.0)])));
+    assert!(invalid(Tgc::Curve(vec![(0.01, f32::NAN)])));
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    let result = cpu.set_config(BeamformingConfig { tgc: tgc::CURVE, num_tgc_points: 2, ..config });
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+    let result = cpu.set_config(BeamformingConfig { tgc: 3, ..config });
+    assert!(matches!(result, Err(BeamformError::InvalidConfig(_))));
+}
+
+#[test]
+fn gpu_matches_cpu_with_tgc() {
+    let curve = Tgc::Curve(vec![(16.0e-3, 0.0), (18.0e-3, 12.0), (22.0e-3, 15.0)]);
+    for format in [input_format::RF, input_format::IQ_PLANAR] {
+        let config = BeamformingConfig { input_format: format, demodulation_frequency: 5.0e6, ..test_config() };
+        let input = if format == input_format::RF {
+            simulate::pulse_echoes(&config, &TARGETS, 5.0e6, 0.6)
+        } else {
+            simulate::pulse_echoes_iq(&config, &TARGETS, 5.0e6, 0.6)
+        };
+        let Some(mut gpu) = gpu_beamformer(config) else { return };
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        for tgc in [ATTENUATION, curve.clone()] {
+            gpu.set_tgc(tgc.clone()).unwrap();
+            cpu.set_tgc(tgc).unwrap();
+            assert_frames_close(&gpu.process_bmode(&input).unwrap(), &cpu.process_bmode(&input).unwrap(), 1e-3);
+        }
+        if format == input_format::RF {
+            // TGC then follows demodulation, at the decimated sample times
+            gpu.set_demodulation(4, &filters::low_pass(&config, 4.0e6, 63)).unwrap();
+            cpu.set_demodulation(4, &filters::low_pass(&config, 4.0e6, 63)).unwrap();
+            assert_frames_close(&gpu.process_bmode(&input).unwrap(), &cpu.process_bmode(&input).unwrap(), 1e-3);
+        }
+    }
+}
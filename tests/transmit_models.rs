@@ -0,0 +1,99 @@
+mod common;
+
+use common::{assert_frames_close, gpu_beamformer, noise, test_config};
+use rust_gpu_app::{
+    interpolation, simulate, BeamformError, BeamformingConfig, CpuBeamformer, TransmitEvent,
+};
+
+/// Asserts that `shader::transmit_delay` matches the travel distance `path`
+/// (m) at each of `points`.
+fn assert_delays(transmit: &TransmitEvent, points: &[(f32, f32)], path: impl Fn(f32, f32) -> f32) {
+    let config = test_config();
+    for &(x, z) in points {
+        let delay = shader::transmit_delay(&config, transmit, x, z);
+        let expected = path(x, z) / config.speed_of_sound;
+        assert!((delay - expected).abs() < 1e-9, "{transmit:?} at ({x}, {z}): {delay} vs {expected}");
+    }
+}
+
+const POINTS: [(f32, f32); 4] = [(0.0, 10.0e-3), (-4.0e-3, 18.0e-3), (3.0e-3, 20.0e-3), (5.0e-3, 32.0e-3)];
+
+#[test]
+fn plane_wave_delay_follows_steering() {
+    let angle = 0.2f32;
+    assert_delays(&TransmitEvent::plane_wave(angle), &POINTS, |x, z| x * angle.sin() + z * angle.cos());
+}
+
+#[test]
+fn diverging_delay_spreads_from_virtual_source() {
+    let (sx, sz) = (2.0e-3, -10.0e-3);
+    // Time 0 is when the spherical front crosses the array centre
+    assert_delays(&TransmitEvent::diverging(sx, sz), &POINTS, |x, z| {
+        (x - sx).hypot(z - sz) - sx.hypot(sz)
+    });
+}
+
+#[test]
+fn focused_delay_converges_then_spreads() {
+    let (fx, fz) = (0.0, 20.0e-3);
+    assert_delays(&TransmitEvent::focused(fx, fz), &POINTS, |x, z| {
+        let from_focus = (x - fx).hypot(z - fz);
+        if z < fz {
+            fz - from_focus
+        } else {
+            fz + from_focus
+        }
+    });
+    // On the axis the wave travels straight down on both sides of the focus
+    assert_delays(&TransmitEvent::focused(fx, fz), &[(0.0, 8.0e-3), (0.0, 31.0e-3)], |_, z| z);
+}
+
+#[test]
+fn every_model_focuses_point_target() {
+    let config = BeamformingConfig { interpolation: interpolation::SINC, ..test_config() };
+    let stacks = [
+        vec![TransmitEvent::plane_wave(-0.1), TransmitEvent::plane_wave(0.1)],
+        vec![TransmitEvent::diverging(-3.0e-3, -8.0e-3), TransmitEvent::diverging(3.0e-3, -8.0e-3)],
+        vec![TransmitEvent::focused(-2.0e-3, 25.0e-3), TransmitEvent::focused(2.0e-3, 25.0e-3)],
+    ];
+
+    for transmits in stacks {
+        let rf = simulate::transmit_echoes(&config, &transmits, &[(0.0, 20.0e-3)], 5.0e6, 0.6);
+        let mut cpu = CpuBeamformer::new(config).unwrap();
+        cpu.set_transmits(&transmits).unwrap();
+
+        let (col, row, value) = cpu.process(&rf).unwrap().peak();
+        let expected = (transmits.len() * config.num_channels as usize) as f32;
+        assert_eq!((col, row), (24, 20), "{transmits:?}");
+        assert!((value - expected).abs() < 0.01 * expected, "{transmits:?}: peak {value} vs {expected}");
+    }
+}
+
+#[test]
+fn misplaced_virtual_sources_are_rejected() {
+    let mut cpu = CpuBeamformer::new(test_config()).unwrap();
+    let unknown = TransmitEvent { model: u32::MAX, ..Default::default() };
+
+    for transmit in [TransmitEvent::diverging(0.0, 5.0e-3), TransmitEvent::focused(0.0, -5.0e-3), unknown] {
+        let err = cpu.set_transmits(&[transmit]).unwrap_err();
+        assert!(matches!(err, BeamformError::InvalidConfig(_)), "{transmit:?}");
+    }
+}
+
+#[test]
+fn gpu_matches_cpu_for_every_model() {
+    let config = BeamformingConfig { interpolation: interpolation::CUBIC, ..test_config() };
+    let Some(mut gpu) = gpu_beamformer(config) else { return };
+    let transmits = [
+        TransmitEvent::plane_wave(0.15),
+        TransmitEvent::diverging(1.0e-3, -12.0e-3),
+        TransmitEvent::focused(-1.5e-3, 18.0e-3),
+    ];
+    let rf = noise(transmits.len() * (config.num_channels * config.num_samples) as usize, 29);
+
+    let mut cpu = CpuBeamformer::new(config).unwrap();
+    cpu.set_transmits(&transmits).unwrap();
+    gpu.set_transmits(&transmits).unwrap();
+
+    assert_frames_close(&gpu.process(&rf).unwrap(), &cpu.process(&rf).unwrap(), 1e-5);
+}